@@ -0,0 +1,110 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(
+    name = "near-indexer",
+    about = "Indexes NEAR staking pool rewards into MongoDB"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Compute and log results without writing to MongoDB (same as DRY_RUN=true)
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Write the processed epochs to epochs.csv and epochs.json in this directory (same as EXPORT_DIR)
+    #[arg(long, global = true, value_name = "DIR")]
+    pub export: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the indexer every 12 hours (default)
+    Run,
+    /// Process the stored epochs overlapping a block or date range
+    Backfill {
+        #[arg(
+            long,
+            required_unless_present = "from_date",
+            conflicts_with = "from_date"
+        )]
+        from_block: Option<u64>,
+        #[arg(long, required_unless_present = "to_date", conflicts_with = "to_date")]
+        to_block: Option<u64>,
+        /// Start at the first block at or after this date (YYYY-MM-DD or RFC 3339, UTC)
+        #[arg(long, value_parser = parse_date)]
+        from_date: Option<DateTime<Utc>>,
+        /// End at the first block at or after this date (YYYY-MM-DD or RFC 3339, UTC)
+        #[arg(long, value_parser = parse_date)]
+        to_date: Option<DateTime<Utc>>,
+    },
+    /// Reprocess a single stored epoch
+    ReprocessEpoch {
+        #[arg(long)]
+        epoch_id: String,
+    },
+    /// Recompute one delegator's records across all stored epochs from their own balances
+    ReprocessDelegator {
+        #[arg(long)]
+        delegator_id: String,
+    },
+    /// Fetch and store new staking transactions without processing epochs
+    FetchTxnsOnly,
+    /// Print a delegator's latest stake and rewards as JSON
+    QueryDelegator {
+        #[arg(long)]
+        delegator_id: String,
+        /// Sum the delegator's position across every indexed pool instead of only VALIDATOR_ACCOUNT_ID
+        #[arg(long)]
+        aggregate_pools: bool,
+        /// Print every stored epoch record for VALIDATOR_ACCOUNT_ID instead of the latest position
+        #[arg(long, conflicts_with = "aggregate_pools")]
+        history: bool,
+    },
+    /// Print VALIDATOR_ACCOUNT_ID's stored metrics for an epoch range as JSON
+    QueryValidator {
+        #[arg(long, default_value_t = 0)]
+        from_epoch: u64,
+        #[arg(long, default_value_t = u64::MAX)]
+        to_epoch: u64,
+    },
+    /// Recompute stored delegator and validator APY from the stored rewards and stakes
+    RecomputeApy,
+    /// Report stored delegator records with zero rewards, implausible APY or unexplained stake drops
+    Validate {
+        /// APY in percent above which a record is reported
+        #[arg(long, default_value_t = 100.0)]
+        max_apy: f64,
+    },
+    /// Search again for epoch boundaries that were stored as estimates and correct them
+    VerifyBoundaries {
+        /// Blocks to search after each epoch's start; defaults to twice EPOCH_BLOCKS
+        #[arg(long)]
+        window_blocks: Option<u64>,
+    },
+    /// Print how many epochs have started since the latest stored one, without processing them
+    CheckNewEpochs {
+        /// Pending epochs tolerated before exiting with --exit-code
+        #[arg(long, default_value_t = 0)]
+        max_pending: u64,
+        /// Exit code when more than --max-pending epochs are pending
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(i32).range(1..))]
+        exit_code: i32,
+    },
+    /// Continuously store new staking transactions as blocks are produced
+    Stream {
+        /// Block to start from; defaults to after the last block streamed
+        #[arg(long)]
+        from_block: Option<u64>,
+    },
+}
+
+/// Parses `YYYY-MM-DD` as midnight UTC, or a full RFC 3339 timestamp.
+fn parse_date(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|datetime| datetime.with_timezone(&Utc))
+        .map_err(|e| format!("Invalid date {}: {}", value, e))
+}
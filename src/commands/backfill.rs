@@ -0,0 +1,108 @@
+use crate::config::Config;
+use crate::repositories::transaction_repository;
+use crate::services::{epoch_processor, near_rpc};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use std::error::Error;
+use std::sync::Arc;
+
+/// Backfills between `from_block` and `to_block`. A date given instead of a block is resolved
+/// to the first block at or after it.
+pub async fn run(
+    config: Arc<Config>,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    from_date: Option<DateTime<Utc>>,
+    to_date: Option<DateTime<Utc>>,
+) -> Result<(), Box<dyn Error>> {
+    let (clients, db) = super::connect(&config).await?;
+
+    let from_block = match (from_block, from_date) {
+        (Some(from_block), _) => from_block,
+        (None, Some(from_date)) => {
+            near_rpc::height_at_timestamp(&clients.0, &clients.1, from_date).await?
+        }
+        (None, None) => return Err("--from-block or --from-date is required".into()),
+    };
+    let to_block = match (to_block, to_date) {
+        (Some(to_block), _) => to_block,
+        (None, Some(to_date)) => {
+            near_rpc::height_at_timestamp(&clients.0, &clients.1, to_date).await?
+        }
+        (None, None) => return Err("--to-block or --to-date is required".into()),
+    };
+    if from_block > to_block {
+        return Err("the start of the range must not be after its end".into());
+    }
+
+    info!("Getting epoch data...");
+    let epoch_data = crate::get_or_sync_epoch_data(
+        &db,
+        from_block,
+        &clients.0,
+        &clients.1,
+        config.batch_size,
+        config.epoch_blocks,
+        config.rpc_inter_request_delay_ms,
+        config.parallel_epoch_sync.then_some(config.parallel_limit),
+        config.dry_run,
+    )
+    .await?;
+
+    if let Some(first_epoch) = epoch_data.first() {
+        if first_epoch.start_block > from_block {
+            warn!(
+                "No stored epochs before block {}, backfill starts from there",
+                first_epoch.start_block
+            );
+        }
+    }
+
+    let indices: Vec<usize> = epoch_data
+        .iter()
+        .enumerate()
+        .filter(|(index, epoch)| {
+            let end_block = epoch_data
+                .get(index + 1)
+                .map(|next| next.start_block - 1)
+                .unwrap_or(u64::MAX);
+            epoch.start_block <= to_block && end_block >= from_block
+        })
+        .map(|(index, _)| index)
+        .collect();
+
+    info!(
+        "Backfilling {} epochs between blocks {} and {}",
+        indices.len(),
+        from_block,
+        to_block
+    );
+
+    let epoch_data = Arc::new(epoch_data);
+    let mut failed_epochs = Vec::new();
+    for validator_account_id in &config.validator_account_ids {
+        info!("Backfilling {}", validator_account_id);
+        let pool_config = Arc::new(config.for_validator(validator_account_id));
+        let transactions =
+            transaction_repository::get_all_transactions(&db, validator_account_id).await?;
+        let failed = epoch_processor::process_epochs(
+            Arc::clone(&pool_config),
+            db.clone(),
+            Arc::clone(&clients),
+            Arc::clone(&epoch_data),
+            indices.clone(),
+            Arc::new(transactions),
+        )
+        .await;
+        if let Some(&first) = indices.first().filter(|_| !config.dry_run) {
+            epoch_processor::recompute_cumulative_rewards(&db, &pool_config, first as u64 + 1)
+                .await?;
+        }
+        failed_epochs.extend(super::failed_for_validator(failed, validator_account_id));
+        super::export_epochs(&db, &pool_config, &epoch_data, &indices).await?;
+    }
+
+    super::check_failed_epochs(failed_epochs)?;
+    info!("Backfill complete. Data has been saved to MongoDB.");
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use crate::config::Config;
+use crate::repositories::epoch_sync_repository;
+use crate::services::{database, near_rpc, telemetry};
+use log::warn;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Prints how many epochs have started on chain since the latest stored epoch, estimated from
+/// the tip height and EPOCH_BLOCKS, without syncing or processing them. Exits with `exit_code`
+/// when more than `max_pending` epochs are pending.
+pub async fn run(
+    config: Arc<Config>,
+    max_pending: u64,
+    exit_code: i32,
+) -> Result<(), Box<dyn Error>> {
+    let db = database::for_reports(&database::connect_to_database().await?);
+    let latest = epoch_sync_repository::get_latest_epoch_sync(&db)
+        .await?
+        .ok_or("No epochs have been synced yet")?;
+
+    let (primary_client, secondary_client) = near_rpc::create_near_connections(
+        &config.primary_rpc,
+        &config.secondary_rpc,
+        config.http_client.clone(),
+    )
+    .await;
+    let latest_block_height =
+        near_rpc::get_latest_block_height(&primary_client, &secondary_client).await?;
+    let pending_epochs =
+        latest_block_height.saturating_sub(latest.start_block) / config.epoch_blocks;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&serde_json::json!({
+            "latestEpochId": latest.epoch_id,
+            "latestEpochStartBlock": latest.start_block,
+            "latestBlockHeight": latest_block_height,
+            "pendingEpochs": pending_epochs,
+        }))?
+    );
+
+    if pending_epochs > max_pending {
+        warn!(
+            "Indexer is behind by {} epochs (more than {})",
+            pending_epochs, max_pending
+        );
+        telemetry::shutdown();
+        std::process::exit(exit_code);
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use crate::config::Config;
+use crate::services::metrics;
+use crate::transaction_fetcher::fetch_and_process_transactions;
+use log::info;
+use std::error::Error;
+use std::sync::Arc;
+
+pub async fn run(config: Arc<Config>) -> Result<(), Box<dyn Error>> {
+    let (clients, db) = super::connect(&config).await?;
+
+    for validator_account_id in &config.validator_account_ids {
+        info!(
+            "Fetching and processing transactions for {}...",
+            validator_account_id
+        );
+        let pool_config = config.for_validator(validator_account_id);
+        let stored =
+            fetch_and_process_transactions(&pool_config, &db, &clients.0, &clients.1).await?;
+        metrics::record_transactions_processed(stored.transactions.len());
+
+        info!(
+            "Transaction fetch complete for {}. {} new transactions processed, {} saved.",
+            validator_account_id,
+            stored.transactions.len(),
+            stored.saved
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+pub mod backfill;
+pub mod check_new_epochs;
+pub mod fetch_txns;
+pub mod query_delegator;
+pub mod query_validator;
+pub mod recompute_apy;
+pub mod reprocess_delegator;
+pub mod reprocess_epoch;
+pub mod stream;
+pub mod validate;
+pub mod verify_boundaries;
+
+use crate::config::Config;
+use crate::models::EpochInfo;
+use crate::repositories::{delegator_repository, indexes};
+use crate::services::near_rpc::RpcClient;
+use crate::services::{database, near_rpc};
+use crate::utils::export;
+use log::{info, warn};
+use mongodb::Database;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+type Clients = Arc<(RpcClient, RpcClient)>;
+
+async fn connect(config: &Config) -> Result<(Clients, Database), Box<dyn Error>> {
+    info!("Connecting to NEAR network...");
+    let (primary_client, secondary_client) = near_rpc::create_near_connections(
+        &config.primary_rpc,
+        &config.secondary_rpc,
+        config.http_client.clone(),
+    )
+    .await;
+    let clients = Arc::new((primary_client, secondary_client));
+    info!("Connected to NEAR network");
+
+    let db = database::connect_to_database().await?;
+    if !config.dry_run {
+        indexes::ensure_indexes(&db).await?;
+    }
+    Ok((clients, db))
+}
+
+/// Tags failed epoch ids with the pool they failed for.
+pub fn failed_for_validator(
+    failed_epochs: Vec<String>,
+    validator_account_id: &str,
+) -> impl Iterator<Item = String> + '_ {
+    failed_epochs
+        .into_iter()
+        .map(move |epoch_id| format!("{} ({})", epoch_id, validator_account_id))
+}
+
+pub fn check_failed_epochs(failed_epochs: Vec<String>) -> Result<(), Box<dyn Error>> {
+    if failed_epochs.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Failed to process {} epochs: {}",
+            failed_epochs.len(),
+            failed_epochs.join(", ")
+        )
+        .into())
+    }
+}
+
+/// Writes the pool's stored delegator records of the processed epochs to a subdirectory of
+/// EXPORT_DIR named after the pool, if set.
+pub async fn export_epochs(
+    db: &Database,
+    config: &Config,
+    epoch_data: &[EpochInfo],
+    indices: &[usize],
+) -> Result<(), Box<dyn Error>> {
+    let numbered_epochs: Vec<(u64, EpochInfo)> = indices
+        .iter()
+        .map(|&index| (index as u64 + 1, epoch_data[index].clone()))
+        .collect();
+    export_numbered_epochs(db, config, &numbered_epochs).await
+}
+
+/// Same as `export_epochs` for `(epoch number, epoch)` pairs.
+pub async fn export_numbered_epochs(
+    db: &Database,
+    config: &Config,
+    numbered_epochs: &[(u64, EpochInfo)],
+) -> Result<(), Box<dyn Error>> {
+    let Some(dir) = &config.export_dir else {
+        return Ok(());
+    };
+    if config.dry_run {
+        warn!(
+            "Dry run, no delegator records were saved, skipping export to {}",
+            dir
+        );
+        return Ok(());
+    }
+
+    let mut epochs = Vec::with_capacity(numbered_epochs.len());
+    let mut delegators = Vec::new();
+    for (epoch_number, epoch) in numbered_epochs {
+        epochs.push(epoch.clone());
+        delegators.extend(
+            delegator_repository::get_epoch_delegators(
+                db,
+                &config.validator_account_id,
+                *epoch_number,
+            )
+            .await?,
+        );
+    }
+
+    let dir = Path::new(dir).join(&config.validator_account_id);
+    std::fs::create_dir_all(&dir)?;
+    export::export_epoch_csv(&epochs, &delegators, &dir.join("epochs.csv"))?;
+    export::export_epoch_json(&epochs, &delegators, &dir.join("epochs.json"))?;
+    info!(
+        "Exported {} delegator records for {} epochs to {}",
+        delegators.len(),
+        epochs.len(),
+        dir.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use crate::config::Config;
+use crate::models::DelegatorPosition;
+use crate::repositories::delegator_repository;
+use crate::services::database;
+use std::error::Error;
+use std::sync::Arc;
+
+pub async fn run(
+    config: Arc<Config>,
+    delegator_id: &str,
+    aggregate_pools: bool,
+    history: bool,
+) -> Result<(), Box<dyn Error>> {
+    let db = database::for_reports(&database::connect_to_database().await?);
+
+    if history {
+        let records = delegator_repository::get_delegator_history(
+            &db,
+            delegator_id,
+            &config.validator_account_id,
+        )
+        .await?;
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    let validator_account_id = if aggregate_pools {
+        None
+    } else {
+        Some(config.validator_account_id.as_str())
+    };
+    let records =
+        delegator_repository::get_latest_records_by_pool(&db, delegator_id, validator_account_id)
+            .await?;
+    if records.is_empty() {
+        return Err(format!("No records found for delegator {}", delegator_id).into());
+    }
+
+    let position = DelegatorPosition::aggregate(delegator_id, &records);
+    println!("{}", serde_json::to_string_pretty(&position)?);
+    Ok(())
+}
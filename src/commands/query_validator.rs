@@ -0,0 +1,23 @@
+use crate::config::Config;
+use crate::repositories::validator_repository;
+use crate::services::database;
+use std::error::Error;
+use std::sync::Arc;
+
+pub async fn run(
+    config: Arc<Config>,
+    from_epoch: u64,
+    to_epoch: u64,
+) -> Result<(), Box<dyn Error>> {
+    let db = database::for_reports(&database::connect_to_database().await?);
+
+    let metrics = validator_repository::get_validator_metrics(
+        &db,
+        &config.validator_account_id,
+        from_epoch,
+        to_epoch.min(i64::MAX as u64),
+    )
+    .await?;
+    println!("{}", serde_json::to_string_pretty(&metrics)?);
+    Ok(())
+}
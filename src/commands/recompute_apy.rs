@@ -0,0 +1,104 @@
+use crate::config::Config;
+use crate::repositories::{delegator_repository, validator_repository};
+use crate::services::{database, epoch_processor};
+use log::info;
+use num_bigint::BigInt;
+use num_traits::Zero;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Rewrites the stored delegator and validator APY from the stored rewards and stakes, without
+/// querying RPC. Provisional epochs are skipped because their elapsed fraction isn't stored.
+pub async fn run(config: Arc<Config>) -> Result<(), Box<dyn Error>> {
+    let db = database::connect_to_database().await?;
+
+    for validator_account_id in &config.validator_account_ids {
+        let metrics = validator_repository::get_validator_metrics(
+            &db,
+            validator_account_id,
+            0,
+            i64::MAX as u64,
+        )
+        .await?;
+        info!(
+            "Recomputing APY for {} epochs of {}",
+            metrics.len(),
+            validator_account_id
+        );
+
+        for epoch_metrics in metrics {
+            if epoch_metrics.apy_provisional {
+                info!(
+                    "Skipping provisional epoch {} of {}",
+                    epoch_metrics.epoch, validator_account_id
+                );
+                continue;
+            }
+
+            let mut delegators = delegator_repository::get_epoch_delegators(
+                &db,
+                validator_account_id,
+                epoch_metrics.epoch,
+            )
+            .await?;
+
+            let mut total_stake = BigInt::zero();
+            let mut total_rewards = BigInt::zero();
+            for delegator in delegators.iter_mut() {
+                delegator.apy = Some(epoch_processor::calculate_apy(
+                    &delegator.rewards,
+                    &delegator.auto_compounded_stake,
+                    config.epochs_per_year,
+                ));
+                total_stake += BigInt::from_str(&delegator.auto_compounded_stake)
+                    .unwrap_or_else(|_| BigInt::zero());
+                total_rewards +=
+                    BigInt::from_str(&delegator.rewards).unwrap_or_else(|_| BigInt::zero());
+            }
+
+            // Epochs the validator was kicked out of keep reporting no APY
+            let validator_apy = if epoch_metrics.kicked_out {
+                None
+            } else {
+                Some(epoch_processor::calculate_apy(
+                    &total_rewards.to_string(),
+                    &total_stake.to_string(),
+                    config.epochs_per_year,
+                ))
+            };
+
+            if config.dry_run {
+                info!(
+                    "Dry run: epoch {} of {} - {} delegators, validator APY {:?}% (stored {:?}%)",
+                    epoch_metrics.epoch,
+                    validator_account_id,
+                    delegators.len(),
+                    validator_apy,
+                    epoch_metrics.apy
+                );
+                continue;
+            }
+
+            delegator_repository::save_delegator_data(
+                &db,
+                &delegators,
+                config.delegator_batch_size,
+                None,
+            )
+            .await?;
+            validator_repository::update_validator_apy(
+                &db,
+                validator_account_id,
+                epoch_metrics.epoch,
+                validator_apy,
+            )
+            .await?;
+            info!(
+                "Recomputed APY for epoch {} of {}: {:?}%",
+                epoch_metrics.epoch, validator_account_id, validator_apy
+            );
+        }
+    }
+    Ok(())
+}
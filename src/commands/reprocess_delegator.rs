@@ -0,0 +1,98 @@
+use crate::config::Config;
+use crate::repositories::{delegator_repository, epoch_sync_repository, transaction_repository};
+use crate::services::{epoch_processor, near_rpc};
+use log::info;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Recomputes one delegator's records for every stored epoch from their first transaction on,
+/// reading only that account's balances. Other delegators' rows, the epoch data and the
+/// validator metrics are left untouched.
+pub async fn run(config: Arc<Config>, delegator_id: &str) -> Result<(), Box<dyn Error>> {
+    let (clients, db) = super::connect(&config).await?;
+    let epochs = epoch_sync_repository::get_all_epochs(&db).await?;
+    let head_block = near_rpc::get_settled_block_height(&clients.0, &clients.1).await?;
+
+    for validator_account_id in &config.validator_account_ids {
+        let pool_config = config.for_validator(validator_account_id);
+        let transactions =
+            transaction_repository::get_all_transactions(&db, validator_account_id).await?;
+        // Before their first transaction the delegator had no stake in the pool
+        let Some(first_block) = transactions
+            .iter()
+            .filter(|tx| tx.delegator_address == delegator_id)
+            .map(|tx| tx.block_height)
+            .min()
+        else {
+            info!(
+                "{} has no transactions with {}, skipping",
+                delegator_id, validator_account_id
+            );
+            continue;
+        };
+
+        let mut records = Vec::new();
+        for (index, epoch) in epochs.iter().enumerate() {
+            let next_epoch = epochs.get(index + 1);
+            let end_block = next_epoch.map_or(head_block, |next| next.start_block - 1);
+            if end_block < first_block {
+                continue;
+            }
+
+            // A record stored before the first recomputed epoch still carries the delegator's
+            // earlier stake and lifetime rewards
+            let stored_previous = if records.is_empty() {
+                delegator_repository::get_previous_epoch_record(
+                    &db,
+                    delegator_id,
+                    validator_account_id,
+                    index as u64 + 1,
+                )
+                .await?
+            } else {
+                None
+            };
+            let record = epoch_processor::process_single_delegator(
+                &clients.0,
+                &clients.1,
+                delegator_id,
+                epoch,
+                index as u64 + 1,
+                end_block,
+                next_epoch.is_none(),
+                &transactions,
+                records.last().or(stored_previous.as_ref()),
+                &pool_config,
+            )
+            .await?;
+            if let Some(record) = record {
+                records.push(record);
+            }
+        }
+
+        info!(
+            "Recomputed {} epochs of {} with {}",
+            records.len(),
+            delegator_id,
+            validator_account_id
+        );
+        if config.dry_run {
+            for record in &records {
+                info!(
+                    "Dry run: epoch {} - stake {}, rewards {}, APY {:?}%",
+                    record.epoch, record.auto_compounded_stake, record.rewards, record.apy
+                );
+            }
+        } else {
+            delegator_repository::save_delegator_data(
+                &db,
+                &records,
+                config.delegator_batch_size,
+                None,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use crate::config::Config;
+use crate::repositories::{epoch_sync_repository, transaction_repository};
+use crate::services::{epoch_processor, error_summary, metrics, near_rpc};
+use log::{error, info};
+use std::error::Error;
+use std::sync::Arc;
+
+/// Reprocesses one stored epoch, looked up by id. Only that epoch and its successor are read
+/// from `epoch_sync`, not the whole epoch list.
+pub async fn run(config: Arc<Config>, epoch_id: &str) -> Result<(), Box<dyn Error>> {
+    let (clients, db) = super::connect(&config).await?;
+
+    let epoch = epoch_sync_repository::get_by_epoch_id(&db, epoch_id)
+        .await?
+        .ok_or_else(|| format!("Epoch {} not found in epoch_sync", epoch_id))?;
+    let epoch_number =
+        epoch_sync_repository::count_epochs_before(&db, epoch.start_block).await? + 1;
+    let next_epoch = epoch_sync_repository::get_next_epoch(&db, epoch.start_block).await?;
+    // The latest epoch is still open, so bound it by the current chain head
+    let end_block = match &next_epoch {
+        Some(next) => next.start_block - 1,
+        None => near_rpc::get_settled_block_height(&clients.0, &clients.1).await?,
+    };
+
+    info!("Reprocessing epoch {} (ID: {})", epoch_number, epoch_id);
+
+    let mut failed_epochs = Vec::new();
+    for validator_account_id in &config.validator_account_ids {
+        let pool_config = config.for_validator(validator_account_id);
+        // Later transactions don't affect this epoch
+        let transactions = transaction_repository::get_transactions_in_range(
+            &db,
+            validator_account_id,
+            0,
+            end_block,
+        )
+        .await?;
+        let result = epoch_processor::process_delegator_data(
+            &clients.0,
+            &clients.1,
+            validator_account_id,
+            epoch.start_block,
+            end_block,
+            &transactions,
+            epoch_number,
+            &epoch.epoch_id,
+            epoch.timestamp.timestamp_millis() as u64,
+            next_epoch.is_none(),
+            Default::default(),
+            &db,
+            &pool_config,
+        )
+        .await;
+        match result {
+            Ok(()) => {
+                metrics::record_epoch_processed();
+                if !config.dry_run {
+                    epoch_processor::recompute_cumulative_rewards(&db, &pool_config, epoch_number)
+                        .await?;
+                }
+            }
+            Err(e) => {
+                error!("Error processing epoch: {:?}", e);
+                error_summary::record_error(format!("epoch {}", epoch_id), &e);
+                failed_epochs.extend(super::failed_for_validator(
+                    vec![epoch_id.to_string()],
+                    validator_account_id,
+                ));
+            }
+        }
+        super::export_numbered_epochs(&db, &pool_config, &[(epoch_number, epoch.clone())]).await?;
+    }
+
+    super::check_failed_epochs(failed_epochs)?;
+    info!("Reprocessed epoch {}", epoch_id);
+    Ok(())
+}
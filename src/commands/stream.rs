@@ -0,0 +1,13 @@
+use crate::config::{Config, StreamSource};
+use crate::services::{block_stream, lake_stream};
+use std::error::Error;
+use std::sync::Arc;
+
+pub async fn run(config: Arc<Config>, from_block: Option<u64>) -> Result<(), Box<dyn Error>> {
+    let (clients, db) = super::connect(&config).await?;
+    match config.stream_source {
+        StreamSource::Rpc => block_stream::run_stream(config, db, clients, from_block).await?,
+        StreamSource::Lake => lake_stream::run_stream(config, db, clients, from_block).await?,
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use crate::config::Config;
+use crate::models::{DelegatorData, Transaction};
+use crate::repositories::{delegator_repository, transaction_repository};
+use crate::services::database;
+use log::info;
+use num_bigint::BigInt;
+use num_traits::Zero;
+use serde::Serialize;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Anomaly {
+    validator_account_id: String,
+    delegator_id: String,
+    epoch: u64,
+    kind: &'static str,
+    detail: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ValidationReport {
+    records_checked: usize,
+    zero_rewards: usize,
+    implausible_apy: usize,
+    unexplained_stake_drops: usize,
+    anomalies: Vec<Anomaly>,
+}
+
+/// Scans the stored delegator records of every configured pool and prints the ones that look
+/// wrong: zero rewards on a stake carried over from the previous epoch (negative rewards are
+/// clamped to zero), APY above `max_apy` percent, and stake drops with no unstake in between.
+pub async fn run(config: Arc<Config>, max_apy: f64) -> Result<(), Box<dyn Error>> {
+    let db = database::for_reports(&database::connect_to_database().await?);
+    let mut report = ValidationReport::default();
+
+    for validator_account_id in &config.validator_account_ids {
+        let records = delegator_repository::get_pool_delegators(&db, validator_account_id).await?;
+        let transactions =
+            transaction_repository::get_all_transactions(&db, validator_account_id).await?;
+        info!(
+            "Validating {} delegator records of {}",
+            records.len(),
+            validator_account_id
+        );
+
+        report.records_checked += records.len();
+        let mut previous: Option<&DelegatorData> = None;
+        for record in &records {
+            let previous_record = previous
+                .filter(|previous| previous.delegator_id == record.delegator_id)
+                .filter(|previous| previous.epoch + 1 == record.epoch);
+            check_record(record, previous_record, &transactions, max_apy, &mut report);
+            previous = Some(record);
+        }
+    }
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn check_record(
+    record: &DelegatorData,
+    previous: Option<&DelegatorData>,
+    transactions: &[Transaction],
+    max_apy: f64,
+    report: &mut ValidationReport,
+) {
+    let mut flag = |kind: &'static str, detail: String| {
+        report.anomalies.push(Anomaly {
+            validator_account_id: record.validator_account_id.clone(),
+            delegator_id: record.delegator_id.clone(),
+            epoch: record.epoch,
+            kind,
+            detail,
+        });
+    };
+
+    if let Some(apy) = record.apy.filter(|&apy| apy > max_apy) {
+        report.implausible_apy += 1;
+        flag("implausible_apy", format!("APY {}%", apy));
+    }
+
+    let Some(previous) = previous else {
+        return;
+    };
+    let stake = parse_stake(&record.auto_compounded_stake);
+    let previous_stake = parse_stake(&previous.auto_compounded_stake);
+
+    if record.rewards == "0" && !previous_stake.is_zero() {
+        report.zero_rewards += 1;
+        flag(
+            "zero_rewards",
+            format!("no rewards on a stake of {}", previous_stake),
+        );
+    }
+
+    if stake < previous_stake {
+        let unstaked = transactions.iter().any(|tx| {
+            tx.type_ == "unstake"
+                && tx.delegator_address == record.delegator_id
+                && tx.block_height > previous.last_update_block
+                && tx.block_height <= record.last_update_block
+        });
+        if !unstaked {
+            report.unexplained_stake_drops += 1;
+            flag(
+                "unexplained_stake_drop",
+                format!(
+                    "stake fell from {} to {} without an unstake",
+                    previous_stake, stake
+                ),
+            );
+        }
+    }
+}
+
+fn parse_stake(amount: &str) -> BigInt {
+    BigInt::from_str(amount).unwrap_or_else(|_| BigInt::zero())
+}
@@ -0,0 +1,101 @@
+use crate::config::Config;
+use crate::models::EpochInfo;
+use crate::repositories::epoch_sync_repository;
+use crate::services::near_rpc;
+use log::{info, warn};
+use std::error::Error;
+use std::sync::Arc;
+
+/// Re-runs the boundary search for every epoch whose end block is an estimate, over a wider
+/// window than the sync uses, and corrects the epoch and its successor when it is found.
+pub async fn run(config: Arc<Config>, window_blocks: Option<u64>) -> Result<(), Box<dyn Error>> {
+    let (clients, db) = super::connect(&config).await?;
+    let window_blocks = window_blocks.unwrap_or(config.epoch_blocks * 2);
+
+    let unverified = epoch_sync_repository::get_unverified_epochs(&db).await?;
+    if unverified.is_empty() {
+        info!("Every stored epoch boundary is verified");
+        return Ok(());
+    }
+    info!("Verifying {} estimated epoch boundaries", unverified.len());
+
+    let current_block = near_rpc::get_settled_block_height(&clients.0, &clients.1).await?;
+    let mut corrected = 0;
+    let mut unresolved = Vec::new();
+    for epoch in &unverified {
+        let Some(next) = epoch_sync_repository::get_next_epoch(&db, epoch.start_block).await?
+        else {
+            warn!(
+                "Epoch {} has no stored successor, skipping it",
+                epoch.epoch_id
+            );
+            unresolved.push(epoch.epoch_id.clone());
+            continue;
+        };
+
+        let (boundary, verified) = near_rpc::find_epoch_boundary(
+            epoch.start_block,
+            (epoch.start_block + window_blocks).min(current_block),
+            &epoch.epoch_id,
+            &clients.0,
+            &clients.1,
+            config.rpc_inter_request_delay_ms,
+        )
+        .await?;
+        if !verified {
+            warn!(
+                "No epoch change found within {} blocks of epoch {} starting at block {}",
+                window_blocks, epoch.epoch_id, epoch.start_block
+            );
+            unresolved.push(epoch.epoch_id.clone());
+            continue;
+        }
+
+        let (_, boundary_block) =
+            near_rpc::get_block_info(&clients.0, &clients.1, boundary).await?;
+        let corrected_next = EpochInfo {
+            start_block: boundary,
+            epoch_id: boundary_block.header.epoch_id.to_string(),
+            timestamp: near_rpc::block_timestamp(boundary_block.header.timestamp),
+            block_hash: Some(boundary_block.header.hash.to_string()),
+            ..next.clone()
+        };
+        info!(
+            "Epoch {} ends at block {}, not {:?}; epoch {} starts at block {}, not {}",
+            epoch.epoch_id,
+            boundary - 1,
+            epoch.end_block,
+            corrected_next.epoch_id,
+            boundary,
+            next.start_block
+        );
+        if config.dry_run {
+            continue;
+        }
+        epoch_sync_repository::correct_boundary(
+            &db,
+            &epoch.epoch_id,
+            next.start_block,
+            &corrected_next,
+        )
+        .await?;
+        corrected += 1;
+        warn!(
+            "Reprocess epochs {} and {} so their rewards use the corrected boundary",
+            epoch.epoch_id, corrected_next.epoch_id
+        );
+    }
+
+    info!(
+        "Corrected {} of {} estimated epoch boundaries",
+        corrected,
+        unverified.len()
+    );
+    if !unresolved.is_empty() {
+        warn!(
+            "Boundaries still unverified for epochs: {}",
+            unresolved.join(", ")
+        );
+    }
+    Ok(())
+}
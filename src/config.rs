@@ -1,37 +1,644 @@
+use num_bigint::BigInt;
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How APY is reported for the open epoch, whose rewards only cover part of an epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialEpochApy {
+    /// Annualize using the fraction of the epoch that has elapsed
+    Scale,
+    /// Store zero APY and mark it provisional
+    Exclude,
+}
+
+impl FromStr for PartialEpochApy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "scale" => Ok(PartialEpochApy::Scale),
+            "exclude" => Ok(PartialEpochApy::Exclude),
+            _ => Err(format!("Invalid PARTIAL_EPOCH_APY: {}", value)),
+        }
+    }
+}
+
+/// What to do when a delegator's rewards for an epoch come out negative, which usually means a
+/// transaction was missed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RewardNegativePolicy {
+    /// Store zero rewards
+    Zero,
+    /// Fail the epoch
+    Error,
+    /// Store the negative rewards and flag the record with `negative_rewards`
+    Record,
+}
+
+impl FromStr for RewardNegativePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "zero" => Ok(RewardNegativePolicy::Zero),
+            "error" => Ok(RewardNegativePolicy::Error),
+            "record" => Ok(RewardNegativePolicy::Record),
+            _ => Err(format!("Invalid REWARD_NEGATIVE_POLICY: {}", value)),
+        }
+    }
+}
+
+/// Block at which delegator balances are read for an epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceSample {
+    /// The epoch's first block
+    Start,
+    /// The epoch's last block, after the epoch's rewards have accrued
+    End,
+    /// Both ends, with rewards derived from the end balance
+    Both,
+}
+
+impl FromStr for BalanceSample {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "start" => Ok(BalanceSample::Start),
+            "end" => Ok(BalanceSample::End),
+            "both" => Ok(BalanceSample::Both),
+            _ => Err(format!("Invalid BALANCE_SAMPLE: {}", value)),
+        }
+    }
+}
+
+/// Format of the log output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// env_logger's plain text lines
+    Text,
+    /// One JSON object per line for log aggregators
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid LOG_FORMAT: {}", value)),
+        }
+    }
+}
+
+/// How final the chain tip read by `get_latest_block` must be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TipFinality {
+    /// Final blocks, which can't be reverted
+    Final,
+    /// Doomslug-final blocks, a couple of blocks ahead of final
+    NearFinal,
+}
+
+impl FromStr for TipFinality {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "final" => Ok(TipFinality::Final),
+            "near-final" => Ok(TipFinality::NearFinal),
+            _ => Err(format!("Invalid FINALITY: {}", value)),
+        }
+    }
+}
+
+/// Order in which nearblocks pages through transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NearblocksOrder {
+    Asc,
+    Desc,
+}
+
+impl NearblocksOrder {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NearblocksOrder::Asc => "asc",
+            NearblocksOrder::Desc => "desc",
+        }
+    }
+}
+
+impl FromStr for NearblocksOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "asc" => Ok(NearblocksOrder::Asc),
+            "desc" => Ok(NearblocksOrder::Desc),
+            _ => Err(format!("Invalid NEARBLOCKS_ORDER: {}", value)),
+        }
+    }
+}
+
+/// Largest page the nearblocks API serves.
+pub const NEARBLOCKS_MAX_PER_PAGE: u32 = 250;
+
+/// Where new staking transactions are discovered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxnSource {
+    /// The nearblocks.io stake-txns API
+    Nearblocks,
+    /// Scan blocks and chunks over NEAR RPC
+    Rpc,
+}
+
+impl FromStr for TxnSource {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "nearblocks" => Ok(TxnSource::Nearblocks),
+            "rpc" => Ok(TxnSource::Rpc),
+            _ => Err(format!("Invalid TXN_SOURCE: {}", value)),
+        }
+    }
+}
+
+/// Where the `stream` command reads new blocks from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamSource {
+    /// Poll the configured RPC nodes
+    Rpc,
+    /// Read NEAR Lake's S3 bucket
+    Lake,
+}
+
+impl FromStr for StreamSource {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "rpc" => Ok(StreamSource::Rpc),
+            "lake" => Ok(StreamSource::Lake),
+            _ => Err(format!("Invalid STREAM_SOURCE: {}", value)),
+        }
+    }
+}
+
+/// The NEAR network being indexed, used to pick RPC, nearblocks and epoch-length defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    /// A local `neard` node started with `neard init --chain-id localnet`
+    Localnet,
+}
+
+impl Network {
+    /// Blocks per epoch from the network's genesis config.
+    pub fn default_epoch_blocks(self) -> u64 {
+        match self {
+            // Testnet genesis uses the same epoch length as mainnet
+            Network::Mainnet | Network::Testnet => 43200,
+            Network::Localnet => 500,
+        }
+    }
+
+    /// Average seconds per block, used to turn epoch length into epochs per year.
+    pub fn default_block_time_secs(self) -> f64 {
+        match self {
+            Network::Mainnet | Network::Testnet => 1.0,
+            Network::Localnet => 0.6,
+        }
+    }
+
+    /// Archival RPC endpoint used when `PRIMARY_RPC` / `SECONDARY_RPC` are unset.
+    pub fn default_rpc(self) -> &'static str {
+        match self {
+            Network::Mainnet => "https://archival-rpc.mainnet.near.org",
+            Network::Testnet => "https://archival-rpc.testnet.near.org",
+            Network::Localnet => "http://127.0.0.1:3030",
+        }
+    }
+
+    /// nearblocks API for the network. Localnet isn't indexed by nearblocks, so it needs
+    /// `TXN_SOURCE=rpc` or a self-hosted `NEARBLOCKS_BASE_URL`.
+    pub fn default_nearblocks_base_url(self) -> &'static str {
+        match self {
+            Network::Mainnet | Network::Localnet => "https://api.nearblocks.io",
+            Network::Testnet => "https://api-testnet.nearblocks.io",
+        }
+    }
+
+    /// NEAR Lake bucket of the network. Localnet has none, so it needs `LAKE_BUCKET`.
+    pub fn default_lake_bucket(self) -> Option<&'static str> {
+        match self {
+            Network::Mainnet => Some("near-lake-data-mainnet"),
+            Network::Testnet => Some("near-lake-data-testnet"),
+            Network::Localnet => None,
+        }
+    }
+
+    /// Account suffix of the lockup contracts created by the network's lockup factory.
+    pub fn default_lockup_account_suffix(self) -> &'static str {
+        match self {
+            Network::Mainnet | Network::Localnet => ".lockup.near",
+            Network::Testnet => ".lockup.testnet",
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "localnet" => Ok(Network::Localnet),
+            _ => Err(format!("Invalid NETWORK: {}", value)),
+        }
+    }
+}
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+/// HTTP client whose connect and whole-request time are both capped at `timeout`, so a wedged
+/// endpoint fails the call instead of stalling the run.
+fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(timeout)
+        .timeout(timeout)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// How many epochs of `epoch_blocks` blocks fit in a year at `block_time_secs` per block.
+pub fn epochs_per_year(epoch_blocks: u64, block_time_secs: f64) -> f64 {
+    SECONDS_PER_YEAR / (epoch_blocks as f64 * block_time_secs)
+}
+
+#[derive(Clone)]
 pub struct Config {
+    /// Pool being processed. Defaults to the first of `validator_account_ids`; the indexing
+    /// pipeline runs once per pool with a copy from `for_validator`.
     pub validator_account_id: String,
+    pub validator_account_ids: Vec<String>,
     pub primary_rpc: String,
     pub secondary_rpc: String,
     pub parallel_limit: usize,
+    pub parallel_epoch_sync: bool,
     pub batch_size: usize,
+    pub network: Network,
     pub epoch_blocks: u64,
+    pub detect_epoch_length: bool,
+    pub block_time_secs: f64,
+    /// Set directly through `EPOCHS_PER_YEAR` rather than derived from `epoch_blocks`.
+    pub epochs_per_year_fixed: bool,
+    pub epochs_per_year: f64,
     pub delegator_batch_size: usize,
+    pub accounts_page_size: u64,
+    pub max_malformed_accounts: f64,
+    pub min_delegators: usize,
+    pub include_zero_stake: bool,
+    pub resolve_lockup_owners: bool,
+    pub lockup_account_suffix: String,
+    pub lockup_accounts: Vec<String>,
+    pub min_total_stake: BigInt,
+    pub balance_sample: BalanceSample,
+    pub rpc_inter_request_delay_ms: Option<u64>,
+    pub rpc_breaker_threshold: u32,
+    pub rpc_breaker_cooldown_secs: u64,
+    pub retry_budget_secs: Option<u64>,
+    /// Shared by every RPC and nearblocks call so connections are pooled and kept alive.
+    pub http_client: reqwest::Client,
+    pub explorer_base_url: Option<String>,
+    pub metrics_port: Option<u16>,
+    pub api_port: Option<u16>,
+    pub webhook_url: Option<String>,
+    pub otel_endpoint: Option<String>,
+    pub statsd_addr: Option<String>,
+    pub collection_prefix: Option<String>,
+    pub error_summary: bool,
+    pub save_run_summary: bool,
+    pub record_reward_events: bool,
+    pub validator_history_limit: u32,
+    pub log_format: LogFormat,
+    pub finality: TipFinality,
+    pub tip_lag_blocks: u64,
+    pub as_of_block: Option<u64>,
+    pub fail_on_epoch_error: bool,
+    pub max_epochs_per_run: Option<usize>,
+    pub partial_epoch_apy: PartialEpochApy,
+    pub reward_negative_policy: RewardNegativePolicy,
+    pub rewards_verify_tolerance: Option<f64>,
+    pub txn_source: TxnSource,
+    pub stream_source: StreamSource,
+    pub lake_bucket: Option<String>,
+    pub lake_region: String,
+    pub nearblocks_base_url: String,
+    pub nearblocks_api_key: Option<String>,
+    pub nearblocks_per_page: u32,
+    pub nearblocks_order: NearblocksOrder,
+    pub nearblocks_backoff_base_secs: u64,
+    pub nearblocks_backoff_max_secs: u64,
+    pub refetch_overlap_blocks: u64,
+    pub fetch_from_block: Option<u64>,
+    pub save_raw_txns: bool,
+    pub mongo_transactions: bool,
+    pub dry_run: bool,
+    pub export_dir: Option<String>,
 }
 
 impl Config {
     pub fn from_env() -> Self {
+        let network: Network = env::var("NETWORK")
+            .unwrap_or_else(|_| "mainnet".to_string())
+            .parse()
+            .unwrap();
+        let epoch_blocks = env::var("EPOCH_BLOCKS")
+            .map(|value| value.parse().unwrap())
+            .unwrap_or_else(|_| network.default_epoch_blocks());
+        let block_time_secs = env::var("BLOCK_TIME_SECS")
+            .map(|value| value.parse().unwrap())
+            .unwrap_or_else(|_| network.default_block_time_secs());
+        let http_timeout_secs = env::var("HTTP_TIMEOUT_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap();
+
+        // VALIDATOR_ACCOUNT_IDS lists several pools; VALIDATOR_ACCOUNT_ID is kept for one
+        let validator_account_ids: Vec<String> = env::var("VALIDATOR_ACCOUNT_IDS")
+            .or_else(|_| env::var("VALIDATOR_ACCOUNT_ID"))
+            .unwrap_or_else(|_| "luganodes.pool.near".to_string())
+            .split(',')
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect();
+        assert!(
+            !validator_account_ids.is_empty(),
+            "VALIDATOR_ACCOUNT_IDS must list at least one pool"
+        );
+
         Self {
-            validator_account_id: env::var("VALIDATOR_ACCOUNT_ID")
-                .unwrap_or_else(|_| "luganodes.pool.near".to_string()),
-            primary_rpc: env::var("PRIMARY_RPC").expect("PRIMARY_RPC must be set"),
-            secondary_rpc: env::var("SECONDARY_RPC").expect("SECONDARY_RPC must be set"),
+            validator_account_id: validator_account_ids[0].clone(),
+            validator_account_ids,
+            primary_rpc: env::var("PRIMARY_RPC")
+                .unwrap_or_else(|_| network.default_rpc().to_string()),
+            secondary_rpc: env::var("SECONDARY_RPC")
+                .unwrap_or_else(|_| network.default_rpc().to_string()),
             parallel_limit: env::var("PARALLEL_LIMIT")
                 .unwrap_or_else(|_| "35".to_string())
                 .parse()
                 .unwrap(),
+            parallel_epoch_sync: env::var("PARALLEL_EPOCH_SYNC")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap(),
             batch_size: env::var("BATCH_SIZE")
                 .unwrap_or_else(|_| "10".to_string())
                 .parse()
                 .unwrap(),
-            epoch_blocks: env::var("EPOCH_BLOCKS")
-                .unwrap_or_else(|_| "43200".to_string())
+            network,
+            epoch_blocks,
+            detect_epoch_length: env::var("DETECT_EPOCH_LENGTH")
+                .unwrap_or_else(|_| "false".to_string())
                 .parse()
                 .unwrap(),
+            block_time_secs,
+            epochs_per_year_fixed: env::var("EPOCHS_PER_YEAR").is_ok(),
+            epochs_per_year: env::var("EPOCHS_PER_YEAR")
+                .map(|value| value.parse().unwrap())
+                .unwrap_or_else(|_| epochs_per_year(epoch_blocks, block_time_secs)),
             delegator_batch_size: env::var("DELEGATOR_BATCH_SIZE")
                 .unwrap_or_else(|_| "1000".to_string())
                 .parse()
                 .unwrap(),
+            accounts_page_size: env::var("ACCOUNTS_PAGE_SIZE")
+                .unwrap_or_else(|_| "1000".to_string())
+                .parse()
+                .unwrap(),
+            max_malformed_accounts: env::var("MAX_MALFORMED_ACCOUNTS")
+                .unwrap_or_else(|_| "0.01".to_string())
+                .parse()
+                .unwrap(),
+            min_delegators: env::var("MIN_DELEGATORS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap(),
+            include_zero_stake: env::var("INCLUDE_ZERO_STAKE")
+                .unwrap_or_else(|_| "true".to_string())
+                .parse()
+                .unwrap(),
+            resolve_lockup_owners: env::var("RESOLVE_LOCKUP_OWNERS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap(),
+            lockup_account_suffix: env::var("LOCKUP_ACCOUNT_SUFFIX")
+                .unwrap_or_else(|_| network.default_lockup_account_suffix().to_string()),
+            lockup_accounts: env::var("LOCKUP_ACCOUNTS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty())
+                .collect(),
+            min_total_stake: env::var("MIN_TOTAL_STAKE")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap(),
+            balance_sample: env::var("BALANCE_SAMPLE")
+                .unwrap_or_else(|_| "start".to_string())
+                .parse()
+                .unwrap(),
+            rpc_inter_request_delay_ms: env::var("RPC_INTER_REQUEST_DELAY_MS")
+                .ok()
+                .map(|delay| delay.parse().unwrap()),
+            rpc_breaker_threshold: env::var("RPC_BREAKER_THRESHOLD")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .unwrap(),
+            rpc_breaker_cooldown_secs: env::var("RPC_BREAKER_COOLDOWN_SECS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap(),
+            retry_budget_secs: env::var("RETRY_BUDGET_SECS")
+                .ok()
+                .map(|secs| secs.parse().unwrap()),
+            http_client: http_client(Duration::from_secs(http_timeout_secs)),
+            explorer_base_url: env::var("EXPLORER_BASE_URL").ok(),
+            metrics_port: env::var("METRICS_PORT")
+                .ok()
+                .map(|port| port.parse().unwrap()),
+            api_port: env::var("API_PORT").ok().map(|port| port.parse().unwrap()),
+            webhook_url: env::var("WEBHOOK_URL").ok(),
+            otel_endpoint: env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok(),
+            statsd_addr: env::var("STATSD_ADDR").ok(),
+            collection_prefix: env::var("COLLECTION_PREFIX").ok(),
+            error_summary: env::var("ERROR_SUMMARY")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap(),
+            validator_history_limit: env::var("VALIDATOR_HISTORY_LIMIT")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()
+                .unwrap(),
+            record_reward_events: env::var("RECORD_REWARD_EVENTS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap(),
+            save_run_summary: env::var("SAVE_RUN_SUMMARY")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap(),
+            log_format: env::var("LOG_FORMAT")
+                .unwrap_or_else(|_| "text".to_string())
+                .parse()
+                .unwrap(),
+            tip_lag_blocks: env::var("TIP_LAG_BLOCKS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap(),
+            as_of_block: env::var("AS_OF_BLOCK").ok().map(|v| v.parse().unwrap()),
+            finality: env::var("FINALITY")
+                .unwrap_or_else(|_| "final".to_string())
+                .parse()
+                .unwrap(),
+            fail_on_epoch_error: env::var("FAIL_ON_EPOCH_ERROR")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap(),
+            max_epochs_per_run: env::var("MAX_EPOCHS_PER_RUN")
+                .ok()
+                .map(|max_epochs| max_epochs.parse().unwrap()),
+            partial_epoch_apy: env::var("PARTIAL_EPOCH_APY")
+                .unwrap_or_else(|_| "scale".to_string())
+                .parse()
+                .unwrap(),
+            reward_negative_policy: env::var("REWARD_NEGATIVE_POLICY")
+                .unwrap_or_else(|_| "zero".to_string())
+                .parse()
+                .unwrap(),
+            rewards_verify_tolerance: env::var("REWARDS_VERIFY_TOLERANCE")
+                .ok()
+                .map(|tolerance| tolerance.parse().unwrap()),
+            txn_source: env::var("TXN_SOURCE")
+                .unwrap_or_else(|_| "nearblocks".to_string())
+                .parse()
+                .unwrap(),
+            stream_source: env::var("STREAM_SOURCE")
+                .unwrap_or_else(|_| "rpc".to_string())
+                .parse()
+                .unwrap(),
+            lake_bucket: env::var("LAKE_BUCKET")
+                .ok()
+                .or_else(|| network.default_lake_bucket().map(str::to_string)),
+            lake_region: env::var("LAKE_REGION").unwrap_or_else(|_| "eu-central-1".to_string()),
+            nearblocks_base_url: env::var("NEARBLOCKS_BASE_URL")
+                .unwrap_or_else(|_| network.default_nearblocks_base_url().to_string()),
+            nearblocks_api_key: env::var("NEARBLOCKS_API_KEY").ok(),
+            nearblocks_per_page: env::var("NEARBLOCKS_PER_PAGE")
+                .unwrap_or_else(|_| "25".to_string())
+                .parse::<u32>()
+                .unwrap()
+                .clamp(1, NEARBLOCKS_MAX_PER_PAGE),
+            nearblocks_order: env::var("NEARBLOCKS_ORDER")
+                .unwrap_or_else(|_| "asc".to_string())
+                .parse()
+                .unwrap(),
+            nearblocks_backoff_base_secs: env::var("NEARBLOCKS_BACKOFF_BASE_SECS")
+                .unwrap_or_else(|_| "2".to_string())
+                .parse()
+                .unwrap(),
+            nearblocks_backoff_max_secs: env::var("NEARBLOCKS_BACKOFF_MAX_SECS")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .unwrap(),
+            refetch_overlap_blocks: env::var("REFETCH_OVERLAP_BLOCKS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap(),
+            fetch_from_block: env::var("FETCH_FROM_BLOCK")
+                .ok()
+                .map(|block| block.parse().unwrap()),
+            save_raw_txns: env::var("SAVE_RAW_TXNS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap(),
+            mongo_transactions: env::var("MONGO_TRANSACTIONS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap(),
+            dry_run: env::var("DRY_RUN")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap(),
+            export_dir: env::var("EXPORT_DIR").ok(),
         }
     }
+
+    /// Copy of the configuration that processes `validator_account_id`.
+    pub fn for_validator(&self, validator_account_id: &str) -> Self {
+        Self {
+            validator_account_id: validator_account_id.to_string(),
+            ..self.clone()
+        }
+    }
+
+    /// Switches to the epoch length read from the chain. `epochs_per_year` follows it unless
+    /// `EPOCHS_PER_YEAR` was set.
+    pub fn set_epoch_blocks(&mut self, epoch_blocks: u64) {
+        self.epoch_blocks = epoch_blocks;
+        if !self.epochs_per_year_fixed {
+            self.epochs_per_year = epochs_per_year(epoch_blocks, self.block_time_secs);
+        }
+    }
+
+    /// Whether `account_id` is a lockup contract whose owner should be looked up: it ends in
+    /// `lockup_account_suffix` or is listed in `LOCKUP_ACCOUNTS`.
+    pub fn is_lockup_account(&self, account_id: &str) -> bool {
+        self.resolve_lockup_owners
+            && (account_id.ends_with(&self.lockup_account_suffix)
+                || self.lockup_accounts.iter().any(|id| id == account_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network_epochs_per_year(network: Network) -> f64 {
+        epochs_per_year(
+            network.default_epoch_blocks(),
+            network.default_block_time_secs(),
+        )
+    }
+
+    #[test]
+    fn mainnet_defaults_give_730_epochs_per_year() {
+        assert_eq!(network_epochs_per_year(Network::Mainnet), 730.0);
+    }
+
+    #[test]
+    fn testnet_annualizes_like_mainnet() {
+        assert_eq!(
+            network_epochs_per_year(Network::Testnet),
+            network_epochs_per_year(Network::Mainnet)
+        );
+    }
+
+    #[test]
+    fn localnet_annualizes_its_short_epochs() {
+        let localnet = network_epochs_per_year(Network::Localnet);
+        assert!((localnet - SECONDS_PER_YEAR / 300.0).abs() < 1e-6);
+        assert_ne!(localnet, network_epochs_per_year(Network::Mainnet));
+    }
+
+    #[test]
+    fn network_parses_from_its_name() {
+        assert_eq!("testnet".parse::<Network>(), Ok(Network::Testnet));
+        assert!("betanet".parse::<Network>().is_err());
+    }
 }
@@ -0,0 +1,170 @@
+use near_jsonrpc_client::errors::JsonRpcError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum IndexerError {
+    #[error("RPC error: {0}")]
+    Rpc(String),
+    #[error("MongoDB error: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+    #[error("Parse error: {0}")]
+    Parse(String),
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+    #[error("NEAR Lake error: {0}")]
+    Lake(String),
+    #[error("Block {0} not found")]
+    BlockNotFound(u64),
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// The account doesn't implement the staking pool's `get_accounts` view method.
+    #[error("{account_id} is not a staking pool contract: {reason}")]
+    NotAStakingPool { account_id: String, reason: String },
+    #[error("{malformed} of {total} accounts of {validator_account_id} at block {block_height} are malformed")]
+    MalformedAccounts {
+        validator_account_id: String,
+        block_height: u64,
+        malformed: usize,
+        total: usize,
+    },
+    #[error("negative rewards {rewards} for {delegator_id} in epoch {epoch_id}")]
+    NegativeRewards {
+        delegator_id: String,
+        epoch_id: String,
+        rewards: String,
+    },
+    #[error("retry budget of {limit_secs}s exhausted ({spent_secs}s spent) while {operation}")]
+    RetryBudgetExhausted {
+        operation: String,
+        spent_secs: u64,
+        limit_secs: u64,
+    },
+    #[error("account state at block {block_height} is after AS_OF_BLOCK {as_of_block}")]
+    BeyondAsOfBlock { block_height: u64, as_of_block: u64 },
+}
+
+/// A transaction amount that couldn't be turned into a whole number of yoctoNEAR.
+#[derive(Debug, Error)]
+pub enum ParseAmountError {
+    #[error("empty amount")]
+    Empty,
+    #[error("negative amount: {0}")]
+    Negative(String),
+    #[error("invalid amount: {0}")]
+    Invalid(String),
+}
+
+/// An epoch boundary that would corrupt the stored epoch sequence if it were saved.
+#[derive(Debug, Error)]
+pub enum EpochSyncError {
+    #[error("epoch starting at block {start_block} has an empty epoch_id")]
+    EmptyEpochId { start_block: u64 },
+    #[error("epoch {epoch_id} ends at block {end_block}, before its start block {start_block}")]
+    EndBeforeStart {
+        epoch_id: String,
+        start_block: u64,
+        end_block: u64,
+    },
+    #[error(
+        "epoch {epoch_id} starts at block {start_block}, not after the latest stored epoch {latest_epoch_id} at block {latest_start_block}"
+    )]
+    NotAfterLatest {
+        epoch_id: String,
+        start_block: u64,
+        latest_epoch_id: String,
+        latest_start_block: u64,
+    },
+    #[error("MongoDB error: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+}
+
+impl IndexerError {
+    /// Whether retrying the same request may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, IndexerError::Rpc(_) | IndexerError::RateLimited(_))
+    }
+}
+
+impl<E: std::fmt::Debug> From<JsonRpcError<E>> for IndexerError {
+    fn from(error: JsonRpcError<E>) -> Self {
+        let message = format!("{:?}", error);
+        if message.contains("TooManyRequests") {
+            IndexerError::RateLimited(message)
+        } else {
+            IndexerError::Rpc(message)
+        }
+    }
+}
+
+impl From<reqwest::Error> for IndexerError {
+    fn from(error: reqwest::Error) -> Self {
+        IndexerError::Rpc(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for IndexerError {
+    fn from(error: serde_json::Error) -> Self {
+        IndexerError::Parse(error.to_string())
+    }
+}
+
+impl From<num_bigint::ParseBigIntError> for IndexerError {
+    fn from(error: num_bigint::ParseBigIntError) -> Self {
+        IndexerError::Parse(error.to_string())
+    }
+}
+
+impl From<std::num::ParseIntError> for IndexerError {
+    fn from(error: std::num::ParseIntError) -> Self {
+        IndexerError::Parse(error.to_string())
+    }
+}
+
+impl From<near_primitives::account::id::ParseAccountError> for IndexerError {
+    fn from(error: near_primitives::account::id::ParseAccountError) -> Self {
+        IndexerError::Parse(error.to_string())
+    }
+}
+
+impl From<ParseAmountError> for IndexerError {
+    fn from(error: ParseAmountError) -> Self {
+        IndexerError::Parse(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_jsonrpc_client::errors::{JsonRpcServerError, JsonRpcServerResponseStatusError};
+
+    fn server_error(status: JsonRpcServerResponseStatusError) -> JsonRpcError<()> {
+        JsonRpcError::ServerError(JsonRpcServerError::ResponseStatusError(status))
+    }
+
+    #[test]
+    fn rpc_errors_are_told_apart_from_rate_limits() {
+        let rate_limited = IndexerError::from(server_error(
+            JsonRpcServerResponseStatusError::TooManyRequests,
+        ));
+        assert!(matches!(rate_limited, IndexerError::RateLimited(_)));
+        assert!(rate_limited.is_retryable());
+
+        let unauthorized =
+            IndexerError::from(server_error(JsonRpcServerResponseStatusError::Unauthorized));
+        assert!(matches!(unauthorized, IndexerError::Rpc(_)));
+        assert!(unauthorized.is_retryable());
+    }
+
+    #[test]
+    fn parse_and_missing_block_errors_are_not_retried() {
+        let parse = IndexerError::from("12a".parse::<u64>().unwrap_err());
+        assert!(matches!(parse, IndexerError::Parse(_)));
+        assert!(!parse.is_retryable());
+
+        let json = IndexerError::from(serde_json::from_str::<u64>("{").unwrap_err());
+        assert!(matches!(json, IndexerError::Parse(_)));
+
+        assert!(!IndexerError::BlockNotFound(100).is_retryable());
+        assert!(!IndexerError::Config("PARALLEL_LIMIT".to_string()).is_retryable());
+    }
+}
@@ -0,0 +1,22 @@
+use crate::config::LogFormat;
+use chrono::Utc;
+use std::io::Write;
+
+/// Initializes env_logger, honoring RUST_LOG for filtering in either format.
+pub fn init(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": Utc::now().to_rfc3339(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+                "module": record.module_path(),
+                "line": record.line(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    builder.init();
+}
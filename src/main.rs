@@ -1,31 +1,157 @@
+use crate::services::near_rpc::RpcClient;
+use chrono::Utc;
+use clap::Parser;
 use dotenv::dotenv;
-use futures::stream::{self, StreamExt};
-use log::{error, info};
+use log::{error, info, warn};
 use mongodb::Database;
-use near_jsonrpc_client::JsonRpcClient;
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::time::{self, Duration};
+mod cli;
+mod commands;
 mod config;
+mod error;
+mod logging;
 mod models;
 mod repositories;
 mod services;
 mod transaction_fetcher;
 mod utils;
 
+use crate::cli::{Cli, Command};
 use crate::config::Config;
-use crate::models::{EpochInfo, Transaction};
-use crate::repositories::epoch_sync_repository;
-use crate::services::{database, epoch_processor, near_rpc};
+use crate::models::{EpochInfo, RunSummary, Transaction};
+use crate::repositories::{
+    collections, epoch_repository, epoch_sync_repository, indexes, run_summary_repository,
+};
+use crate::services::{
+    api, database, epoch_processor, error_summary, metrics, near_rpc, retry_budget, statsd,
+    telemetry,
+};
 use crate::transaction_fetcher::fetch_and_process_transactions;
 
 #[tokio::main]
 
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    dotenv().ok();
+
+    let mut config = Config::from_env();
+    logging::init(config.log_format);
+    telemetry::init(config.otel_endpoint.as_deref());
+    statsd::init(config.statsd_addr.as_deref());
+    collections::set_prefix(config.collection_prefix.as_deref());
+    near_rpc::set_tip_finality(config.finality);
+    near_rpc::set_tip_lag(config.tip_lag_blocks);
+    near_rpc::set_as_of_block(config.as_of_block);
+    near_rpc::set_circuit_breaker(
+        config.rpc_breaker_threshold,
+        Duration::from_secs(config.rpc_breaker_cooldown_secs),
+    );
+    retry_budget::init(config.retry_budget_secs);
+    config.dry_run |= cli.dry_run;
+    if cli.export.is_some() {
+        config.export_dir = cli.export.clone();
+    }
+    if config.detect_epoch_length {
+        let (primary_client, secondary_client) = near_rpc::create_near_connections(
+            &config.primary_rpc,
+            &config.secondary_rpc,
+            config.http_client.clone(),
+        )
+        .await;
+        match near_rpc::get_epoch_length(&primary_client, &secondary_client).await {
+            Ok(epoch_length) => {
+                info!("Detected epoch length of {} blocks", epoch_length);
+                config.set_epoch_blocks(epoch_length);
+            }
+            Err(e) => warn!(
+                "Could not detect epoch length, using EPOCH_BLOCKS={}: {}",
+                config.epoch_blocks, e
+            ),
+        }
+    }
+    let config = Arc::new(config);
+    if config.dry_run {
+        warn!("Dry run: results are computed and logged but not saved to MongoDB");
+    }
+    info!(
+        "Network {:?}: {} blocks per epoch, {:.1} epochs per year",
+        config.network, config.epoch_blocks, config.epochs_per_year
+    );
+
+    let result = match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run_scheduled(config).await,
+        Command::Backfill {
+            from_block,
+            to_block,
+            from_date,
+            to_date,
+        } => commands::backfill::run(config, from_block, to_block, from_date, to_date).await,
+        Command::ReprocessEpoch { epoch_id } => {
+            commands::reprocess_epoch::run(config, &epoch_id).await
+        }
+        Command::ReprocessDelegator { delegator_id } => {
+            commands::reprocess_delegator::run(config, &delegator_id).await
+        }
+        Command::FetchTxnsOnly => commands::fetch_txns::run(config).await,
+        Command::QueryDelegator {
+            delegator_id,
+            aggregate_pools,
+            history,
+        } => commands::query_delegator::run(config, &delegator_id, aggregate_pools, history).await,
+        Command::QueryValidator {
+            from_epoch,
+            to_epoch,
+        } => commands::query_validator::run(config, from_epoch, to_epoch).await,
+        Command::RecomputeApy => commands::recompute_apy::run(config).await,
+        Command::Validate { max_apy } => commands::validate::run(config, max_apy).await,
+        Command::VerifyBoundaries { window_blocks } => {
+            commands::verify_boundaries::run(config, window_blocks).await
+        }
+        Command::CheckNewEpochs {
+            max_pending,
+            exit_code,
+        } => commands::check_new_epochs::run(config, max_pending, exit_code).await,
+        Command::Stream { from_block } => commands::stream::run(config, from_block).await,
+    };
+    telemetry::shutdown();
+    result
+}
+
+async fn run_scheduled(config: Arc<Config>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(port) = config.metrics_port {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(port).await {
+                error!("Metrics server error: {:?}", e);
+            }
+        });
+    }
+
+    if let Some(port) = config.api_port {
+        let db = database::connect_to_database().await?;
+        tokio::spawn(async move {
+            if let Err(e) = api::serve(port, database::for_reports(&db)).await {
+                error!("API server error: {:?}", e);
+            }
+        });
+    }
+
+    if !config.dry_run {
+        let db = database::connect_to_database().await?;
+        indexes::ensure_indexes(&db).await?;
+    }
+
     // Run the task immediately
     info!("Starting initial run...");
-    if let Err(e) = run_indexer().await {
+    let result = run_indexer(Arc::clone(&config)).await;
+    if let Err(e) = &result {
         error!("Error in initial run: {:?}", e);
+        error_summary::record_error("run".to_string(), e.as_ref());
     }
+    report_run_errors(&config);
+    settle_run(result, config.fail_on_epoch_error)?;
 
     // Create an interval that fires every 12 hours
     let mut interval = time::interval(Duration::from_secs(12 * 60 * 60));
@@ -36,41 +162,102 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         info!("Starting scheduled run...");
 
         // Run the indexer in a new task to prevent blocking
-        if let Err(e) = run_indexer().await {
+        let result = run_indexer(Arc::clone(&config)).await;
+        if let Err(e) = &result {
             error!("Error in scheduled run: {:?}", e);
+            error_summary::record_error("run".to_string(), e.as_ref());
         }
+        report_run_errors(&config);
+        settle_run(result, config.fail_on_epoch_error)?;
     }
 }
 
-async fn run_indexer() -> Result<(), Box<dyn std::error::Error>> {
-    dotenv().ok();
-    env_logger::init();
+/// Whether the scheduler should stop after a run. With FAIL_ON_EPOCH_ERROR set, a run that
+/// failed outright or left epochs unprocessed ends the process with a non-zero exit code;
+/// otherwise it is retried at the next scheduled run.
+fn settle_run(
+    result: Result<Vec<String>, Box<dyn std::error::Error>>,
+    fail_on_epoch_error: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !fail_on_epoch_error {
+        return Ok(());
+    }
+    commands::check_failed_epochs(result?)
+}
 
+fn report_run_errors(config: &Config) {
+    let summary = error_summary::take();
+    if !config.error_summary {
+        return;
+    }
+
+    if summary.is_empty() {
+        info!("Run completed without errors");
+    } else {
+        match serde_json::to_string_pretty(&summary) {
+            Ok(json) => warn!("Run completed with {} errors:\n{}", summary.total, json),
+            Err(e) => error!("Failed to serialize error summary: {:?}", e),
+        }
+    }
+}
+
+/// Returns the ids of the epochs that failed to process.
+async fn run_indexer(config: Arc<Config>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
     info!("Starting NEAR indexer script");
-    let config = Arc::new(Config::from_env());
+    retry_budget::reset();
+    let started_at = Utc::now();
+    let started = Instant::now();
 
     info!("Connecting to NEAR network...");
-    let (primary_client, secondary_client) =
-        near_rpc::create_near_connections(&config.primary_rpc, &config.secondary_rpc).await;
+    let (primary_client, secondary_client) = near_rpc::create_near_connections(
+        &config.primary_rpc,
+        &config.secondary_rpc,
+        config.http_client.clone(),
+    )
+    .await;
     let clients = Arc::new((primary_client, secondary_client));
     info!("Connected to NEAR network");
 
     let db = database::connect_to_database().await?;
 
-    info!("Fetching and processing transactions...");
-    let new_transactions =
-        fetch_and_process_transactions(&config, &db, &clients.0, &clients.1).await?;
+    // Transactions are fetched per pool; the epoch boundaries are shared by all of them
+    let mut pools = Vec::with_capacity(config.validator_account_ids.len());
+    let mut transactions_saved = 0;
+    for validator_account_id in &config.validator_account_ids {
+        info!(
+            "Fetching and processing transactions for {}...",
+            validator_account_id
+        );
+        let pool_config = Arc::new(config.for_validator(validator_account_id));
+        let stored =
+            fetch_and_process_transactions(&pool_config, &db, &clients.0, &clients.1).await?;
+        metrics::record_transactions_processed(stored.transactions.len());
+        transactions_saved += stored.saved;
+        pools.push((pool_config, stored.transactions));
+    }
 
-    let start_block_height = new_transactions
+    // A pool without recent stake activity is normal; keep processing epochs from the latest
+    // stored one so their rewards and APY stay current
+    let new_start_block = pools
         .iter()
+        .flat_map(|(_, transactions)| transactions)
         .map(|tx| tx.block_height)
-        .min()
-        .unwrap_or_else(|| panic!("No transactions found"));
+        .min();
+    let latest_epoch = match new_start_block {
+        Some(_) => None,
+        None => epoch_sync_repository::get_latest_epoch_sync(&db).await?,
+    };
+    let Some(start_block_height) = start_block(
+        new_start_block,
+        latest_epoch.as_ref(),
+        config.fetch_from_block,
+    ) else {
+        warn!("No transactions and no stored epochs yet, nothing to process");
+        return Ok(Vec::new());
+    };
 
     info!("Starting from block height: {}", start_block_height);
 
-    let transactions: Arc<Vec<Transaction>> = Arc::new(new_transactions);
-
     info!("Getting epoch data...");
     let epoch_data = Arc::new(
         get_or_sync_epoch_data(
@@ -80,71 +267,205 @@ async fn run_indexer() -> Result<(), Box<dyn std::error::Error>> {
             &clients.1,
             config.batch_size,
             config.epoch_blocks,
+            config.rpc_inter_request_delay_ms,
+            config.parallel_epoch_sync.then_some(config.parallel_limit),
+            config.dry_run,
         )
         .await?,
     );
 
-    let validator_account_id = config.validator_account_id.clone();
-    let epoch_data_clone = Arc::clone(&epoch_data);
-    let config_clone = Arc::clone(&config);
-    let process_epoch_tasks = stream::iter(epoch_data_clone.iter().enumerate())
-        .map(move |(index, epoch)| {
-            let clients = Arc::clone(&clients);
-            let transactions = Arc::clone(&transactions);
-            let epoch_data = Arc::clone(&epoch_data);
-            let db = db.clone();
-            let validator_account_id = validator_account_id.clone();
-            let config = Arc::clone(&config_clone);
-            async move {
-                info!("Processing epoch {}: {:?}", index + 1, epoch);
-                let next_epoch = epoch_data.get(index + 1);
-                let end_block = next_epoch.map(|e| e.start_block - 1).unwrap_or(u64::MAX);
-
-                epoch_processor::process_delegator_data(
-                    &clients.0,
-                    &clients.1,
-                    &validator_account_id,
-                    epoch.start_block,
-                    end_block,
-                    &transactions,
-                    index as u64 + 1,
-                    &epoch.epoch_id,
-                    epoch.timestamp.timestamp_millis() as u64,
-                    &db,
-                    &config,
-                )
-                .await
+    let all_indices: Vec<usize> = (0..epoch_data.len()).collect();
+    let dry_run = config.dry_run;
+    let transactions_fetched: usize = pools
+        .iter()
+        .map(|(_, transactions)| transactions.len())
+        .sum();
+    let mut failed_epochs = Vec::new();
+    let mut epochs_attempted = 0;
+    for (pool_config, new_transactions) in pools {
+        info!("Processing epochs for {}", pool_config.validator_account_id);
+        let indices = match config.max_epochs_per_run {
+            Some(max_epochs) => {
+                let stored_end_blocks =
+                    epoch_repository::get_stored_end_blocks(&db, &pool_config.validator_account_id)
+                        .await?;
+                let (unprocessed, remaining) = oldest_epochs(
+                    epoch_processor::unprocessed_epoch_indices(&epoch_data, &stored_end_blocks),
+                    max_epochs,
+                );
+                info!(
+                    "Processing {} epochs for {} this run (MAX_EPOCHS_PER_RUN={}), {} remaining for later runs",
+                    unprocessed.len(),
+                    pool_config.validator_account_id,
+                    max_epochs,
+                    remaining
+                );
+                unprocessed
             }
-        })
-        .buffer_unordered(config.parallel_limit)
-        .collect::<Vec<_>>()
+            None => all_indices.clone(),
+        };
+        epochs_attempted += indices.len();
+        let transactions: Arc<Vec<Transaction>> = Arc::new(new_transactions);
+        let failed = epoch_processor::process_epochs(
+            Arc::clone(&pool_config),
+            db.clone(),
+            Arc::clone(&clients),
+            Arc::clone(&epoch_data),
+            indices.clone(),
+            transactions,
+        )
         .await;
+        if let Some(&first) = indices.iter().min().filter(|_| !dry_run) {
+            epoch_processor::recompute_cumulative_rewards(&db, &pool_config, first as u64 + 1)
+                .await?;
+        }
+        failed_epochs.extend(commands::failed_for_validator(
+            failed,
+            &pool_config.validator_account_id,
+        ));
+        commands::export_epochs(&db, &pool_config, &epoch_data, &indices).await?;
+    }
 
-    for result in process_epoch_tasks {
-        if let Err(e) = result {
-            error!("Error processing epoch: {:?}", e);
+    let gaps = epoch_sync_repository::find_gaps(&db).await?;
+    if !gaps.is_empty() {
+        let ranges: Vec<String> = gaps
+            .iter()
+            .map(|(start, end)| format!("{}-{}", start, end))
+            .collect();
+        warn!(
+            "Found {} gaps in the stored epochs, blocks {}",
+            gaps.len(),
+            ranges.join(", ")
+        );
+    }
+
+    let summary = RunSummary::new(
+        started_at,
+        config.validator_account_ids.clone(),
+        epochs_attempted,
+        failed_epochs.len(),
+        transactions_fetched,
+        transactions_saved,
+        start_block_height,
+        epoch_data.last().and_then(|epoch| epoch.end_block),
+        started.elapsed().as_secs_f64(),
+        dry_run,
+    );
+    info!("Run summary: {}", serde_json::to_string(&summary)?);
+    if config.save_run_summary && !dry_run {
+        run_summary_repository::save_run_summary(&db, &summary).await?;
+    }
+
+    if dry_run {
+        info!(
+            "Processing complete with {} failed epochs. Dry run, nothing was saved to MongoDB.",
+            failed_epochs.len()
+        );
+    } else {
+        info!(
+            "Processing complete with {} failed epochs. Data has been saved to MongoDB.",
+            failed_epochs.len()
+        );
+    }
+    Ok(failed_epochs)
+}
+
+/// Appends newly synced epochs to the stored ones. The resync starts at the latest stored
+/// epoch, so epoch ids that are already stored are skipped and the stored version is kept.
+/// The oldest `max_epochs` of the unprocessed epoch indices, and how many are left for later
+/// runs.
+fn oldest_epochs(mut unprocessed: Vec<usize>, max_epochs: usize) -> (Vec<usize>, usize) {
+    let remaining = unprocessed.len().saturating_sub(max_epochs);
+    unprocessed.truncate(max_epochs);
+    (unprocessed, remaining)
+}
+
+/// Block to process epochs from: the lowest new transaction, else the latest stored epoch's
+/// start, else `FETCH_FROM_BLOCK`.
+fn start_block(
+    new_start_block: Option<u64>,
+    latest_epoch: Option<&EpochInfo>,
+    fetch_from_block: Option<u64>,
+) -> Option<u64> {
+    if new_start_block.is_some() {
+        return new_start_block;
+    }
+    match (latest_epoch, fetch_from_block) {
+        (Some(epoch), _) => {
+            info!(
+                "No new transactions, continuing from the latest stored epoch {}",
+                epoch.epoch_id
+            );
+            Some(epoch.start_block)
+        }
+        (None, Some(fetch_from_block)) => {
+            info!("No new transactions, starting from FETCH_FROM_BLOCK");
+            Some(fetch_from_block)
         }
+        (None, None) => None,
     }
+}
 
-    info!("Processing complete. Data has been saved to MongoDB.");
-    Ok(())
+fn merge_epochs(stored: Vec<EpochInfo>, new_epochs: Vec<EpochInfo>) -> Vec<EpochInfo> {
+    let mut seen: HashSet<String> = stored.iter().map(|epoch| epoch.epoch_id.clone()).collect();
+    let mut merged = stored;
+    merged.extend(
+        new_epochs
+            .into_iter()
+            .filter(|epoch| seen.insert(epoch.epoch_id.clone())),
+    );
+    merged
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn get_or_sync_epoch_data(
     db: &Database,
     start_block_height: u64,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
     batch_size: usize,
     epoch_blocks: u64,
+    inter_request_delay_ms: Option<u64>,
+    parallel_segments: Option<usize>,
+    dry_run: bool,
 ) -> Result<Vec<EpochInfo>, Box<dyn std::error::Error>> {
     let latest_epoch_sync = epoch_sync_repository::get_latest_epoch_sync(db).await?;
-    let epoch_sync_count = epoch_sync_repository::get_epoch_sync_count(db).await?;
 
-    if let Some(latest) = latest_epoch_sync {
+    let epochs = if let Some(mut latest) = latest_epoch_sync {
+        if !epoch_processor::verify_latest_epoch(
+            db,
+            primary_client,
+            secondary_client,
+            epoch_blocks,
+            dry_run,
+        )
+        .await?
+        {
+            warn!(
+                "The latest stored epoch {} may have been reorged, resyncing its boundary",
+                latest.epoch_id
+            );
+            resync_latest_epoch(
+                db,
+                &latest,
+                primary_client,
+                secondary_client,
+                batch_size,
+                epoch_blocks,
+                inter_request_delay_ms,
+                parallel_segments,
+                dry_run,
+            )
+            .await?;
+            if let Some(resynced) = epoch_sync_repository::get_latest_epoch_sync(db).await? {
+                latest = resynced;
+            }
+        }
         let current_block =
             near_rpc::get_latest_block_height(primary_client, secondary_client).await?;
-        if current_block - latest.start_block > epoch_blocks {
+        // The tip is clamped by AS_OF_BLOCK and TIP_LAG_BLOCKS, so it can be below the latest
+        // stored epoch's start
+        if current_block.saturating_sub(latest.start_block) > epoch_blocks {
             // More than one epoch has passed, sync from the last known epoch
             let new_epochs = near_rpc::get_epoch_data(
                 latest.start_block,
@@ -152,30 +473,20 @@ async fn get_or_sync_epoch_data(
                 secondary_client,
                 batch_size,
                 epoch_blocks,
+                inter_request_delay_ms,
+                parallel_segments,
             )
             .await?;
 
-            for epoch in &new_epochs {
-                epoch_sync_repository::save_epoch_sync(db, epoch).await?;
+            if !dry_run {
+                epoch_sync_repository::save_epoch_syncs_bulk(db, &new_epochs).await?;
             }
 
-            let mut all_epochs = Vec::with_capacity(epoch_sync_count as usize + new_epochs.len());
-            for i in 0..epoch_sync_count {
-                if let Some(epoch) = epoch_sync_repository::get_epoch_sync_by_index(db, i).await? {
-                    all_epochs.push(epoch);
-                }
-            }
-            all_epochs.extend(new_epochs);
-            Ok(all_epochs)
+            let all_epochs = epoch_sync_repository::get_all_epochs(db).await?;
+            merge_epochs(all_epochs, new_epochs)
         } else {
             // Less than one epoch has passed, use existing data
-            let mut all_epochs = Vec::with_capacity(epoch_sync_count as usize);
-            for i in 0..epoch_sync_count {
-                if let Some(epoch) = epoch_sync_repository::get_epoch_sync_by_index(db, i).await? {
-                    all_epochs.push(epoch);
-                }
-            }
-            Ok(all_epochs)
+            epoch_sync_repository::get_all_epochs(db).await?
         }
     } else {
         // No existing data, sync from the start
@@ -185,13 +496,194 @@ async fn get_or_sync_epoch_data(
             secondary_client,
             batch_size,
             epoch_blocks,
+            inter_request_delay_ms,
+            parallel_segments,
         )
         .await?;
 
-        for epoch in &epochs {
-            epoch_sync_repository::save_epoch_sync(db, epoch).await?;
+        if !dry_run {
+            epoch_sync_repository::save_epoch_syncs_bulk(db, &epochs).await?;
+        }
+
+        epochs
+    };
+    epochs_as_of(epochs, near_rpc::as_of_block())
+}
+
+/// Searches the latest stored epoch's boundary again after it failed `verify_latest_epoch`,
+/// starting from the epoch before it, and replaces the stored epochs from there. The epoch
+/// before gets a new end block, so `epoch_processor::unprocessed_epoch_indices` no longer
+/// counts it or the resynced epochs as processed and they run again.
+#[allow(clippy::too_many_arguments)]
+async fn resync_latest_epoch(
+    db: &Database,
+    latest: &EpochInfo,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    batch_size: usize,
+    epoch_blocks: u64,
+    inter_request_delay_ms: Option<u64>,
+    parallel_segments: Option<usize>,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let previous = epoch_sync_repository::get_previous_epoch(db, latest.start_block).await?;
+    // Without an epoch before it, the first synced epoch starts at an arbitrary block and
+    // is dropped
+    let from_block = previous.as_ref().map_or_else(
+        || latest.start_block.saturating_sub(epoch_blocks),
+        |previous| previous.start_block,
+    );
+    let epochs: Vec<EpochInfo> = near_rpc::get_epoch_data(
+        from_block,
+        primary_client,
+        secondary_client,
+        batch_size,
+        epoch_blocks,
+        inter_request_delay_ms,
+        parallel_segments,
+    )
+    .await?
+    .into_iter()
+    .filter(|epoch| epoch.start_block > from_block || previous.is_some())
+    .collect();
+
+    let resynced = epochs
+        .iter()
+        .find(|epoch| epoch.start_block > from_block)
+        .map(|epoch| (epoch.epoch_id.as_str(), epoch.start_block));
+    warn!(
+        "Epoch {} stored at block {} resynced as {:?}",
+        latest.epoch_id, latest.start_block, resynced
+    );
+    if dry_run {
+        return Ok(());
+    }
+    epoch_sync_repository::delete_epochs_from(db, latest.start_block).await?;
+    epoch_sync_repository::save_epoch_syncs_bulk(db, &epochs).await?;
+    Ok(())
+}
+
+/// Drops the epochs that start after AS_OF_BLOCK, whose account state can't be read. Fails if
+/// AS_OF_BLOCK predates every epoch.
+fn epochs_as_of(
+    mut epochs: Vec<EpochInfo>,
+    as_of_block: Option<u64>,
+) -> Result<Vec<EpochInfo>, Box<dyn std::error::Error>> {
+    let Some(as_of_block) = as_of_block else {
+        return Ok(epochs);
+    };
+    let count = epochs.len();
+    epochs.retain(|epoch| epoch.start_block <= as_of_block);
+    if epochs.is_empty() && count > 0 {
+        return Err(format!(
+            "AS_OF_BLOCK {} predates the first stored epoch, nothing to process",
+            as_of_block
+        )
+        .into());
+    }
+    if epochs.len() < count {
+        info!(
+            "Ignoring {} epochs that start after AS_OF_BLOCK {}",
+            count - epochs.len(),
+            as_of_block
+        );
+    }
+    Ok(epochs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn epoch(epoch_id: &str, start_block: u64) -> EpochInfo {
+        EpochInfo {
+            start_block,
+            end_block: None,
+            epoch_id: epoch_id.to_string(),
+            timestamp: Utc::now(),
+            block_hash: None,
+            finalized: false,
+            boundary_verified: true,
         }
+    }
+
+    #[test]
+    fn failed_run_stops_the_scheduler_only_with_fail_on_epoch_error() {
+        let failed_run = || Err("RPC unavailable".into());
+        let error = settle_run(failed_run(), true).unwrap_err();
+        assert_eq!(error.to_string(), "RPC unavailable");
+        assert!(settle_run(failed_run(), false).is_ok());
+
+        assert!(settle_run(Ok(vec!["epoch-1".to_string()]), true).is_err());
+        assert!(settle_run(Ok(vec!["epoch-1".to_string()]), false).is_ok());
+        assert!(settle_run(Ok(Vec::new()), true).is_ok());
+    }
+
+    #[test]
+    fn overlapping_resync_keeps_the_stored_epochs() {
+        // The resync starts at the latest stored epoch, so "b" comes back with other bounds
+        let stored = vec![epoch("a", 100), epoch("b", 200)];
+        let new_epochs = vec![epoch("b", 201), epoch("c", 300)];
+        let merged = merge_epochs(stored, new_epochs);
+        let epochs: Vec<_> = merged
+            .iter()
+            .map(|epoch| (epoch.epoch_id.as_str(), epoch.start_block))
+            .collect();
+        assert_eq!(epochs, [("a", 100), ("b", 200), ("c", 300)]);
+    }
+
+    #[test]
+    fn epochs_as_of_drops_epochs_after_the_as_of_block() {
+        let epochs = vec![epoch("a", 100), epoch("b", 200), epoch("c", 300)];
+        let kept = epochs_as_of(epochs, Some(250)).unwrap();
+        let ids: Vec<_> = kept.iter().map(|epoch| epoch.epoch_id.as_str()).collect();
+        assert_eq!(ids, ["a", "b"]);
+    }
+
+    #[test]
+    fn epochs_as_of_rejects_a_block_before_every_epoch() {
+        let epochs = vec![epoch("a", 100), epoch("b", 200)];
+        assert!(epochs_as_of(epochs, Some(50)).is_err());
+    }
+
+    #[test]
+    fn epochs_as_of_keeps_everything_without_an_as_of_block() {
+        let epochs = vec![epoch("a", 100), epoch("b", 200)];
+        assert_eq!(epochs_as_of(epochs, None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn no_new_transactions_continue_from_the_latest_stored_epoch() {
+        let latest = epoch("epoch-2", 2_000);
+        assert_eq!(
+            start_block(Some(2_500), Some(&latest), Some(100)),
+            Some(2_500)
+        );
+        assert_eq!(start_block(None, Some(&latest), Some(100)), Some(2_000));
+        assert_eq!(start_block(None, None, Some(100)), Some(100));
+        assert_eq!(start_block(None, None, None), None);
+    }
+
+    #[test]
+    fn only_the_oldest_unprocessed_epochs_run_under_the_limit() {
+        let epochs = [
+            epoch("epoch-1", 100),
+            epoch("epoch-2", 200),
+            epoch("epoch-3", 300),
+            epoch("epoch-4", 400),
+            epoch("epoch-5", 500),
+        ];
+        let stored_end_blocks = HashMap::from([("epoch-1".to_string(), 199)]);
+        let unprocessed = epoch_processor::unprocessed_epoch_indices(&epochs, &stored_end_blocks);
+        assert_eq!(unprocessed, [1, 2, 3, 4]);
+
+        let (indices, remaining) = oldest_epochs(unprocessed.clone(), 2);
+        assert_eq!(indices, [1, 2]);
+        assert_eq!(remaining, 2);
 
-        Ok(epochs)
+        let (indices, remaining) = oldest_epochs(unprocessed, 10);
+        assert_eq!(indices, [1, 2, 3, 4]);
+        assert_eq!(remaining, 0);
     }
 }
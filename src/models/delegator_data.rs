@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DelegatorData {
@@ -13,5 +13,127 @@ pub struct DelegatorData {
     pub last_update_block: u64,
     pub epoch_id: String,
     pub rewards: String,
-    pub apy: String, // New field for APY
+    /// Null while the still-running epoch has no APY yet, see `PARTIAL_EPOCH_APY`
+    #[serde(default, deserialize_with = "deserialize_optional_apy")]
+    pub apy: Option<f64>,
+    /// Lifetime rewards up to and including this epoch: the cumulative value of the
+    /// delegator's latest earlier record plus `rewards`, or just `rewards` without one. Empty
+    /// on records stored before it was tracked, which counts as zero.
+    #[serde(default)]
+    pub cumulative_rewards: String,
+    #[serde(default)]
+    pub stake_at_start: Option<String>,
+    #[serde(default)]
+    pub stake_at_end: Option<String>,
+    /// Set when `rewards` is negative and was stored as is under `REWARD_NEGATIVE_POLICY=record`
+    #[serde(default)]
+    pub negative_rewards: bool,
+    /// Owner of the lockup contract `delegator_id`, under `RESOLVE_LOCKUP_OWNERS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beneficiary_id: Option<String>,
+    /// Total staked and unstaked within the epoch, before netting. Both non-zero means the
+    /// delegator churned, even when the net change is zero. Empty on records stored before
+    /// they were tracked.
+    #[serde(default)]
+    pub gross_staked: String,
+    #[serde(default)]
+    pub gross_unstaked: String,
+}
+
+/// APY is null for the still-running epoch before it can be annualized, and for epochs the
+/// validator was kicked out of.
+pub(super) fn deserialize_optional_apy<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Apy(#[serde(deserialize_with = "deserialize_apy")] f64);
+
+    Ok(Option::<Apy>::deserialize(deserializer)?.map(|Apy(apy)| apy))
+}
+
+/// Records written before APY was stored as a number hold it as a string.
+fn deserialize_apy<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Apy {
+        Number(f64),
+        Text(String),
+    }
+
+    match Apy::deserialize(deserializer)? {
+        Apy::Number(apy) => Ok(apy),
+        Apy::Text(apy) => apy.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::{doc, from_document, to_document, Bson};
+
+    fn record(apy: Option<f64>) -> DelegatorData {
+        DelegatorData {
+            delegator_id: "alice.near".to_string(),
+            validator_account_id: "pool.poolv1.near".to_string(),
+            epoch: 2_000,
+            start_block_height: 86_400_000,
+            end_block_height: 86_443_199,
+            timestamp: 0,
+            initial_stake: "100".to_string(),
+            auto_compounded_stake: "101".to_string(),
+            last_update_block: 0,
+            epoch_id: "epoch-2000".to_string(),
+            rewards: "1".to_string(),
+            apy,
+            cumulative_rewards: "1".to_string(),
+            stake_at_start: None,
+            stake_at_end: None,
+            negative_rewards: false,
+            beneficiary_id: None,
+            gross_staked: String::new(),
+            gross_unstaked: String::new(),
+        }
+    }
+
+    #[test]
+    fn apy_is_stored_as_a_double() {
+        let document = to_document(&record(Some(9.75))).unwrap();
+        assert_eq!(document.get("apy"), Some(&Bson::Double(9.75)));
+
+        let document = to_document(&record(None)).unwrap();
+        assert_eq!(document.get("apy"), Some(&Bson::Null));
+    }
+
+    #[test]
+    fn apy_stored_as_a_string_is_still_read() {
+        let mut document = to_document(&record(None)).unwrap();
+        document.insert("apy", "9.75");
+        let record: DelegatorData = from_document(document).unwrap();
+        assert_eq!(record.apy, Some(9.75));
+
+        let mut document = to_document(&record).unwrap();
+        document.extend(doc! { "apy": Bson::Null });
+        let record: DelegatorData = from_document(document).unwrap();
+        assert_eq!(record.apy, None);
+    }
+
+    #[test]
+    fn end_block_height_is_stored_as_a_positive_integer() {
+        // The open epoch is bounded by the settled chain head rather than a u64::MAX sentinel
+        let document = to_document(&record(None)).unwrap();
+        assert!(matches!(
+            document.get("end_block_height"),
+            Some(&Bson::Int64(end_block_height)) if end_block_height > 0
+        ));
+
+        let sentinel = DelegatorData {
+            end_block_height: u64::MAX,
+            ..record(None)
+        };
+        assert!(to_document(&sentinel).is_err());
+    }
 }
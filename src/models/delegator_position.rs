@@ -0,0 +1,124 @@
+use crate::models::DelegatorData;
+use num_bigint::BigInt;
+use num_traits::Zero;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// A delegator's latest position in a single staking pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolPosition {
+    pub validator_account_id: String,
+    pub epoch: u64,
+    pub epoch_id: String,
+    pub stake: String,
+    pub rewards: String,
+    pub cumulative_rewards: String,
+}
+
+/// A delegator's position summed across the pools it delegates to.
+#[derive(Debug, Clone, Serialize)]
+pub struct DelegatorPosition {
+    pub delegator_id: String,
+    pub total_stake: String,
+    pub total_rewards: String,
+    pub total_cumulative_rewards: String,
+    pub pools: Vec<PoolPosition>,
+}
+
+impl DelegatorPosition {
+    /// Sums the latest record of each pool. Pools may have been indexed up to different
+    /// epochs, so each pool contributes its own most recent epoch.
+    pub fn aggregate(delegator_id: &str, latest_records: &[DelegatorData]) -> Self {
+        let mut total_stake = BigInt::zero();
+        let mut total_rewards = BigInt::zero();
+        let mut total_cumulative_rewards = BigInt::zero();
+        let mut pools = Vec::with_capacity(latest_records.len());
+
+        for record in latest_records {
+            total_stake += parse_amount(&record.auto_compounded_stake);
+            total_rewards += parse_amount(&record.rewards);
+            total_cumulative_rewards += parse_amount(&record.cumulative_rewards);
+            pools.push(PoolPosition {
+                validator_account_id: record.validator_account_id.clone(),
+                epoch: record.epoch,
+                epoch_id: record.epoch_id.clone(),
+                stake: record.auto_compounded_stake.clone(),
+                rewards: record.rewards.clone(),
+                cumulative_rewards: record.cumulative_rewards.clone(),
+            });
+        }
+
+        DelegatorPosition {
+            delegator_id: delegator_id.to_string(),
+            total_stake: total_stake.to_string(),
+            total_rewards: total_rewards.to_string(),
+            total_cumulative_rewards: total_cumulative_rewards.to_string(),
+            pools,
+        }
+    }
+}
+
+fn parse_amount(amount: &str) -> BigInt {
+    BigInt::from_str(amount).unwrap_or_else(|_| BigInt::zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(validator_account_id: &str, epoch: u64, stake: &str, rewards: &str) -> DelegatorData {
+        DelegatorData {
+            delegator_id: "alice.near".to_string(),
+            validator_account_id: validator_account_id.to_string(),
+            epoch,
+            start_block_height: epoch * 43_200,
+            end_block_height: (epoch + 1) * 43_200 - 1,
+            timestamp: 0,
+            initial_stake: stake.to_string(),
+            auto_compounded_stake: stake.to_string(),
+            last_update_block: 0,
+            epoch_id: format!("epoch-{}", epoch),
+            rewards: rewards.to_string(),
+            apy: None,
+            cumulative_rewards: format!("{}0", rewards),
+            stake_at_start: None,
+            stake_at_end: None,
+            negative_rewards: false,
+            beneficiary_id: None,
+            gross_staked: String::new(),
+            gross_unstaked: String::new(),
+        }
+    }
+
+    #[test]
+    fn pools_indexed_to_different_epochs_are_summed() {
+        // The second pool was last indexed two epochs earlier than the first
+        let records = [
+            record("a.poolv1.near", 2_000, "1000", "5"),
+            record("b.poolv1.near", 1_998, "500", "2"),
+        ];
+        let position = DelegatorPosition::aggregate("alice.near", &records);
+
+        assert_eq!(position.total_stake, "1500");
+        assert_eq!(position.total_rewards, "7");
+        assert_eq!(position.total_cumulative_rewards, "70");
+        let pools: Vec<_> = position
+            .pools
+            .iter()
+            .map(|pool| {
+                (
+                    pool.validator_account_id.as_str(),
+                    pool.epoch,
+                    pool.stake.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            pools,
+            [
+                ("a.poolv1.near", 2_000, "1000"),
+                ("b.poolv1.near", 1_998, "500")
+            ]
+        );
+    }
+}
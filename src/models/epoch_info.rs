@@ -8,4 +8,21 @@ pub struct EpochInfo {
     pub epoch_id: String,
     #[serde(with = "chrono::serde::ts_milliseconds")]
     pub timestamp: DateTime<Utc>,
+    /// Hash of `start_block`, to detect a reorg replacing it. Missing on epochs synced before
+    /// hashes were stored.
+    #[serde(default)]
+    pub block_hash: Option<String>,
+    /// Set once the epoch ended more than an epoch's worth of blocks before the tip, after
+    /// which its boundary is no longer re-verified.
+    #[serde(default)]
+    pub finalized: bool,
+    /// False when the boundary search found no epoch change in its window and `end_block` is
+    /// only an estimate, see `verify-boundaries`. Missing on epochs synced before the flag
+    /// was stored, which count as verified.
+    #[serde(default = "verified_by_default")]
+    pub boundary_verified: bool,
+}
+
+fn verified_by_default() -> bool {
+    true
 }
@@ -1,7 +1,17 @@
 mod delegator_data;
+mod delegator_position;
 mod epoch_info;
+mod nearblocks_txn;
+mod reward_event;
+mod run_summary;
 mod transaction;
+mod validator_metrics;
 
 pub use delegator_data::DelegatorData;
+pub use delegator_position::DelegatorPosition;
 pub use epoch_info::EpochInfo;
+pub use nearblocks_txn::{Action, ActionsAgg, Block, NearblocksTxn};
+pub use reward_event::RewardEvent;
+pub use run_summary::RunSummary;
 pub use transaction::Transaction;
+pub use validator_metrics::ValidatorMetrics;
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+
+/// An entry of the nearblocks `stake-txns` response. Transactions found over RPC are converted
+/// to the same shape. Fields nearblocks may leave out are optional; fields not modelled here
+/// are kept in `extra`, so the raw transaction can still be stored as received.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NearblocksTxn {
+    pub transaction_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<Block>,
+    /// Older responses had the height at the top level only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u64>,
+    /// Nanoseconds, as a string or a JSON number.
+    #[serde(
+        default,
+        deserialize_with = "string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub block_timestamp: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub predecessor_account_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receiver_account_id: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actions_agg: Option<ActionsAgg>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Action {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionsAgg {
+    /// Total attached deposit in yoctoNEAR, as a string or a JSON number.
+    #[serde(
+        default,
+        deserialize_with = "string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub deposit: Option<String>,
+}
+
+impl NearblocksTxn {
+    /// Height of the transaction's block, preferring the nested `block.block_height`. A zero
+    /// height counts as missing.
+    pub fn block_height(&self) -> Option<u64> {
+        self.block
+            .as_ref()
+            .and_then(|block| block.block_height)
+            .or(self.block_height)
+            .filter(|&block_height| block_height > 0)
+    }
+
+    /// Block timestamp in nanoseconds, if present and a whole number.
+    pub fn timestamp_nanos(&self) -> Option<u64> {
+        self.block_timestamp.as_deref()?.trim().parse().ok()
+    }
+
+    pub fn deposit(&self) -> Option<&str> {
+        self.actions_agg.as_ref()?.deposit.as_deref()
+    }
+
+    /// Method of the first function call action.
+    pub fn method(&self) -> Option<&str> {
+        self.actions.first()?.method.as_deref()
+    }
+}
+
+/// Reads a JSON string or number as text, and anything else as missing.
+fn string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::String(text)) => Some(text),
+        Some(Value::Number(number)) => Some(number.to_string()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn numbers_and_strings_are_both_accepted() {
+        let txn: NearblocksTxn = serde_json::from_value(json!({
+            "transaction_hash": "abc",
+            "block": { "block_height": 120 },
+            "block_timestamp": 1717000000000000000u64,
+            "actions": [{ "action": "FUNCTION_CALL", "method": "deposit_and_stake" }],
+            "actions_agg": { "deposit": "1000" },
+            "outcomes": { "status": true },
+        }))
+        .unwrap();
+        assert_eq!(txn.block_height(), Some(120));
+        assert_eq!(txn.timestamp_nanos(), Some(1717000000000000000));
+        assert_eq!(txn.deposit(), Some("1000"));
+        assert_eq!(txn.method(), Some("deposit_and_stake"));
+        // Unmodelled fields are kept for the raw transaction
+        assert_eq!(txn.extra["outcomes"], json!({ "status": true }));
+    }
+
+    #[test]
+    fn missing_fields_default() {
+        let txn: NearblocksTxn = serde_json::from_value(json!({
+            "transaction_hash": "abc",
+            "block_height": 0,
+            "block_timestamp": null,
+            "actions_agg": { "deposit": { "unexpected": true } },
+        }))
+        .unwrap();
+        assert_eq!(txn.block_height(), None);
+        assert_eq!(txn.timestamp_nanos(), None);
+        assert_eq!(txn.deposit(), None);
+        assert_eq!(txn.method(), None);
+    }
+
+    #[test]
+    fn top_level_height_is_the_fallback() {
+        let txn: NearblocksTxn = serde_json::from_value(json!({
+            "transaction_hash": "abc",
+            "block_height": 99,
+        }))
+        .unwrap();
+        assert_eq!(txn.block_height(), Some(99));
+    }
+}
@@ -0,0 +1,18 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// One delegator reward as computed by `process_delegator_data`, with the inputs it was derived
+/// from. Appended to `reward_events` and never updated, unlike the `delegators` snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewardEvent {
+    pub delegator_id: String,
+    pub validator_account_id: String,
+    pub epoch: u64,
+    pub epoch_id: String,
+    pub current_stake: String,
+    pub previous_stake: Option<String>,
+    pub tx_total: String,
+    /// Rewards before `REWARD_NEGATIVE_POLICY` is applied, so they can be negative.
+    pub rewards: String,
+    pub recorded_at: DateTime<Utc>,
+}
@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Outcome of one indexer run, logged as JSON and optionally stored in `run_summaries`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummary {
+    pub started_at: DateTime<Utc>,
+    pub validators: Vec<String>,
+    pub epochs_processed: u64,
+    pub epochs_failed: u64,
+    /// New staking transactions found across all pools.
+    pub transactions_fetched: u64,
+    /// Of those, the ones written to MongoDB; zero on a dry run.
+    pub transactions_saved: u64,
+    pub start_block: u64,
+    pub end_block: Option<u64>,
+    pub duration_secs: f64,
+    pub dry_run: bool,
+}
+
+impl RunSummary {
+    /// Summary of a run that attempted `epochs_attempted` epochs, `epochs_failed` of which
+    /// failed, over the blocks from `start_block` to `end_block`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        started_at: DateTime<Utc>,
+        validators: Vec<String>,
+        epochs_attempted: usize,
+        epochs_failed: usize,
+        transactions_fetched: usize,
+        transactions_saved: usize,
+        start_block: u64,
+        end_block: Option<u64>,
+        duration_secs: f64,
+        dry_run: bool,
+    ) -> Self {
+        RunSummary {
+            started_at,
+            validators,
+            epochs_processed: epochs_attempted.saturating_sub(epochs_failed) as u64,
+            epochs_failed: epochs_failed as u64,
+            transactions_fetched: transactions_fetched as u64,
+            transactions_saved: if dry_run {
+                0
+            } else {
+                transactions_saved as u64
+            },
+            start_block,
+            end_block,
+            duration_secs,
+            dry_run,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(dry_run: bool) -> RunSummary {
+        RunSummary::new(
+            Utc::now(),
+            vec!["a.pool.near".to_string(), "b.pool.near".to_string()],
+            10,
+            3,
+            25,
+            20,
+            1_000,
+            Some(2_000),
+            12.5,
+            dry_run,
+        )
+    }
+
+    #[test]
+    fn summary_counts_come_from_the_run() {
+        let summary = summary(false);
+        assert_eq!(summary.validators.len(), 2);
+        assert_eq!(summary.epochs_processed, 7);
+        assert_eq!(summary.epochs_failed, 3);
+        assert_eq!(summary.transactions_fetched, 25);
+        assert_eq!(summary.transactions_saved, 20);
+        assert_eq!(summary.start_block, 1_000);
+        assert_eq!(summary.end_block, Some(2_000));
+    }
+
+    #[test]
+    fn dry_run_saves_nothing() {
+        let summary = summary(true);
+        assert_eq!(summary.transactions_fetched, 25);
+        assert_eq!(summary.transactions_saved, 0);
+    }
+
+    #[test]
+    fn summary_serializes_in_camel_case() {
+        let json = serde_json::to_value(summary(false)).unwrap();
+        assert_eq!(json["epochsProcessed"], 7);
+        assert_eq!(json["transactionsSaved"], 20);
+    }
+}
@@ -11,4 +11,47 @@ pub struct Transaction {
     pub block_height: u64,
     pub timestamp: DateTime<Utc>,
     pub delegator_address: String,
+    /// Empty for transactions stored before several pools could be indexed.
+    #[serde(default)]
+    pub validator_account_id: String,
+    /// Rewards from `dist.stak` events in the transaction, not part of `amount`. Missing when
+    /// there were none, and on transactions stored before rewards were split out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reward_amount: Option<String>,
+    #[serde(
+        rename = "explorerUrl",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub explorer_url: Option<String>,
+}
+
+impl Transaction {
+    /// Builds the explorer link for a transaction hash, e.g. `https://nearblocks.io/txns/<hash>`.
+    pub fn explorer_url_for(base_url: &str, transaction_hash: &str) -> String {
+        format!("{}/{}", base_url.trim_end_matches('/'), transaction_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "6pWZ5ZvkDFBBbFyEhs8oDDWLSUwuRwSqA5yLEAMXWeWH";
+
+    #[test]
+    fn explorer_url_joins_base_and_hash() {
+        assert_eq!(
+            Transaction::explorer_url_for("https://nearblocks.io/txns", HASH),
+            format!("https://nearblocks.io/txns/{}", HASH)
+        );
+    }
+
+    #[test]
+    fn trailing_slashes_on_the_base_are_dropped() {
+        assert_eq!(
+            Transaction::explorer_url_for("https://testnet.nearblocks.io/txns//", HASH),
+            format!("https://testnet.nearblocks.io/txns/{}", HASH)
+        );
+    }
 }
@@ -0,0 +1,20 @@
+use super::delegator_data::deserialize_optional_apy;
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidatorMetrics {
+    pub validator_account_id: String,
+    pub epoch: u64,
+    pub epoch_id: String,
+    pub total_staked: String,
+    pub total_delegators: i64,
+    pub timestamp: DateTime,
+    #[serde(deserialize_with = "deserialize_optional_apy")]
+    pub apy: Option<f64>,
+    #[serde(default)]
+    pub apy_provisional: bool,
+    #[serde(default)]
+    pub kicked_out: bool,
+}
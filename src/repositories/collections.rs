@@ -0,0 +1,126 @@
+use log::info;
+use mongodb::{Collection, Database};
+use std::sync::OnceLock;
+
+/// Prepended to every collection name, see `set_prefix`.
+static PREFIX: OnceLock<String> = OnceLock::new();
+
+/// Names collections `<prefix>_<name>`, e.g. `mainnet_transactions`, so several deployments
+/// can share one database. Without a prefix the plain names are used.
+pub fn set_prefix(prefix: Option<&str>) {
+    let Some(prefix) = prefix.filter(|prefix| !prefix.is_empty()) else {
+        return;
+    };
+    info!(
+        "Using MongoDB collections prefixed with {}",
+        prefixed(Some(prefix), "")
+    );
+    let _ = PREFIX.set(prefix.to_string());
+}
+
+/// The collection name for `base` with the configured prefix.
+pub fn name(base: &str) -> String {
+    prefixed(PREFIX.get().map(String::as_str), base)
+}
+
+/// `base` as named under `prefix`. A trailing underscore on the prefix is optional.
+fn prefixed(prefix: Option<&str>, base: &str) -> String {
+    match prefix.filter(|prefix| !prefix.is_empty()) {
+        Some(prefix) => format!("{}_{}", prefix.trim_end_matches('_'), base),
+        None => base.to_string(),
+    }
+}
+
+fn collection<T: Send + Sync>(db: &Database, base: &str) -> Collection<T> {
+    db.collection(&name(base))
+}
+
+pub fn transactions<T: Send + Sync>(db: &Database) -> Collection<T> {
+    collection(db, "transactions")
+}
+
+pub fn delegators<T: Send + Sync>(db: &Database) -> Collection<T> {
+    collection(db, "delegators")
+}
+
+pub fn epoch_data<T: Send + Sync>(db: &Database) -> Collection<T> {
+    collection(db, "epoch_data")
+}
+
+pub fn epoch_sync<T: Send + Sync>(db: &Database) -> Collection<T> {
+    collection(db, "epoch_sync")
+}
+
+pub fn validator_metrics<T: Send + Sync>(db: &Database) -> Collection<T> {
+    collection(db, "validator_metrics")
+}
+
+pub fn raw_transactions<T: Send + Sync>(db: &Database) -> Collection<T> {
+    collection(db, "raw_transactions")
+}
+
+pub fn reward_events<T: Send + Sync>(db: &Database) -> Collection<T> {
+    collection(db, "reward_events")
+}
+
+pub fn run_summaries<T: Send + Sync>(db: &Database) -> Collection<T> {
+    collection(db, "run_summaries")
+}
+
+pub fn scan_cursors<T: Send + Sync>(db: &Database) -> Collection<T> {
+    collection(db, "scan_cursors")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::Document;
+    use mongodb::options::{ClientOptions, ServerAddress};
+    use mongodb::Client;
+
+    type Accessor = fn(&Database) -> Collection<Document>;
+
+    /// Every accessor with the base name it resolves.
+    const ACCESSORS: [(Accessor, &str); 9] = [
+        (transactions, "transactions"),
+        (delegators, "delegators"),
+        (epoch_data, "epoch_data"),
+        (epoch_sync, "epoch_sync"),
+        (validator_metrics, "validator_metrics"),
+        (raw_transactions, "raw_transactions"),
+        (reward_events, "reward_events"),
+        (run_summaries, "run_summaries"),
+        (scan_cursors, "scan_cursors"),
+    ];
+
+    #[test]
+    fn prefix_is_joined_with_one_underscore() {
+        assert_eq!(
+            prefixed(Some("mainnet"), "transactions"),
+            "mainnet_transactions"
+        );
+        assert_eq!(
+            prefixed(Some("mainnet_"), "transactions"),
+            "mainnet_transactions"
+        );
+        assert_eq!(prefixed(Some(""), "transactions"), "transactions");
+        assert_eq!(prefixed(None, "transactions"), "transactions");
+    }
+
+    #[tokio::test]
+    async fn every_accessor_resolves_its_name_through_the_prefix() {
+        // Never connected; naming a collection doesn't need a server
+        let options = ClientOptions::builder()
+            .hosts(vec![ServerAddress::Tcp {
+                host: "localhost".to_string(),
+                port: Some(27017),
+            }])
+            .build();
+        let db = Client::with_options(options).unwrap().database("indexer");
+
+        for (accessor, base) in ACCESSORS {
+            assert_eq!(accessor(&db).name(), name(base));
+            assert_eq!(prefixed(Some("mainnet"), base), format!("mainnet_{}", base));
+        }
+    }
+}
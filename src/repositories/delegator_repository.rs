@@ -1,32 +1,307 @@
 use crate::models::DelegatorData;
-use mongodb::bson::{doc, to_bson, Bson};
-use mongodb::options::UpdateOptions;
-use mongodb::{Collection, Database};
+use crate::repositories::collections;
+use futures::TryStreamExt;
+use log::info;
+use mongodb::bson::{doc, from_document, to_bson, Bson};
+use mongodb::options::{UpdateOneModel, WriteModel};
+use mongodb::{ClientSession, Collection, Database};
+use std::collections::HashMap;
 
+/// Upserts the delegator records with one `bulk_write` round-trip per `batch_size` chunk.
 pub async fn save_delegator_data(
     db: &Database,
     delegator_data: &[DelegatorData],
     batch_size: usize,
+    mut session: Option<&mut ClientSession>,
 ) -> Result<(), mongodb::error::Error> {
-    let collection: Collection<DelegatorData> = db.collection("delegators");
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    let namespace = collection.namespace();
+    let mut round_trips = 0;
+    let mut upserted = 0;
+    let mut modified = 0;
 
     for chunk in delegator_data.chunks(batch_size) {
+        let mut models = Vec::with_capacity(chunk.len());
         for data in chunk {
             let filter = doc! {
-                "delegatorId": &data.delegator_id,
-                "validatorAccountId": &data.validator_account_id,
+                "delegator_id": &data.delegator_id,
+                "validator_account_id": &data.validator_account_id,
                 "epoch": Bson::Int64(data.epoch as i64),
             };
             let update = doc! {
                 "$set": to_bson(data)?
             };
-            let options = UpdateOptions::builder().upsert(Some(true)).build();
-            collection
-                .update_one(filter, update)
-                .upsert(options.upsert.unwrap_or(false))
-                .await?;
+            models.push(WriteModel::UpdateOne(
+                UpdateOneModel::builder()
+                    .namespace(namespace.clone())
+                    .filter(filter)
+                    .update(update)
+                    .upsert(true)
+                    .build(),
+            ));
         }
+
+        let bulk_write = db.client().bulk_write(models);
+        let result = match session.as_deref_mut() {
+            Some(session) => bulk_write.session(session).await?,
+            None => bulk_write.await?,
+        };
+        round_trips += 1;
+        upserted += result.upserted_count;
+        modified += result.modified_count;
     }
 
+    info!(
+        "Saved {} delegator records in {} bulk writes ({} inserted, {} updated)",
+        delegator_data.len(),
+        round_trips,
+        upserted,
+        modified
+    );
+
     Ok(())
 }
+
+/// Returns each delegator's most recent record from an epoch before `epoch`, keyed by delegator id.
+pub async fn get_latest_before_epoch(
+    db: &Database,
+    validator_account_id: &str,
+    epoch: u64,
+) -> Result<HashMap<String, DelegatorData>, mongodb::error::Error> {
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    let pipeline = vec![
+        doc! { "$match": {
+            "validator_account_id": validator_account_id,
+            "epoch": { "$lt": epoch as i64 },
+        } },
+        doc! { "$sort": { "epoch": -1 } },
+        doc! { "$group": { "_id": "$delegator_id", "record": { "$first": "$$ROOT" } } },
+        doc! { "$replaceRoot": { "newRoot": "$record" } },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline).await?;
+    let mut records = HashMap::new();
+    while let Some(document) = cursor.try_next().await? {
+        let record: DelegatorData = from_document(document)?;
+        records.insert(record.delegator_id.clone(), record);
+    }
+    Ok(records)
+}
+
+/// Returns the delegator's most recent record in the pool from an epoch before `epoch`.
+pub async fn get_previous_epoch_record(
+    db: &Database,
+    delegator_id: &str,
+    validator_account_id: &str,
+    epoch: u64,
+) -> Result<Option<DelegatorData>, mongodb::error::Error> {
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    collection
+        .find_one(doc! {
+            "delegator_id": delegator_id,
+            "validator_account_id": validator_account_id,
+            "epoch": { "$lt": epoch as i64 },
+        })
+        .sort(doc! { "epoch": -1 })
+        .await
+}
+
+/// Returns the delegator's most recent record in each pool, or only in `validator_account_id`
+/// when given. Pools can be at different epochs.
+pub async fn get_latest_records_by_pool(
+    db: &Database,
+    delegator_id: &str,
+    validator_account_id: Option<&str>,
+) -> Result<Vec<DelegatorData>, mongodb::error::Error> {
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    let mut filter = doc! { "delegator_id": delegator_id };
+    if let Some(validator_account_id) = validator_account_id {
+        filter.insert("validator_account_id", validator_account_id);
+    }
+    let pipeline = vec![
+        doc! { "$match": filter },
+        doc! { "$sort": { "epoch": -1 } },
+        doc! { "$group": { "_id": "$validator_account_id", "record": { "$first": "$$ROOT" } } },
+        doc! { "$replaceRoot": { "newRoot": "$record" } },
+        doc! { "$sort": { "validator_account_id": 1 } },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline).await?;
+    let mut records = Vec::new();
+    while let Some(document) = cursor.try_next().await? {
+        records.push(from_document(document)?);
+    }
+    Ok(records)
+}
+
+/// Returns every stored record of a delegator in one pool, oldest epoch first.
+pub async fn get_delegator_history(
+    db: &Database,
+    delegator_id: &str,
+    validator_account_id: &str,
+) -> Result<Vec<DelegatorData>, mongodb::error::Error> {
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    let cursor = collection
+        .find(doc! {
+            "delegator_id": delegator_id,
+            "validator_account_id": validator_account_id,
+        })
+        .sort(doc! { "epoch": 1 })
+        .await?;
+    cursor.try_collect().await
+}
+
+/// Returns the records of every delegator in a pool for one epoch.
+pub async fn get_epoch_delegators(
+    db: &Database,
+    validator_account_id: &str,
+    epoch: u64,
+) -> Result<Vec<DelegatorData>, mongodb::error::Error> {
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    let cursor = collection
+        .find(doc! {
+            "validator_account_id": validator_account_id,
+            "epoch": epoch as i64,
+        })
+        .sort(doc! { "delegator_id": 1 })
+        .await?;
+    cursor.try_collect().await
+}
+
+/// Returns `limit` of a pool's delegator records for one epoch after skipping `skip`, ordered
+/// by delegator id.
+pub async fn get_epoch_delegators_page(
+    db: &Database,
+    validator_account_id: &str,
+    epoch: u64,
+    skip: u64,
+    limit: i64,
+) -> Result<Vec<DelegatorData>, mongodb::error::Error> {
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    let cursor = collection
+        .find(doc! {
+            "validator_account_id": validator_account_id,
+            "epoch": epoch as i64,
+        })
+        .sort(doc! { "delegator_id": 1 })
+        .skip(skip)
+        .limit(limit)
+        .await?;
+    cursor.try_collect().await
+}
+
+/// Returns `limit` of a delegator's records after skipping `skip`, oldest epoch first. Covers
+/// every pool unless `validator_account_id` is given.
+pub async fn get_delegator_history_page(
+    db: &Database,
+    delegator_id: &str,
+    validator_account_id: Option<&str>,
+    skip: u64,
+    limit: i64,
+) -> Result<Vec<DelegatorData>, mongodb::error::Error> {
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    let mut filter = doc! { "delegator_id": delegator_id };
+    if let Some(validator_account_id) = validator_account_id {
+        filter.insert("validator_account_id", validator_account_id);
+    }
+    let cursor = collection
+        .find(filter)
+        .sort(doc! { "epoch": 1, "validator_account_id": 1 })
+        .skip(skip)
+        .limit(limit)
+        .await?;
+    cursor.try_collect().await
+}
+
+/// Returns every stored record in a pool, grouped by delegator and oldest epoch first.
+pub async fn get_pool_delegators(
+    db: &Database,
+    validator_account_id: &str,
+) -> Result<Vec<DelegatorData>, mongodb::error::Error> {
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    let cursor = collection
+        .find(doc! { "validator_account_id": validator_account_id })
+        .sort(doc! { "delegator_id": 1, "epoch": 1 })
+        .await?;
+    cursor.try_collect().await
+}
+
+/// Returns a pool's records from epoch `from_epoch` on, grouped by delegator and oldest epoch
+/// first.
+pub async fn get_pool_delegators_from(
+    db: &Database,
+    validator_account_id: &str,
+    from_epoch: u64,
+) -> Result<Vec<DelegatorData>, mongodb::error::Error> {
+    let collection: Collection<DelegatorData> = collections::delegators(db);
+    let cursor = collection
+        .find(doc! {
+            "validator_account_id": validator_account_id,
+            "epoch": { "$gte": from_epoch as i64 },
+        })
+        .sort(doc! { "delegator_id": 1, "epoch": 1 })
+        .await?;
+    cursor.try_collect().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POOL: &str = "pool.poolv1.near";
+
+    fn record(delegator_id: &str, epoch: u64) -> DelegatorData {
+        DelegatorData {
+            delegator_id: delegator_id.to_string(),
+            validator_account_id: POOL.to_string(),
+            epoch,
+            start_block_height: epoch * 43_200,
+            end_block_height: (epoch + 1) * 43_200 - 1,
+            timestamp: 0,
+            initial_stake: "100".to_string(),
+            auto_compounded_stake: "100".to_string(),
+            last_update_block: 0,
+            epoch_id: format!("epoch-{}", epoch),
+            rewards: "1".to_string(),
+            apy: Some(9.5),
+            cumulative_rewards: "1".to_string(),
+            stake_at_start: None,
+            stake_at_end: None,
+            negative_rewards: false,
+            beneficiary_id: None,
+            gross_staked: String::new(),
+            gross_unstaked: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn stored_records_are_read_back() {
+        let Ok(uri) = std::env::var("MONGO_TEST_URI") else {
+            eprintln!("MONGO_TEST_URI is unset, skipping");
+            return;
+        };
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let db = client.database(&format!("near_indexer_test_{}", std::process::id()));
+
+        let records = [
+            record("alice.near", 2),
+            record("bob.near", 2),
+            record("alice.near", 1),
+        ];
+        let saved = save_delegator_data(&db, &records, 2, None).await;
+        let history = get_delegator_history(&db, "alice.near", POOL).await;
+        let delegators = get_epoch_delegators(&db, POOL, 2).await;
+        db.drop().await.unwrap();
+
+        saved.unwrap();
+        let epochs: Vec<u64> = history.unwrap().iter().map(|record| record.epoch).collect();
+        assert_eq!(epochs, [1, 2]);
+        let delegators = delegators.unwrap();
+        let delegator_ids: Vec<&str> = delegators
+            .iter()
+            .map(|record| record.delegator_id.as_str())
+            .collect();
+        assert_eq!(delegator_ids, ["alice.near", "bob.near"]);
+        assert_eq!(delegators[0].apy, Some(9.5));
+    }
+}
@@ -1,12 +1,15 @@
 use crate::models::{DelegatorData, Transaction};
+use crate::repositories::collections;
+use futures::TryStreamExt;
 use mongodb::options::UpdateOptions;
 
 use mongodb::{
     bson::{doc, to_bson},
-    Collection, Database,
+    ClientSession, Collection, Database,
 };
 use std::collections::HashMap;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn save_epoch_data(
     db: &Database,
     epoch: u64,
@@ -17,9 +20,11 @@ pub async fn save_epoch_data(
     end_block_height: u64,
     epoch_transactions: &[&Transaction],
     epoch_timestamp: u64,
+    rewards_verified: Option<bool>,
+    session: Option<&mut ClientSession>,
 ) -> Result<(), mongodb::error::Error> {
-    let collection: Collection<mongodb::bson::Document> = db.collection("epoch_data");
-    let epoch_data = doc! {
+    let collection: Collection<mongodb::bson::Document> = collections::epoch_data(db);
+    let mut epoch_data = doc! {
         "epoch": epoch as i64,
         "epochId": epoch_id,
         "validatorAccountId": validator_account_id,
@@ -29,11 +34,40 @@ pub async fn save_epoch_data(
         "delegators": to_bson(delegator_data)?,
         "transactions": to_bson(epoch_transactions)?,
     };
+    if let Some(rewards_verified) = rewards_verified {
+        epoch_data.insert("rewardsVerified", rewards_verified);
+    }
 
     let options = UpdateOptions::builder().upsert(Some(true)).build();
-    collection.update_one(
+    let update = collection.update_one(
         doc! { "epoch": epoch as i64, "epochId": epoch_id, "validatorAccountId": validator_account_id },
         doc! { "$set": epoch_data },
-    ).upsert(options.upsert.unwrap_or(false)).await?;
+    ).upsert(options.upsert.unwrap_or(false));
+    match session {
+        Some(session) => update.session(session).await?,
+        None => update.await?,
+    };
     Ok(())
 }
+
+/// Returns the end block of every epoch stored for the validator, keyed by epoch id.
+pub async fn get_stored_end_blocks(
+    db: &Database,
+    validator_account_id: &str,
+) -> Result<HashMap<String, u64>, mongodb::error::Error> {
+    let collection: Collection<mongodb::bson::Document> = collections::epoch_data(db);
+    let mut cursor = collection
+        .find(doc! { "validatorAccountId": validator_account_id })
+        .projection(doc! { "epochId": 1, "endBlockHeight": 1 })
+        .await?;
+    let mut end_blocks = HashMap::new();
+    while let Some(document) = cursor.try_next().await? {
+        if let (Ok(epoch_id), Ok(end_block)) = (
+            document.get_str("epochId"),
+            document.get_i64("endBlockHeight"),
+        ) {
+            end_blocks.insert(epoch_id.to_string(), end_block as u64);
+        }
+    }
+    Ok(end_blocks)
+}
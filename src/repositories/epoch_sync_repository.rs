@@ -1,16 +1,21 @@
+use crate::error::EpochSyncError;
 use crate::models::EpochInfo;
-use futures::StreamExt;
+use crate::repositories::collections;
+use futures::{StreamExt, TryStreamExt};
 use mongodb::bson::{doc, to_document};
-use mongodb::options::{FindOptions, UpdateOptions};
+use mongodb::options::{FindOptions, UpdateOneModel, UpdateOptions, WriteModel};
 use mongodb::{Collection, Database};
 
-pub async fn save_epoch_sync(
-    db: &Database,
-    epoch_info: &EpochInfo,
-) -> Result<(), mongodb::error::Error> {
-    let collection: Collection<EpochInfo> = db.collection("epoch_sync");
+/// Upserts the epoch by `epoch_id`. Epochs with an empty id, an end before their start, or
+/// a start before the latest stored epoch are rejected; re-saving the latest epoch is allowed.
+#[allow(dead_code)]
+pub async fn save_epoch_sync(db: &Database, epoch_info: &EpochInfo) -> Result<(), EpochSyncError> {
+    let latest = get_latest_epoch_sync(db).await?;
+    validate_epoch(epoch_info, latest.as_ref())?;
+
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
     let filter = doc! { "epoch_id": &epoch_info.epoch_id };
-    let update = doc! { "$set": to_document(epoch_info)? };
+    let update = doc! { "$set": to_document(epoch_info).map_err(mongodb::error::Error::from)? };
     let options = UpdateOptions::builder().upsert(true).build();
     collection
         .update_one(filter, update)
@@ -19,10 +24,79 @@ pub async fn save_epoch_sync(
     Ok(())
 }
 
+/// Upserts the epochs by `epoch_id` in one `bulk_write`. Each epoch is validated like in
+/// `save_epoch_sync`, against the one before it in `epochs`, and nothing is written if any
+/// is rejected.
+pub async fn save_epoch_syncs_bulk(
+    db: &Database,
+    epochs: &[EpochInfo],
+) -> Result<(), EpochSyncError> {
+    if epochs.is_empty() {
+        return Ok(());
+    }
+
+    let mut latest = get_latest_epoch_sync(db).await?;
+    for epoch_info in epochs {
+        validate_epoch(epoch_info, latest.as_ref())?;
+        latest = Some(epoch_info.clone());
+    }
+
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    let namespace = collection.namespace();
+    let mut models = Vec::with_capacity(epochs.len());
+    for epoch_info in epochs {
+        let update = doc! { "$set": to_document(epoch_info).map_err(mongodb::error::Error::from)? };
+        models.push(WriteModel::UpdateOne(
+            UpdateOneModel::builder()
+                .namespace(namespace.clone())
+                .filter(doc! { "epoch_id": &epoch_info.epoch_id })
+                .update(update)
+                .upsert(true)
+                .build(),
+        ));
+    }
+    db.client().bulk_write(models).await?;
+    Ok(())
+}
+
+fn validate_epoch(
+    epoch_info: &EpochInfo,
+    latest: Option<&EpochInfo>,
+) -> Result<(), EpochSyncError> {
+    if epoch_info.epoch_id.is_empty() {
+        return Err(EpochSyncError::EmptyEpochId {
+            start_block: epoch_info.start_block,
+        });
+    }
+    if let Some(end_block) = epoch_info.end_block {
+        if end_block < epoch_info.start_block {
+            return Err(EpochSyncError::EndBeforeStart {
+                epoch_id: epoch_info.epoch_id.clone(),
+                start_block: epoch_info.start_block,
+                end_block,
+            });
+        }
+    }
+    if let Some(latest) = latest {
+        let goes_backwards = epoch_info.start_block < latest.start_block
+            || (epoch_info.start_block == latest.start_block
+                && epoch_info.epoch_id != latest.epoch_id);
+        if goes_backwards {
+            return Err(EpochSyncError::NotAfterLatest {
+                epoch_id: epoch_info.epoch_id.clone(),
+                start_block: epoch_info.start_block,
+                latest_epoch_id: latest.epoch_id.clone(),
+                latest_start_block: latest.start_block,
+            });
+        }
+    }
+    Ok(())
+}
+
 pub async fn get_latest_epoch_sync(
     db: &Database,
 ) -> Result<Option<EpochInfo>, mongodb::error::Error> {
-    let collection: Collection<EpochInfo> = db.collection("epoch_sync");
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
     let options = FindOptions::builder()
         .sort(doc! { "start_block": -1 })
         .limit(1)
@@ -35,26 +109,336 @@ pub async fn get_latest_epoch_sync(
     cursor.next().await.transpose()
 }
 
-pub async fn get_epoch_sync_count(db: &Database) -> Result<u64, mongodb::error::Error> {
-    let collection: Collection<EpochInfo> = db.collection("epoch_sync");
-    collection.count_documents(doc! {}).await
+/// Every stored epoch ordered by `start_block`, in one query.
+pub async fn get_all_epochs(db: &Database) -> Result<Vec<EpochInfo>, mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    collection
+        .find(doc! {})
+        .sort(doc! { "start_block": 1 })
+        .await?
+        .try_collect()
+        .await
 }
 
-pub async fn get_epoch_sync_by_index(
+/// Stored epochs starting at or after `start_block`, ordered by `start_block`. Positions in
+/// the result are offset from epoch numbers by `count_epochs_before(start_block)`.
+#[allow(dead_code)]
+pub async fn get_epochs_from(
     db: &Database,
-    index: u64,
-) -> Result<Option<EpochInfo>, mongodb::error::Error> {
-    let collection: Collection<EpochInfo> = db.collection("epoch_sync");
-    let options = FindOptions::builder()
+    start_block: u64,
+) -> Result<Vec<EpochInfo>, mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    collection
+        .find(doc! { "start_block": { "$gte": start_block as i64 } })
         .sort(doc! { "start_block": 1 })
-        .skip(Some(index))
-        .limit(1)
-        .build();
-    let mut cursor = collection
+        .await?
+        .try_collect()
+        .await
+}
+
+/// Returns the block ranges missing between consecutive stored epochs, i.e. where an epoch's
+/// `end_block + 1` is not the next epoch's `start_block`.
+pub async fn find_gaps(db: &Database) -> Result<Vec<(u64, u64)>, mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    let epochs: Vec<EpochInfo> = collection
         .find(doc! {})
-        .sort(options.sort.unwrap_or_default())
-        .skip(options.skip.unwrap_or(0))
-        .limit(options.limit.unwrap_or(1))
+        .sort(doc! { "start_block": 1 })
+        .await?
+        .try_collect()
         .await?;
-    cursor.next().await.transpose()
+    Ok(gaps(&epochs))
+}
+
+/// Gaps between consecutive epochs of `epochs`, sorted by `start_block`.
+fn gaps(epochs: &[EpochInfo]) -> Vec<(u64, u64)> {
+    epochs
+        .windows(2)
+        .filter_map(|pair| {
+            let end_block = pair[0].end_block?;
+            let start_block = pair[1].start_block;
+            (end_block + 1 < start_block).then(|| (end_block + 1, start_block - 1))
+        })
+        .collect()
+}
+
+pub async fn mark_finalized(db: &Database, epoch_id: &str) -> Result<(), mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    collection
+        .update_one(
+            doc! { "epoch_id": epoch_id },
+            doc! { "$set": { "finalized": true } },
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn get_by_epoch_id(
+    db: &Database,
+    epoch_id: &str,
+) -> Result<Option<EpochInfo>, mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    collection.find_one(doc! { "epoch_id": epoch_id }).await
+}
+
+/// Number of stored epochs starting before `start_block`, i.e. the epoch number of the epoch
+/// starting there minus one.
+pub async fn count_epochs_before(
+    db: &Database,
+    start_block: u64,
+) -> Result<u64, mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    collection
+        .count_documents(doc! { "start_block": { "$lt": start_block as i64 } })
+        .await
+}
+
+/// The stored epoch starting right after `start_block`, if any.
+pub async fn get_next_epoch(
+    db: &Database,
+    start_block: u64,
+) -> Result<Option<EpochInfo>, mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    collection
+        .find_one(doc! { "start_block": { "$gt": start_block as i64 } })
+        .sort(doc! { "start_block": 1 })
+        .await
+}
+
+/// The stored epoch starting right before `start_block`, if any.
+pub async fn get_previous_epoch(
+    db: &Database,
+    start_block: u64,
+) -> Result<Option<EpochInfo>, mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    collection
+        .find_one(doc! { "start_block": { "$lt": start_block as i64 } })
+        .sort(doc! { "start_block": -1 })
+        .await
+}
+
+/// Deletes the stored epochs starting at or after `start_block`, so they can be resynced.
+pub async fn delete_epochs_from(
+    db: &Database,
+    start_block: u64,
+) -> Result<u64, mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    let result = collection
+        .delete_many(doc! { "start_block": { "$gte": start_block as i64 } })
+        .await?;
+    Ok(result.deleted_count)
+}
+
+/// Stored epochs whose end block is only an estimate, ordered by `start_block`.
+pub async fn get_unverified_epochs(db: &Database) -> Result<Vec<EpochInfo>, mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    collection
+        .find(doc! { "boundary_verified": false })
+        .sort(doc! { "start_block": 1 })
+        .await?
+        .try_collect()
+        .await
+}
+
+/// Moves the boundary after `epoch_id` to `next.start_block`: the epoch now ends right before
+/// it and is marked verified, and the epoch stored at `old_next_start` is replaced by `next`.
+pub async fn correct_boundary(
+    db: &Database,
+    epoch_id: &str,
+    old_next_start: u64,
+    next: &EpochInfo,
+) -> Result<(), mongodb::error::Error> {
+    let collection: Collection<EpochInfo> = collections::epoch_sync(db);
+    collection
+        .update_one(
+            doc! { "epoch_id": epoch_id },
+            doc! { "$set": {
+                "end_block": (next.start_block - 1) as i64,
+                "boundary_verified": true,
+            } },
+        )
+        .await?;
+    collection
+        .update_one(
+            doc! { "start_block": old_next_start as i64 },
+            doc! { "$set": to_document(next)? },
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn epoch(start_block: u64, end_block: u64) -> EpochInfo {
+        EpochInfo {
+            start_block,
+            end_block: Some(end_block),
+            epoch_id: format!("epoch-{}", start_block),
+            timestamp: Utc::now(),
+            block_hash: None,
+            finalized: true,
+            boundary_verified: true,
+        }
+    }
+
+    #[test]
+    fn missing_epoch_is_reported_as_a_gap() {
+        let epochs = [
+            epoch(100, 199),
+            epoch(200, 299),
+            // 300..=399 was never synced
+            epoch(400, 499),
+            epoch(500, 599),
+        ];
+        assert_eq!(gaps(&epochs), [(300, 399)]);
+        assert!(gaps(&epochs[..2]).is_empty());
+    }
+
+    #[test]
+    fn empty_epoch_id_is_rejected() {
+        let mut epoch_info = epoch(100, 199);
+        epoch_info.epoch_id.clear();
+        assert!(matches!(
+            validate_epoch(&epoch_info, None),
+            Err(EpochSyncError::EmptyEpochId { start_block: 100 })
+        ));
+    }
+
+    #[test]
+    fn end_before_start_is_rejected() {
+        assert!(matches!(
+            validate_epoch(&epoch(200, 199), None),
+            Err(EpochSyncError::EndBeforeStart { .. })
+        ));
+    }
+
+    #[test]
+    fn epoch_not_after_the_latest_is_rejected() {
+        let latest = epoch(200, 299);
+        assert!(matches!(
+            validate_epoch(&epoch(100, 199), Some(&latest)),
+            Err(EpochSyncError::NotAfterLatest { .. })
+        ));
+        let mut same_start = epoch(200, 299);
+        same_start.epoch_id = "other".to_string();
+        assert!(matches!(
+            validate_epoch(&same_start, Some(&latest)),
+            Err(EpochSyncError::NotAfterLatest { .. })
+        ));
+
+        // Re-saving the latest epoch and saving the next one are allowed
+        assert!(validate_epoch(&epoch(200, 299), Some(&latest)).is_ok());
+        assert!(validate_epoch(&epoch(300, 399), Some(&latest)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn epoch_is_looked_up_by_its_id() {
+        let Ok(uri) = std::env::var("MONGO_TEST_URI") else {
+            eprintln!("MONGO_TEST_URI is unset, skipping");
+            return;
+        };
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let db = client.database(&format!("near_indexer_test_epochs_{}", std::process::id()));
+
+        let first = save_epoch_sync(&db, &epoch(100, 199)).await;
+        let second = save_epoch_sync(&db, &epoch(200, 299)).await;
+        let found = get_by_epoch_id(&db, "epoch-100").await;
+        let missing = get_by_epoch_id(&db, "epoch-300").await;
+        db.drop().await.unwrap();
+
+        first.unwrap();
+        second.unwrap();
+        let found = found.unwrap().expect("epoch-100 was saved");
+        assert_eq!((found.start_block, found.end_block), (100, Some(199)));
+        assert!(missing.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn one_query_matches_the_index_by_index_order() {
+        let Ok(uri) = std::env::var("MONGO_TEST_URI") else {
+            eprintln!("MONGO_TEST_URI is unset, skipping");
+            return;
+        };
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let db = client.database(&format!("near_indexer_test_order_{}", std::process::id()));
+        let collection: Collection<EpochInfo> = collections::epoch_sync(&db);
+
+        // Inserted out of order, as a backfill would
+        let inserted = collection
+            .insert_many([
+                epoch(300, 399),
+                epoch(100, 199),
+                epoch(400, 499),
+                epoch(200, 299),
+            ])
+            .await;
+        let mut by_index = Vec::new();
+        for index in 0..4 {
+            // The removed get_epoch_sync_by_index query
+            let mut cursor = collection
+                .find(doc! {})
+                .sort(doc! { "start_block": 1 })
+                .skip(index)
+                .limit(1)
+                .await
+                .unwrap();
+            by_index.push(cursor.next().await.transpose().unwrap().unwrap());
+        }
+        let all = get_all_epochs(&db).await;
+        let from = get_epochs_from(&db, 250).await;
+        let before = count_epochs_before(&db, 250).await;
+        db.drop().await.unwrap();
+
+        inserted.unwrap();
+        let ids = |epochs: &[EpochInfo]| -> Vec<String> {
+            epochs.iter().map(|epoch| epoch.epoch_id.clone()).collect()
+        };
+        let all = all.unwrap();
+        assert_eq!(ids(&all), ids(&by_index));
+        let before = before.unwrap() as usize;
+        assert_eq!(before, 2);
+        assert_eq!(ids(&from.unwrap()), ids(&all[before..]));
+    }
+
+    #[tokio::test]
+    async fn bulk_and_single_saves_store_the_same_documents() {
+        let Ok(uri) = std::env::var("MONGO_TEST_URI") else {
+            eprintln!("MONGO_TEST_URI is unset, skipping");
+            return;
+        };
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let single_db =
+            client.database(&format!("near_indexer_test_single_{}", std::process::id()));
+        let bulk_db = client.database(&format!("near_indexer_test_bulk_{}", std::process::id()));
+
+        let epochs = [epoch(100, 199), epoch(200, 299), epoch(300, 399)];
+        let mut single = Ok(());
+        for epoch_info in &epochs {
+            single = single.and(save_epoch_sync(&single_db, epoch_info).await);
+        }
+        let bulk = save_epoch_syncs_bulk(&bulk_db, &epochs).await;
+        let stored = |db: Database| async move {
+            let documents: Vec<mongodb::bson::Document> = db
+                .collection("epoch_sync")
+                .find(doc! {})
+                .projection(doc! { "_id": 0 })
+                .sort(doc! { "start_block": 1 })
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            db.drop().await.unwrap();
+            documents
+        };
+        let single_documents = stored(single_db).await;
+        let bulk_documents = stored(bulk_db).await;
+
+        single.unwrap();
+        bulk.unwrap();
+        assert_eq!(single_documents.len(), 3);
+        assert_eq!(single_documents, bulk_documents);
+    }
 }
@@ -0,0 +1,163 @@
+use crate::repositories::collections;
+use futures::TryStreamExt;
+use log::{info, warn};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::IndexOptions;
+use mongodb::{Collection, Database, IndexModel};
+
+/// Creates the indexes backing the repositories' sorts and upsert filters. Creating an
+/// index that already exists is a no-op, so this is safe to call on every startup. Unique
+/// indexes are built after removing duplicates that older versions stored, and if one still
+/// can't be built the indexer starts without it.
+pub async fn ensure_indexes(db: &Database) -> Result<(), mongodb::error::Error> {
+    create_index(
+        collections::transactions(db),
+        doc! { "block_height": -1 },
+        false,
+    )
+    .await?;
+    create_unique_index(
+        collections::transactions(db),
+        doc! { "transaction_hash": 1 },
+    )
+    .await;
+    create_index(
+        collections::transactions(db),
+        doc! { "validator_account_id": 1, "block_height": -1 },
+        false,
+    )
+    .await?;
+    create_unique_index(
+        collections::delegators(db),
+        doc! { "delegator_id": 1, "validator_account_id": 1, "epoch": 1 },
+    )
+    .await;
+    create_index(
+        collections::epoch_data(db),
+        doc! { "validatorAccountId": 1, "epoch": 1, "epochId": 1 },
+        false,
+    )
+    .await?;
+    create_index(
+        collections::epoch_sync(db),
+        doc! { "start_block": -1 },
+        false,
+    )
+    .await?;
+    create_index(collections::epoch_sync(db), doc! { "epoch_id": 1 }, false).await?;
+    create_unique_index(
+        collections::raw_transactions(db),
+        doc! { "transaction_hash": 1 },
+    )
+    .await;
+    create_index(
+        collections::reward_events(db),
+        doc! { "validator_account_id": 1, "delegator_id": 1, "epoch": 1 },
+        false,
+    )
+    .await?;
+    create_unique_index(collections::scan_cursors(db), doc! { "name": 1 }).await;
+
+    info!("MongoDB indexes are in place");
+    Ok(())
+}
+
+/// Builds a unique index on `keys`. The first time, documents sharing the same keys are
+/// deduplicated first, keeping the most recently inserted one. A build that still fails,
+/// e.g. on duplicates written meanwhile, is logged rather than stopping the indexer.
+async fn create_unique_index(collection: Collection<Document>, keys: Document) {
+    let existing = collection.list_index_names().await.unwrap_or_default();
+    if existing.contains(&index_name(&keys)) {
+        return;
+    }
+
+    match remove_duplicates(&collection, &keys).await {
+        Ok(0) => {}
+        Ok(removed) => warn!(
+            "Removed {} duplicate documents from {} before building a unique index on {}",
+            removed,
+            collection.name(),
+            keys
+        ),
+        Err(e) => warn!(
+            "Could not remove duplicates from {} before building a unique index on {}: {}",
+            collection.name(),
+            keys,
+            e
+        ),
+    }
+    if let Err(e) = create_index(collection.clone(), keys.clone(), true).await {
+        warn!(
+            "Could not build the unique index on {} of {}, continuing without it: {}",
+            keys,
+            collection.name(),
+            e
+        );
+    }
+}
+
+/// Default name MongoDB gives an index on `keys`, e.g. `epoch_1_start_block_-1`.
+fn index_name(keys: &Document) -> String {
+    keys.iter()
+        .map(|(field, direction)| format!("{}_{}", field, direction))
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Deletes all but the most recently inserted document of every group sharing `keys`, and
+/// returns how many were deleted.
+async fn remove_duplicates(
+    collection: &Collection<Document>,
+    keys: &Document,
+) -> Result<u64, mongodb::error::Error> {
+    let group_id: Document = keys
+        .keys()
+        .map(|field| (field.clone(), Bson::String(format!("${}", field))))
+        .collect();
+    let pipeline = vec![
+        doc! { "$sort": { "_id": -1 } },
+        doc! { "$group": { "_id": group_id, "ids": { "$push": "$_id" }, "count": { "$sum": 1 } } },
+        doc! { "$match": { "count": { "$gt": 1 } } },
+    ];
+    let mut groups = collection.aggregate(pipeline).allow_disk_use(true).await?;
+
+    let mut removed = 0;
+    while let Some(group) = groups.try_next().await? {
+        let Ok(ids) = group.get_array("ids") else {
+            continue;
+        };
+        let duplicates = ids[1..].to_vec();
+        removed += collection
+            .delete_many(doc! { "_id": { "$in": duplicates } })
+            .await?
+            .deleted_count;
+    }
+    Ok(removed)
+}
+
+async fn create_index(
+    collection: Collection<Document>,
+    keys: Document,
+    unique: bool,
+) -> Result<(), mongodb::error::Error> {
+    let index = IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(unique).build())
+        .build();
+    collection.create_index(index).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_name_matches_the_mongodb_default() {
+        assert_eq!(
+            index_name(&doc! { "delegator_id": 1, "validator_account_id": 1, "epoch": 1 }),
+            "delegator_id_1_validator_account_id_1_epoch_1"
+        );
+        assert_eq!(index_name(&doc! { "start_block": -1 }), "start_block_-1");
+    }
+}
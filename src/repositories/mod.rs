@@ -1,5 +1,11 @@
+pub mod collections;
 pub mod delegator_repository;
 pub mod epoch_repository;
 pub mod epoch_sync_repository;
+pub mod indexes;
+pub mod raw_transaction_repository;
+pub mod reward_event_repository;
+pub mod run_summary_repository;
+pub mod scan_cursor_repository;
 pub mod transaction_repository;
 pub mod validator_repository;
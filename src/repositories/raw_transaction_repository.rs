@@ -0,0 +1,27 @@
+use crate::repositories::collections;
+use mongodb::bson::{doc, to_bson, DateTime, Document};
+use mongodb::{Collection, Database};
+use serde_json::Value;
+
+/// Stores the transaction as returned by the transaction source together with its
+/// `EXPERIMENTAL_tx_status` response, so its parsing can be replayed without RPC calls.
+pub async fn save_raw_transaction(
+    db: &Database,
+    transaction_hash: &str,
+    transaction: &Value,
+    receipts: &Value,
+) -> Result<(), mongodb::error::Error> {
+    let collection: Collection<Document> = collections::raw_transactions(db);
+    let update = doc! {
+        "$set": {
+            "transaction": to_bson(transaction)?,
+            "receipts": to_bson(receipts)?,
+            "savedAt": DateTime::now(),
+        }
+    };
+    collection
+        .update_one(doc! { "transaction_hash": transaction_hash }, update)
+        .upsert(true)
+        .await?;
+    Ok(())
+}
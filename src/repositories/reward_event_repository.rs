@@ -0,0 +1,12 @@
+use crate::models::RewardEvent;
+use crate::repositories::collections;
+use mongodb::{Collection, Database};
+
+pub async fn record_reward_event(
+    db: &Database,
+    event: &RewardEvent,
+) -> Result<(), mongodb::error::Error> {
+    let collection: Collection<RewardEvent> = collections::reward_events(db);
+    collection.insert_one(event).await?;
+    Ok(())
+}
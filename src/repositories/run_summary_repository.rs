@@ -0,0 +1,12 @@
+use crate::models::RunSummary;
+use crate::repositories::collections;
+use mongodb::{Collection, Database};
+
+pub async fn save_run_summary(
+    db: &Database,
+    summary: &RunSummary,
+) -> Result<(), mongodb::error::Error> {
+    let collection: Collection<RunSummary> = collections::run_summaries(db);
+    collection.insert_one(summary).await?;
+    Ok(())
+}
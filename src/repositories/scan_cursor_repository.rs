@@ -0,0 +1,35 @@
+use crate::repositories::collections;
+use mongodb::bson::{doc, Bson, DateTime, Document};
+use mongodb::{Collection, Database};
+
+/// Highest block height already scanned under `name`, or `None` before its first scan.
+pub async fn get_scanned_height(
+    db: &Database,
+    name: &str,
+) -> Result<Option<u64>, mongodb::error::Error> {
+    let collection: Collection<Document> = collections::scan_cursors(db);
+    let cursor = collection.find_one(doc! { "name": name }).await?;
+    Ok(cursor
+        .and_then(|cursor| cursor.get_i64("block_height").ok())
+        .map(|height| height as u64))
+}
+
+/// Records that every block up to `block_height` has been scanned under `name`.
+pub async fn save_scanned_height(
+    db: &Database,
+    name: &str,
+    block_height: u64,
+) -> Result<(), mongodb::error::Error> {
+    let collection: Collection<Document> = collections::scan_cursors(db);
+    let update = doc! {
+        "$set": {
+            "block_height": Bson::Int64(block_height as i64),
+            "updatedAt": DateTime::now(),
+        }
+    };
+    collection
+        .update_one(doc! { "name": name }, update)
+        .upsert(true)
+        .await?;
+    Ok(())
+}
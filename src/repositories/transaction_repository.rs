@@ -1,29 +1,199 @@
 use crate::models::Transaction;
-use futures::StreamExt;
+use crate::repositories::collections;
+use futures::{StreamExt, TryStreamExt};
+use log::info;
+use mongodb::error::{ErrorKind, InsertManyError};
 use mongodb::options::FindOptions;
-use mongodb::{bson::doc, Collection, Database};
+use mongodb::{bson::doc, bson::Document, Collection, Database};
+use std::collections::HashSet;
 
+const DUPLICATE_KEY_ERROR_CODE: i32 = 11000;
+
+/// Inserts the transactions, skipping any whose `transaction_hash` is already stored so a
+/// re-run after a partial failure doesn't abort the batch. Returns how many were inserted.
 pub async fn save_transactions(
     db: &Database,
     transactions: &[Transaction],
-) -> Result<(), mongodb::error::Error> {
-    let collection: Collection<Transaction> = db.collection("transactions");
-    collection.insert_many(transactions).await?;
-    Ok(())
+) -> Result<usize, mongodb::error::Error> {
+    let collection: Collection<Transaction> = collections::transactions(db);
+    match collection.insert_many(transactions).ordered(false).await {
+        Ok(result) => Ok(result.inserted_ids.len()),
+        Err(e) => match duplicate_key_count(&e) {
+            Some(skipped) => {
+                info!("Skipped {} transactions that were already stored", skipped);
+                Ok(transactions.len().saturating_sub(skipped))
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Returns how many documents were rejected if every write error is a duplicate key.
+fn duplicate_key_count(error: &mongodb::error::Error) -> Option<usize> {
+    match error.kind.as_ref() {
+        ErrorKind::InsertMany(InsertManyError {
+            write_errors: Some(write_errors),
+            write_concern_error: None,
+            ..
+        }) if write_errors
+            .iter()
+            .all(|write_error| write_error.code == DUPLICATE_KEY_ERROR_CODE) =>
+        {
+            Some(write_errors.len())
+        }
+        _ => None,
+    }
+}
+
+/// Matches the pool's transactions, plus those stored before transactions carried a pool.
+fn validator_filter(validator_account_id: &str) -> Document {
+    doc! {
+        "$or": [
+            { "validator_account_id": validator_account_id },
+            { "validator_account_id": { "$exists": false } },
+        ]
+    }
 }
 
 pub async fn get_latest_transaction(
     db: &Database,
+    validator_account_id: &str,
 ) -> Result<Option<Transaction>, mongodb::error::Error> {
-    let collection: Collection<Transaction> = db.collection("transactions");
+    let collection: Collection<Transaction> = collections::transactions(db);
     let options = FindOptions::builder()
         .sort(doc! { "block_height": -1 })
         .limit(1)
         .build();
     let mut cursor = collection
-        .find(doc! {})
+        .find(validator_filter(validator_account_id))
         .sort(options.sort.unwrap_or_default())
         .limit(options.limit.unwrap_or(1))
         .await?;
     cursor.next().await.transpose()
 }
+
+pub async fn get_all_transactions(
+    db: &Database,
+    validator_account_id: &str,
+) -> Result<Vec<Transaction>, mongodb::error::Error> {
+    let collection: Collection<Transaction> = collections::transactions(db);
+    let options = FindOptions::builder()
+        .sort(doc! { "block_height": 1 })
+        .build();
+    let cursor = collection
+        .find(validator_filter(validator_account_id))
+        .sort(options.sort.unwrap_or_default())
+        .await?;
+    cursor.try_collect().await
+}
+
+/// Returns the pool's `limit` most recent transactions, highest block first.
+#[allow(dead_code)]
+pub async fn get_recent_transactions(
+    db: &Database,
+    validator_account_id: &str,
+    limit: i64,
+) -> Result<Vec<Transaction>, mongodb::error::Error> {
+    let collection: Collection<Transaction> = collections::transactions(db);
+    let cursor = collection
+        .find(validator_filter(validator_account_id))
+        .sort(doc! { "block_height": -1 })
+        .limit(limit)
+        .await?;
+    cursor.try_collect().await
+}
+
+/// Returns the pool's transactions with `from_block <= block_height <= to_block`, lowest
+/// block first.
+pub async fn get_transactions_in_range(
+    db: &Database,
+    validator_account_id: &str,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<Transaction>, mongodb::error::Error> {
+    let collection: Collection<Transaction> = collections::transactions(db);
+    let cursor = collection
+        .find(doc! {
+            "$and": [
+                validator_filter(validator_account_id),
+                { "block_height": {
+                    "$gte": from_block.min(i64::MAX as u64) as i64,
+                    "$lte": to_block.min(i64::MAX as u64) as i64,
+                } },
+            ]
+        })
+        .sort(doc! { "block_height": 1 })
+        .await?;
+    cursor.try_collect().await
+}
+
+/// Hashes of the pool's stored transactions at or above `from_block_height`.
+pub async fn get_transaction_hashes_from_block(
+    db: &Database,
+    validator_account_id: &str,
+    from_block_height: u64,
+) -> Result<HashSet<String>, mongodb::error::Error> {
+    let collection: Collection<Transaction> = collections::transactions(db);
+    let hashes = collection
+        .distinct(
+            "transaction_hash",
+            doc! {
+                "$and": [
+                    validator_filter(validator_account_id),
+                    { "block_height": { "$gte": from_block_height as i64 } },
+                ]
+            },
+        )
+        .await?;
+    Ok(hashes
+        .into_iter()
+        .filter_map(|hash| hash.as_str().map(str::to_string))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    const POOL: &str = "pool.poolv1.near";
+
+    fn transaction(block_height: u64) -> Transaction {
+        Transaction {
+            transaction_hash: format!("tx-{}", block_height),
+            amount: "1".to_string(),
+            method: "deposit_and_stake".to_string(),
+            action: "stake".to_string(),
+            type_: "stake".to_string(),
+            block_height,
+            timestamp: Utc::now(),
+            delegator_address: "alice.near".to_string(),
+            validator_account_id: POOL.to_string(),
+            reward_amount: None,
+            explorer_url: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn range_bounds_are_inclusive_and_recent_is_highest_first() {
+        let Ok(uri) = std::env::var("MONGO_TEST_URI") else {
+            eprintln!("MONGO_TEST_URI is unset, skipping");
+            return;
+        };
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let db = client.database(&format!("near_indexer_test_tx_{}", std::process::id()));
+
+        let transactions: Vec<_> = [99, 100, 150, 200, 201].map(transaction).into();
+        let saved = save_transactions(&db, &transactions).await;
+        let in_range = get_transactions_in_range(&db, POOL, 100, 200).await;
+        let recent = get_recent_transactions(&db, POOL, 2).await;
+        db.drop().await.unwrap();
+
+        assert_eq!(saved.unwrap(), 5);
+        let heights = |transactions: Vec<Transaction>| -> Vec<u64> {
+            transactions.iter().map(|tx| tx.block_height).collect()
+        };
+        assert_eq!(heights(in_range.unwrap()), [100, 150, 200]);
+        assert_eq!(heights(recent.unwrap()), [201, 200]);
+    }
+}
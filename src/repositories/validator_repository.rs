@@ -1,11 +1,17 @@
-use crate::models::DelegatorData;
+use crate::models::{DelegatorData, ValidatorMetrics};
+use crate::repositories::collections;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, to_document, Document};
 use mongodb::options::UpdateOptions;
-use mongodb::{bson::doc, Collection, Database};
+use mongodb::{ClientSession, Collection, Database};
 use num_bigint::BigInt;
 use num_traits::Zero;
 use std::collections::HashMap;
 use std::str::FromStr; // Add this import
 
+/// Upserts the epoch's metrics and appends them to the `history` array, which keeps the latest
+/// `history_limit` entries. A `history_limit` of 0 leaves the history alone.
+#[allow(clippy::too_many_arguments)]
 pub async fn save_validator_metrics(
     db: &Database,
     validator_account_id: &str,
@@ -13,9 +19,13 @@ pub async fn save_validator_metrics(
     epoch_id: &str,
     delegator_data: &HashMap<String, DelegatorData>,
     epoch_timestamp: u64,
-    apy: String, // Added APY parameter
+    apy: Option<f64>,
+    apy_provisional: bool,
+    kicked_out: bool,
+    history_limit: u32,
+    session: Option<&mut ClientSession>,
 ) -> Result<(), mongodb::error::Error> {
-    let collection: Collection<mongodb::bson::Document> = db.collection("validator_metrics");
+    let collection: Collection<mongodb::bson::Document> = collections::validator_metrics(db);
 
     let mut total_staked = BigInt::from(0);
     let total_delegators = delegator_data.len() as i64;
@@ -25,36 +35,124 @@ pub async fn save_validator_metrics(
             BigInt::from_str(&data.auto_compounded_stake).unwrap_or_else(|_| BigInt::zero());
     }
 
-    let metrics = doc! {
-        "validatorAccountId": validator_account_id,
-        "epoch": epoch as i64,
-        "epochId": epoch_id,
-        "totalStaked": total_staked.to_string(),
-        "totalDelegators": total_delegators,
-        "timestamp": mongodb::bson::DateTime::from_millis(epoch_timestamp as i64),
-        "apy": apy,  // Added APY to metrics
-    };
+    let metrics = to_document(&ValidatorMetrics {
+        validator_account_id: validator_account_id.to_string(),
+        epoch,
+        epoch_id: epoch_id.to_string(),
+        total_staked: total_staked.to_string(),
+        total_delegators,
+        timestamp: mongodb::bson::DateTime::from_millis(epoch_timestamp as i64),
+        apy,
+        apy_provisional,
+        kicked_out,
+    })?;
+
+    let update = metrics_update(metrics, history_limit);
 
     let options = UpdateOptions::builder().upsert(true).build();
-    collection
+    let update = collection
         .update_one(
             doc! {
                 "validatorAccountId": validator_account_id,
                 "epoch": epoch as i64,
                 "epochId": epoch_id
             },
+            update,
+        )
+        .upsert(options.upsert.unwrap_or(false));
+    match session {
+        Some(session) => update.session(session).await?,
+        None => update.await?,
+    };
+
+    Ok(())
+}
+
+/// Sets the metrics and, unless `history_limit` is 0, appends them to `history` keeping the
+/// latest `history_limit` entries.
+fn metrics_update(metrics: Document, history_limit: u32) -> Document {
+    let mut update = doc! { "$set": metrics.clone() };
+    if history_limit > 0 {
+        update.insert(
+            "$push",
             doc! {
-                "$set": metrics.clone(),
-                "$push": {
-                    "history": {
-                        "$each": [metrics],
-                        "$slice": -100,
-                    }
+                "history": {
+                    "$each": [metrics],
+                    "$slice": -(history_limit as i64),
                 }
             },
-        )
-        .upsert(options.upsert.unwrap_or(false))
+        );
+    }
+    update
+}
+
+/// Returns the validator's metrics for epochs `from_epoch..=to_epoch`, oldest first.
+pub async fn get_validator_metrics(
+    db: &Database,
+    validator_account_id: &str,
+    from_epoch: u64,
+    to_epoch: u64,
+) -> Result<Vec<ValidatorMetrics>, mongodb::error::Error> {
+    let collection: Collection<ValidatorMetrics> = collections::validator_metrics(db);
+    let cursor = collection
+        .find(doc! {
+            "validatorAccountId": validator_account_id,
+            "epoch": { "$gte": from_epoch as i64, "$lte": to_epoch as i64 },
+        })
+        .sort(doc! { "epoch": 1 })
         .await?;
+    cursor.try_collect().await
+}
 
+/// Returns `limit` of the validator's metrics after skipping `skip`, newest epoch first.
+pub async fn get_validator_metrics_page(
+    db: &Database,
+    validator_account_id: &str,
+    skip: u64,
+    limit: i64,
+) -> Result<Vec<ValidatorMetrics>, mongodb::error::Error> {
+    let collection: Collection<ValidatorMetrics> = collections::validator_metrics(db);
+    let cursor = collection
+        .find(doc! { "validatorAccountId": validator_account_id })
+        .sort(doc! { "epoch": -1 })
+        .skip(skip)
+        .limit(limit)
+        .await?;
+    cursor.try_collect().await
+}
+
+/// Overwrites the stored APY of one epoch, leaving the history untouched.
+pub async fn update_validator_apy(
+    db: &Database,
+    validator_account_id: &str,
+    epoch: u64,
+    apy: Option<f64>,
+) -> Result<(), mongodb::error::Error> {
+    let collection: Collection<mongodb::bson::Document> = collections::validator_metrics(db);
+    collection
+        .update_one(
+            doc! { "validatorAccountId": validator_account_id, "epoch": epoch as i64 },
+            doc! { "$set": { "apy": apy } },
+        )
+        .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_limit_is_passed_as_the_slice() {
+        let metrics = doc! { "epoch": 7_i64 };
+
+        let update = metrics_update(metrics.clone(), 365);
+        let push = update.get_document("$push").unwrap();
+        let history = push.get_document("history").unwrap();
+        assert_eq!(history.get_i64("$slice").unwrap(), -365);
+        assert_eq!(update.get_document("$set").unwrap(), &metrics);
+
+        let update = metrics_update(metrics, 0);
+        assert!(!update.contains_key("$push"));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::repositories::{delegator_repository, validator_repository};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use log::{error, info};
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// `offset` and `limit` query parameters of the list routes.
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    offset: Option<u64>,
+    limit: Option<i64>,
+}
+
+impl Pagination {
+    fn offset(&self) -> u64 {
+        self.offset.unwrap_or(0)
+    }
+
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    fn page<T>(&self, items: Vec<T>) -> Page<T> {
+        Page {
+            offset: self.offset(),
+            limit: self.limit(),
+            items,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Page<T> {
+    offset: u64,
+    limit: i64,
+    items: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryFilter {
+    validator: Option<String>,
+}
+
+/// A failed read, returned as a 500 with the error message.
+struct ApiError(mongodb::error::Error);
+
+impl From<mongodb::error::Error> for ApiError {
+    fn from(error: mongodb::error::Error) -> Self {
+        ApiError(error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        error!("API query failed: {:?}", self.0);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": self.0.to_string() })),
+        )
+            .into_response()
+    }
+}
+
+async fn validator_epochs(
+    State(db): State<Database>,
+    Path(validator_account_id): Path<String>,
+    Query(pagination): Query<Pagination>,
+) -> Result<impl IntoResponse, ApiError> {
+    let metrics = validator_repository::get_validator_metrics_page(
+        &db,
+        &validator_account_id,
+        pagination.offset(),
+        pagination.limit(),
+    )
+    .await?;
+    Ok(Json(pagination.page(metrics)))
+}
+
+async fn epoch_delegators(
+    State(db): State<Database>,
+    Path((validator_account_id, epoch)): Path<(String, u64)>,
+    Query(pagination): Query<Pagination>,
+) -> Result<impl IntoResponse, ApiError> {
+    let records = delegator_repository::get_epoch_delegators_page(
+        &db,
+        &validator_account_id,
+        epoch,
+        pagination.offset(),
+        pagination.limit(),
+    )
+    .await?;
+    Ok(Json(pagination.page(records)))
+}
+
+async fn delegator_history(
+    State(db): State<Database>,
+    Path(delegator_id): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<HistoryFilter>,
+) -> Result<impl IntoResponse, ApiError> {
+    let records = delegator_repository::get_delegator_history_page(
+        &db,
+        &delegator_id,
+        filter.validator.as_deref(),
+        pagination.offset(),
+        pagination.limit(),
+    )
+    .await?;
+    Ok(Json(pagination.page(records)))
+}
+
+/// Serves the indexed validator and delegator data as read-only JSON.
+pub async fn serve(port: u16, db: Database) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/validators/:id/epochs", get(validator_epochs))
+        .route(
+            "/validators/:id/epochs/:epoch/delegators",
+            get(epoch_delegators),
+        )
+        .route("/delegators/:id/history", get(delegator_history))
+        .with_state(db);
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("API server listening on port {}", port);
+    axum::serve(listener, app).await
+}
@@ -0,0 +1,158 @@
+use crate::config::Config;
+use crate::error::IndexerError;
+use crate::models::NearblocksTxn;
+use crate::repositories::scan_cursor_repository;
+use crate::services::near_rpc;
+use crate::services::near_rpc::RpcClient;
+use crate::transaction_fetcher;
+use log::info;
+use mongodb::Database;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+/// How often the chain head is polled once the stream has caught up.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Upper bound on the blocks scanned per step, so a long catch-up saves progress as it goes.
+const MAX_BLOCKS_PER_STEP: u64 = 1000;
+
+/// Scan cursor of the stream, see `scan_cursor_repository`. Shared with `lake_stream`, so
+/// switching between the two resumes where the other stopped.
+pub const STREAM_CURSOR: &str = "stream";
+
+/// Follows the chain head over RPC and stores the staking transactions of every configured pool
+/// as blocks are produced, through the same analysis as the batch fetch. Blocks are polled from
+/// the RPC nodes already configured, so no AWS credentials are needed; see `lake_stream` for
+/// reading them from NEAR Lake instead. Starts at `from_block`, or after the last block the
+/// stream stored, or at the chain head.
+pub async fn run_stream(
+    config: Arc<Config>,
+    db: Database,
+    clients: Arc<(RpcClient, RpcClient)>,
+    from_block: Option<u64>,
+) -> Result<(), IndexerError> {
+    let (primary_client, secondary_client) = (&clients.0, &clients.1);
+
+    let mut next_block = match start_block(&db, from_block).await? {
+        Some(block) => block,
+        None => near_rpc::get_latest_block_height(primary_client, secondary_client).await?,
+    };
+    info!("Streaming transactions from block {}", next_block);
+
+    // The cursor stays short of the first transaction that failed analysis, so a restart
+    // retries it
+    let mut first_failed_block: Option<u64> = None;
+    loop {
+        let head = near_rpc::get_latest_block_height(primary_client, secondary_client).await?;
+        if head < next_block {
+            sleep(POLL_INTERVAL).await;
+            continue;
+        }
+
+        let to_block = head.min(next_block + MAX_BLOCKS_PER_STEP - 1);
+        let transactions = transaction_fetcher::scan_blocks(
+            &config,
+            primary_client,
+            secondary_client,
+            next_block,
+            to_block,
+            &config.validator_account_ids,
+        )
+        .await?;
+
+        let failed =
+            store_streamed(&config, &db, transactions, primary_client, secondary_client).await?;
+        if let Some(block) = failed {
+            first_failed_block = Some(first_failed_block.map_or(block, |first| first.min(block)));
+        }
+        if !config.dry_run {
+            scan_cursor_repository::save_scanned_height(
+                &db,
+                STREAM_CURSOR,
+                streamed_height(to_block, first_failed_block),
+            )
+            .await?;
+        }
+        info!("Streamed blocks {} to {}", next_block, to_block);
+        next_block = to_block + 1;
+    }
+}
+
+/// Block a stream starts at: `from_block`, else the one after the stream cursor. `None` when
+/// neither is set, to start at the chain head.
+pub async fn start_block(
+    db: &Database,
+    from_block: Option<u64>,
+) -> Result<Option<u64>, IndexerError> {
+    if from_block.is_some() {
+        return Ok(from_block);
+    }
+    let scanned_height = scan_cursor_repository::get_scanned_height(db, STREAM_CURSOR).await?;
+    Ok(resume_from(from_block, scanned_height))
+}
+
+fn resume_from(from_block: Option<u64>, scanned_height: Option<u64>) -> Option<u64> {
+    from_block.or(scanned_height.map(|height| height + 1))
+}
+
+/// Height the stream cursor is saved at after storing up to `to_block`. Held short of the first
+/// transaction that failed analysis, so a restart retries it.
+pub fn streamed_height(to_block: u64, first_failed_block: Option<u64>) -> u64 {
+    first_failed_block.map_or(to_block, |block| to_block.min(block.saturating_sub(1)))
+}
+
+/// Stores the streamed transactions of each configured pool. Returns the lowest block of a
+/// transaction that failed analysis, if any.
+pub async fn store_streamed(
+    config: &Config,
+    db: &Database,
+    transactions: Vec<NearblocksTxn>,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<Option<u64>, IndexerError> {
+    let mut first_failed_block: Option<u64> = None;
+    for validator_account_id in &config.validator_account_ids {
+        let pool_transactions: Vec<_> = transactions
+            .iter()
+            .filter(|tx| tx.receiver_account_id.as_ref() == Some(validator_account_id))
+            .cloned()
+            .collect();
+        if pool_transactions.is_empty() {
+            continue;
+        }
+        let pool_config = config.for_validator(validator_account_id);
+        let stored = transaction_fetcher::store_transactions(
+            &pool_config,
+            db,
+            pool_transactions,
+            primary_client,
+            secondary_client,
+        )
+        .await?;
+        if let Some(block) = stored.first_failed_block {
+            first_failed_block = Some(first_failed_block.map_or(block, |first| first.min(block)));
+        }
+    }
+    Ok(first_failed_block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_resumes_after_its_cursor_unless_a_start_block_is_given() {
+        assert_eq!(resume_from(None, Some(1_000)), Some(1_001));
+        assert_eq!(resume_from(Some(500), Some(1_000)), Some(500));
+        // Nothing streamed yet: start at the chain head
+        assert_eq!(resume_from(None, None), None);
+    }
+
+    #[test]
+    fn cursor_is_held_short_of_the_first_failed_block() {
+        assert_eq!(streamed_height(2_000, None), 2_000);
+        assert_eq!(streamed_height(2_000, Some(1_500)), 1_499);
+        // A failure carried over from an earlier step past the current one
+        assert_eq!(streamed_height(2_000, Some(2_500)), 2_000);
+    }
+}
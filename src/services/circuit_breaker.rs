@@ -0,0 +1,96 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A trial request is in flight; others keep skipping the endpoint until it resolves.
+    HalfOpen,
+}
+
+/// Skips an endpoint after `failure_threshold` consecutive failures. Once `cooldown` has passed
+/// a single trial request is let through: success closes the breaker, failure reopens it.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    state: BreakerState,
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            state: BreakerState::Closed { failures: 0 },
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+        }
+    }
+
+    /// Whether a request may be sent now. Moves an expired open breaker to half-open.
+    pub fn allow_request(&mut self) -> bool {
+        match self.state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if Instant::now() >= until => {
+                self.state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen => false,
+        }
+    }
+
+    pub fn on_success(&mut self) {
+        self.state = BreakerState::Closed { failures: 0 };
+    }
+
+    /// Records a failure and returns true if it opened the breaker.
+    pub fn on_failure(&mut self) -> bool {
+        let failures = match self.state {
+            BreakerState::Closed { failures } => failures + 1,
+            BreakerState::HalfOpen => self.failure_threshold,
+            BreakerState::Open { .. } => return false,
+        };
+        if failures >= self.failure_threshold {
+            self.state = BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            };
+            true
+        } else {
+            self.state = BreakerState::Closed { failures };
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let mut breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        assert!(!breaker.on_failure());
+        breaker.on_success();
+        assert!(!breaker.on_failure());
+        assert!(breaker.on_failure());
+        assert!(!breaker.allow_request());
+    }
+
+    #[test]
+    fn lets_one_trial_through_after_the_cooldown() {
+        let mut breaker = CircuitBreaker::new(1, Duration::ZERO);
+        assert!(breaker.on_failure());
+        assert!(breaker.allow_request());
+        assert!(!breaker.allow_request());
+
+        // A failed trial reopens it, a successful one closes it
+        assert!(breaker.on_failure());
+        assert!(breaker.allow_request());
+        breaker.on_success();
+        assert!(breaker.allow_request());
+        assert!(breaker.allow_request());
+    }
+}
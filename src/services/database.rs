@@ -1,12 +1,161 @@
+use crate::services::health;
 use log::info;
+use mongodb::bson::doc;
+use mongodb::options::{
+    Acknowledgment, ClientOptions, DatabaseOptions, ReadPreference, SelectionCriteria, WriteConcern,
+};
 use mongodb::{Client, Database};
 use std::env;
 
 pub async fn connect_to_database() -> mongodb::error::Result<Database> {
     let mongo_uri = env::var("MONGO_URI").expect("MONGO_URI must be set");
     let db_name = env::var("DB_NAME").expect("DB_NAME must be set");
-    let client = Client::with_uri_str(&mongo_uri).await?;
+    let mut client_options = ClientOptions::parse(&mongo_uri).await?;
+    // Unset variables keep whatever MONGO_URI (or the driver default) specifies.
+    if let Ok(value) = env::var("MONGO_WRITE_CONCERN") {
+        let write_concern = WriteConcern::builder()
+            .w(parse_acknowledgment(&value))
+            .build();
+        info!("Using MongoDB write concern: {:?}", write_concern);
+        client_options.write_concern = Some(write_concern);
+    }
+    if let Ok(value) = env::var("MONGO_MAX_POOL_SIZE") {
+        let max_pool_size = value
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid MONGO_MAX_POOL_SIZE: {}", value));
+        info!("Using MongoDB max pool size: {}", max_pool_size);
+        client_options.max_pool_size = Some(max_pool_size);
+    }
+    if let Ok(app_name) = env::var("MONGO_APP_NAME") {
+        client_options.app_name = Some(app_name);
+    }
+    connect(client_options, &db_name).await
+}
+
+async fn connect(client_options: ClientOptions, db_name: &str) -> mongodb::error::Result<Database> {
+    let client = Client::with_options(client_options)?;
+    // with_uri_str does not contact the server, so ping it to fail fast on a bad MONGO_URI.
+    client
+        .database("admin")
+        .run_command(doc! { "ping": 1 })
+        .await?;
     info!("Connected to MongoDB");
-    info!("a {}", db_name);
-    Ok(client.database(&db_name))
+
+    let db = client.database(db_name);
+    health::mark_mongo_ready();
+    Ok(db)
+}
+
+/// Handle on the same database for the read-only query and report commands, whose collection
+/// handles use MONGO_READ_PREFERENCE. The indexer's own reads stay on `db`, so they never see
+/// a stale secondary.
+pub fn for_reports(db: &Database) -> Database {
+    let read_preference = env::var("MONGO_READ_PREFERENCE").ok().map(|value| {
+        parse_read_preference(&value)
+            .unwrap_or_else(|| panic!("Invalid MONGO_READ_PREFERENCE: {}", value))
+    });
+    if let Some(read_preference) = &read_preference {
+        info!(
+            "Using MongoDB read preference for reports: {:?}",
+            read_preference
+        );
+    }
+    with_read_preference(db, read_preference)
+}
+
+fn with_read_preference(db: &Database, read_preference: Option<ReadPreference>) -> Database {
+    let options = DatabaseOptions::builder()
+        .selection_criteria(read_preference.map(SelectionCriteria::ReadPreference))
+        .build();
+    db.client().database_with_options(db.name(), options)
+}
+
+/// `majority`, a number of nodes, or a custom write concern tag.
+fn parse_acknowledgment(value: &str) -> Acknowledgment {
+    if value.eq_ignore_ascii_case("majority") {
+        Acknowledgment::Majority
+    } else if let Ok(nodes) = value.parse() {
+        Acknowledgment::Nodes(nodes)
+    } else {
+        Acknowledgment::Custom(value.to_string())
+    }
+}
+
+pub fn parse_read_preference(value: &str) -> Option<ReadPreference> {
+    match value.to_ascii_lowercase().as_str() {
+        "primary" => Some(ReadPreference::Primary),
+        "primarypreferred" => Some(ReadPreference::PrimaryPreferred { options: None }),
+        "secondary" => Some(ReadPreference::Secondary { options: None }),
+        "secondarypreferred" => Some(ReadPreference::SecondaryPreferred { options: None }),
+        "nearest" => Some(ReadPreference::Nearest { options: None }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DelegatorData;
+    use crate::repositories::collections;
+    use mongodb::error::ErrorKind;
+    use mongodb::options::ServerAddress;
+    use mongodb::Collection;
+    use std::time::Duration;
+
+    /// A client that is never connected; handles and their options don't need a server.
+    fn database() -> Database {
+        let options = ClientOptions::builder()
+            .hosts(vec![ServerAddress::Tcp {
+                host: "localhost".to_string(),
+                port: Some(27017),
+            }])
+            .build();
+        Client::with_options(options).unwrap().database("indexer")
+    }
+
+    #[tokio::test]
+    async fn report_reads_use_the_read_preference() {
+        let db = database();
+        let reports = with_read_preference(
+            &db,
+            Some(ReadPreference::SecondaryPreferred { options: None }),
+        );
+        let delegators: Collection<DelegatorData> = collections::delegators(&reports);
+        assert!(matches!(
+            delegators.selection_criteria(),
+            Some(SelectionCriteria::ReadPreference(
+                ReadPreference::SecondaryPreferred { .. }
+            ))
+        ));
+        assert_eq!(reports.name(), "indexer");
+    }
+
+    #[tokio::test]
+    async fn indexer_reads_stay_on_the_default() {
+        let db = database();
+        let delegators: Collection<DelegatorData> = collections::delegators(&db);
+        assert!(delegators.selection_criteria().is_none());
+    }
+
+    #[test]
+    fn read_preference_names_are_case_insensitive() {
+        assert!(matches!(
+            parse_read_preference("secondaryPreferred"),
+            Some(ReadPreference::SecondaryPreferred { .. })
+        ));
+        assert!(parse_read_preference("fastest").is_none());
+    }
+
+    #[tokio::test]
+    async fn unreachable_server_fails_the_connection() {
+        let options = ClientOptions::builder()
+            .hosts(vec![ServerAddress::Tcp {
+                host: "127.0.0.1".to_string(),
+                port: Some(9),
+            }])
+            .server_selection_timeout(Duration::from_millis(200))
+            .build();
+        let error = connect(options, "indexer").await.unwrap_err();
+        assert!(matches!(*error.kind, ErrorKind::ServerSelection { .. }));
+    }
 }
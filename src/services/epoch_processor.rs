@@ -1,31 +1,57 @@
-use crate::config::Config;
-use crate::models::{DelegatorData, Transaction};
-use crate::repositories::{delegator_repository, epoch_repository, validator_repository};
-use crate::services::near_rpc;
-use log::{info, warn};
+use crate::config::{BalanceSample, Config, PartialEpochApy, RewardNegativePolicy};
+use crate::error::IndexerError;
+use crate::models::{DelegatorData, EpochInfo, RewardEvent, Transaction};
+use crate::repositories::{
+    delegator_repository, epoch_repository, epoch_sync_repository, reward_event_repository,
+    validator_repository,
+};
+use crate::services::near_rpc::RpcClient;
+use crate::services::webhook::{self, EpochNotification};
+use crate::services::{error_summary, metrics, near_rpc, staking_parser, statsd, telemetry};
+use chrono::Utc;
+use futures::stream::{self, StreamExt};
+use log::{error, info, warn};
 use mongodb::Database;
-use near_jsonrpc_client::JsonRpcClient;
 use num_bigint::BigInt;
-use num_traits::Zero;
-use std::collections::HashMap;
+use num_traits::{ToPrimitive, Zero};
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::oneshot;
+use tracing::Instrument;
 
-const EPOCHS_PER_YEAR: u128 = 730; // 365 days * 2 epochs per day
+/// Each delegator's staked balance as sampled for an epoch, keyed by delegator id.
+pub type EpochStakes = HashMap<String, String>;
 
+/// Hands an epoch's balances to the next epoch processed in the same run. Epochs run
+/// concurrently, so the next one can't rely on finding this epoch's records in MongoDB yet.
+#[derive(Debug, Default)]
+pub struct StakeCarry {
+    pub previous: Option<oneshot::Receiver<EpochStakes>>,
+    pub next: Option<oneshot::Sender<EpochStakes>>,
+}
+
+/// Owner of each lockup contract looked up so far. Lockup owners don't change in practice,
+/// so the first lookup is reused for every epoch.
+static LOCKUP_OWNERS: LazyLock<Mutex<HashMap<String, Option<String>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Rewards as the change in stake not explained by transactions. May be negative, see
+/// `REWARD_NEGATIVE_POLICY`.
 fn calculate_rewards(
     current_stake: &str,
     previous_stake: Option<&String>,
     transaction_total: Option<&BigInt>,
-) -> String {
+) -> BigInt {
     let current = BigInt::from_str(current_stake).unwrap_or_else(|_| BigInt::zero());
     let previous = previous_stake
         .and_then(|s| BigInt::from_str(s).ok())
-        .unwrap_or_else(|| BigInt::zero());
-    let tx_total = transaction_total.cloned().unwrap_or_else(|| BigInt::zero());
+        .unwrap_or_else(BigInt::zero);
+    let tx_total = transaction_total.cloned().unwrap_or_else(BigInt::zero);
 
     // For first epoch with no previous stake
     if previous.is_zero() && !current.is_zero() {
-        return "0".to_string(); // First stake is not a reward
+        return BigInt::zero(); // First stake is not a reward
     }
 
     // Clone the values before the arithmetic operations
@@ -42,18 +68,70 @@ fn calculate_rewards(
             "Negative rewards calculated: {} = {} - ({} + {})",
             rewards, current_clone, previous_clone, tx_total_clone
         );
-        "0".to_string()
-    } else {
-        rewards.to_string()
     }
+    rewards
 }
 
-fn calculate_apy(rewards: &str, stake_amount: &str) -> u128 {
+/// Sums each delegator's `dist.stak` reward events. Transactions stored before rewards were
+/// split out carry them as the amount of a `distribute_staking` transaction.
+fn distributed_rewards(transactions: &[&Transaction]) -> HashMap<String, BigInt> {
+    let mut totals = HashMap::new();
+    for tx in transactions {
+        let reward = match &tx.reward_amount {
+            Some(reward_amount) => reward_amount,
+            None if tx.method == "distribute_staking" => &tx.amount,
+            None => continue,
+        };
+        if let Ok(amount) = BigInt::from_str(reward) {
+            *totals
+                .entry(tx.delegator_address.clone())
+                .or_insert_with(BigInt::zero) += amount;
+        }
+    }
+    totals
+}
+
+/// Whether `derived` is within `tolerance` (a fraction of `distributed`) of the event total.
+fn rewards_match(derived: &BigInt, distributed: &BigInt, tolerance: f64) -> bool {
+    let difference = (derived - distributed)
+        .magnitude()
+        .to_f64()
+        .unwrap_or(f64::MAX);
+    let allowed = distributed.magnitude().to_f64().unwrap_or(0.0) * tolerance;
+    difference <= allowed
+}
+
+/// Compares the balance-difference rewards with the epoch's reward events for every delegator
+/// that has events, warning on each divergence. Returns whether all of them matched.
+fn verify_rewards(
+    delegator_data: &HashMap<String, DelegatorData>,
+    epoch_transactions: &[&Transaction],
+    tolerance: f64,
+    epoch_id: &str,
+) -> bool {
+    let mut verified = true;
+    for (delegator_id, distributed) in distributed_rewards(epoch_transactions) {
+        let derived = delegator_data
+            .get(&delegator_id)
+            .and_then(|data| BigInt::from_str(&data.rewards).ok())
+            .unwrap_or_else(BigInt::zero);
+        if !rewards_match(&derived, &distributed, tolerance) {
+            warn!(
+                "Rewards for {} in epoch {} diverge from reward events: derived {}, distributed {}",
+                delegator_id, epoch_id, derived, distributed
+            );
+            verified = false;
+        }
+    }
+    verified
+}
+
+pub fn calculate_apy(rewards: &str, stake_amount: &str, epochs_per_year: f64) -> f64 {
     let rewards_big = BigInt::from_str(rewards).unwrap_or_else(|_| BigInt::zero());
     let stake_big = BigInt::from_str(stake_amount).unwrap_or_else(|_| BigInt::zero());
 
     if stake_big.is_zero() {
-        return 0;
+        return 0.0;
     }
 
     // Debug logging
@@ -62,19 +140,18 @@ fn calculate_apy(rewards: &str, stake_amount: &str) -> u128 {
         rewards_big, stake_big
     );
 
-    // Convert to u128, handling the yoctoNEAR conversion implicitly
-    // We'll keep the numbers in yoctoNEAR to maintain precision
-    let rewards_u128 = rewards_big.to_string().parse::<u128>().unwrap_or(0);
-    let stake_u128 = stake_big.to_string().parse::<u128>().unwrap_or(1);
+    // yoctoNEAR amounts exceed u64, so compute the ratio in floating point
+    let rewards_f64 = rewards_big.to_f64().unwrap_or(0.0);
+    let stake_f64 = stake_big.to_f64().unwrap_or(f64::MAX);
 
     // Calculate epoch rate
-    let epoch_rate = rewards_u128 / stake_u128;
+    let epoch_rate = rewards_f64 / stake_f64;
 
     // Annualize the rate
-    let annual_rate = epoch_rate * EPOCHS_PER_YEAR;
+    let annual_rate = epoch_rate * epochs_per_year;
 
     // Convert to percentage and round to 2 decimal places
-    let apy = (annual_rate * 100) / 100;
+    let apy = (annual_rate * 100.0 * 100.0).round() / 100.0;
 
     // Debug logging
     info!(
@@ -85,6 +162,249 @@ fn calculate_apy(rewards: &str, stake_amount: &str) -> u128 {
     apy
 }
 
+/// Fraction of a nominal epoch of `epoch_blocks` blocks that has elapsed between
+/// `epoch_start_millis` and `latest_block_millis`, at most 1. Zero until at least one block's
+/// worth of time has passed, which is too little to annualize.
+fn elapsed_epoch_fraction(
+    epoch_start_millis: u64,
+    latest_block_millis: u64,
+    epochs_per_year: f64,
+    epoch_blocks: u64,
+) -> f64 {
+    let epoch_duration_millis = 365.0 * 24.0 * 60.0 * 60.0 * 1000.0 / epochs_per_year;
+    let elapsed = latest_block_millis.saturating_sub(epoch_start_millis) as f64;
+    let fraction = (elapsed / epoch_duration_millis).min(1.0);
+    if fraction < 1.0 / epoch_blocks.max(1) as f64 {
+        0.0
+    } else {
+        fraction
+    }
+}
+
+/// Epochs-per-year factor used to annualize an epoch's rewards. Closed epochs use the
+/// nominal factor; the open epoch is scaled by how much of it has elapsed. `None` when the
+/// open epoch gets no APY yet: it is excluded, or has barely started.
+fn annualization_factor(
+    epochs_per_year: f64,
+    open_epoch_fraction: Option<f64>,
+    mode: PartialEpochApy,
+) -> Option<f64> {
+    match (open_epoch_fraction, mode) {
+        (None, _) => Some(epochs_per_year),
+        (Some(_), PartialEpochApy::Exclude) => None,
+        (Some(fraction), PartialEpochApy::Scale) if fraction > 0.0 => {
+            Some(epochs_per_year / fraction)
+        }
+        (Some(_), PartialEpochApy::Scale) => None,
+    }
+}
+
+/// Annualization factor for an epoch and whether its APY is provisional. The latest epoch is
+/// still running, so its rewards only cover part of an epoch.
+async fn epoch_annualization(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    epoch_timestamp: u64,
+    is_open_epoch: bool,
+    config: &Config,
+) -> Result<(Option<f64>, bool), IndexerError> {
+    let open_epoch_fraction = if is_open_epoch {
+        let latest_block = near_rpc::get_latest_block(primary_client, secondary_client).await?;
+        let latest_block_millis = latest_block.header.timestamp / 1_000_000;
+        Some(elapsed_epoch_fraction(
+            epoch_timestamp,
+            latest_block_millis,
+            config.epochs_per_year,
+            config.epoch_blocks,
+        ))
+    } else {
+        None
+    };
+    let epochs_per_year = annualization_factor(
+        config.epochs_per_year,
+        open_epoch_fraction,
+        config.partial_epoch_apy,
+    );
+    Ok((epochs_per_year, open_epoch_fraction.is_some()))
+}
+
+/// Rewards to store under `REWARD_NEGATIVE_POLICY`, and whether they are stored negative.
+fn apply_negative_policy(
+    rewards: BigInt,
+    policy: RewardNegativePolicy,
+    delegator_id: &str,
+    epoch_id: &str,
+) -> Result<(String, bool), IndexerError> {
+    if rewards >= BigInt::zero() {
+        return Ok((rewards.to_string(), false));
+    }
+    match policy {
+        RewardNegativePolicy::Zero => Ok(("0".to_string(), false)),
+        RewardNegativePolicy::Record => Ok((rewards.to_string(), true)),
+        RewardNegativePolicy::Error => Err(IndexerError::NegativeRewards {
+            delegator_id: delegator_id.to_string(),
+            epoch_id: epoch_id.to_string(),
+            rewards: rewards.to_string(),
+        }),
+    }
+}
+
+fn calculate_cumulative_rewards(previous_cumulative: Option<&str>, rewards: &str) -> String {
+    let previous = previous_cumulative
+        .and_then(|s| BigInt::from_str(s).ok())
+        .unwrap_or_else(BigInt::zero);
+    let rewards = BigInt::from_str(rewards).unwrap_or_else(|_| BigInt::zero());
+    (previous + rewards).to_string()
+}
+
+/// Chains `cumulative_rewards` through one delegator's records, oldest epoch first, starting
+/// from the cumulative value of their latest earlier record. Returns the records whose total
+/// changed.
+fn chain_cumulative_rewards(
+    previous_cumulative: Option<&str>,
+    records: Vec<DelegatorData>,
+) -> Vec<DelegatorData> {
+    let mut previous = previous_cumulative.map(str::to_string);
+    let mut changed = Vec::new();
+    for mut record in records {
+        let cumulative = calculate_cumulative_rewards(previous.as_deref(), &record.rewards);
+        if record.cumulative_rewards != cumulative {
+            record.cumulative_rewards = cumulative.clone();
+            changed.push(record);
+        }
+        previous = Some(cumulative);
+    }
+    changed
+}
+
+/// Recomputes `cumulative_rewards` of the pool's records from epoch `from_epoch` on, one
+/// delegator at a time in epoch order. Epochs are processed concurrently, so each one may have
+/// chained from a predecessor that wasn't saved yet, and reprocessing an epoch shifts every
+/// later total.
+pub async fn recompute_cumulative_rewards(
+    db: &Database,
+    config: &Config,
+    from_epoch: u64,
+) -> Result<(), mongodb::error::Error> {
+    let validator_account_id = &config.validator_account_id;
+    let previous_records =
+        delegator_repository::get_latest_before_epoch(db, validator_account_id, from_epoch).await?;
+    let records =
+        delegator_repository::get_pool_delegators_from(db, validator_account_id, from_epoch)
+            .await?;
+
+    let mut changed = Vec::new();
+    for history in records.chunk_by(|a, b| a.delegator_id == b.delegator_id) {
+        let previous_cumulative = previous_records
+            .get(&history[0].delegator_id)
+            .map(|record| record.cumulative_rewards.as_str());
+        changed.extend(chain_cumulative_rewards(
+            previous_cumulative,
+            history.to_vec(),
+        ));
+    }
+
+    if !changed.is_empty() {
+        info!(
+            "Updating cumulative rewards of {} records of {} from epoch {}",
+            changed.len(),
+            validator_account_id,
+            from_epoch
+        );
+        delegator_repository::save_delegator_data(db, &changed, config.delegator_batch_size, None)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Balance rewards are derived from, and the block it was read at: the end balance when
+/// sampled, as the epoch's rewards have accrued by then, else the start balance.
+fn sampled_balance<'a, T>(
+    start: Option<&'a T>,
+    end: Option<&'a T>,
+    start_block_height: u64,
+    end_block_height: u64,
+) -> (&'a T, u64) {
+    match (end, start) {
+        (Some(end), _) => (end, end_block_height),
+        (None, Some(start)) => (start, start_block_height),
+        (None, None) => unreachable!("at least one end of the epoch is sampled"),
+    }
+}
+
+/// Pairs each epoch index with a carry linking it to the adjacent epochs of the same run.
+/// Sorted by index, so an epoch waiting for its predecessor's balances never holds a slot the
+/// predecessor needs.
+fn stake_carries(mut indices: Vec<usize>) -> Vec<(usize, StakeCarry)> {
+    indices.sort_unstable();
+    indices.dedup();
+    let queued: HashSet<usize> = indices.iter().copied().collect();
+    let mut receivers = HashMap::new();
+    let mut carries = Vec::with_capacity(indices.len());
+    for index in indices {
+        let mut carry = StakeCarry {
+            previous: receivers.remove(&index),
+            next: None,
+        };
+        if queued.contains(&(index + 1)) {
+            let (sender, receiver) = oneshot::channel();
+            carry.next = Some(sender);
+            receivers.insert(index + 1, receiver);
+        }
+        carries.push((index, carry));
+    }
+    carries
+}
+
+/// Stake an epoch's rewards are measured from. Without transactions this epoch the balance
+/// only moves by auto-compounded rewards, so it continues from the previous epoch's balance:
+/// `carried` when that epoch was processed in the same run, else its stored record. With
+/// transactions, or without a previous epoch, it is the RPC balance at the delegator's last
+/// transaction.
+fn previous_stake<'a>(
+    account_id: &str,
+    epoch_number: u64,
+    has_transactions: bool,
+    carried: Option<&'a EpochStakes>,
+    previous_record: Option<&'a DelegatorData>,
+    prev_epoch_stakes: &'a HashMap<String, String>,
+) -> Option<&'a String> {
+    if !has_transactions {
+        if let Some(stake) = carried.and_then(|carried| carried.get(account_id)) {
+            return Some(stake);
+        }
+        if let Some(record) = previous_record.filter(|record| record.epoch + 1 == epoch_number) {
+            return Some(&record.auto_compounded_stake);
+        }
+    }
+    prev_epoch_stakes.get(account_id)
+}
+
+/// Owner of `account_id` at `block_height` when it is a lockup contract (see
+/// `Config::is_lockup_account`), through `LOCKUP_OWNERS`.
+async fn resolve_beneficiary(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    account_id: &str,
+    block_height: u64,
+    config: &Config,
+) -> Result<Option<String>, IndexerError> {
+    if !config.is_lockup_account(account_id) {
+        return Ok(None);
+    }
+    if let Some(owner) = LOCKUP_OWNERS.lock().unwrap().get(account_id) {
+        return Ok(owner.clone());
+    }
+    let owner =
+        near_rpc::get_lockup_owner(primary_client, secondary_client, account_id, block_height)
+            .await?;
+    LOCKUP_OWNERS
+        .lock()
+        .unwrap()
+        .insert(account_id.to_string(), owner.clone());
+    Ok(owner)
+}
+
 fn calculate_initial_stakes(transactions: &[&Transaction]) -> HashMap<String, BigInt> {
     let mut stakes = HashMap::new();
 
@@ -104,13 +424,13 @@ fn calculate_initial_stakes(transactions: &[&Transaction]) -> HashMap<String, Bi
             }
         };
 
-        let stake = stakes
-            .entry(delegator.clone())
-            .or_insert_with(|| BigInt::zero());
+        let stake = stakes.entry(delegator.clone()).or_insert_with(BigInt::zero);
 
         match tx.type_.as_str() {
             "stake" => *stake += &amount,
             "unstake" => *stake -= &amount,
+            // Withdrawn tokens already left the stake when they were unstaked
+            "withdraw" => {}
             _ => {
                 warn!(
                     "Unknown transaction type {} for transaction {}",
@@ -127,20 +447,58 @@ fn calculate_initial_stakes(transactions: &[&Transaction]) -> HashMap<String, Bi
     stakes
 }
 
-fn calculate_epoch_transaction_totals(transactions: &[&Transaction]) -> HashMap<String, BigInt> {
+/// A delegator's stake and unstake amounts within an epoch. `net` drives the reward math; the
+/// gross amounts show churn that nets out, such as unstaking and re-staking the same amount.
+#[derive(Debug, Clone, Default)]
+struct TransactionTotals {
+    net: BigInt,
+    gross_staked: BigInt,
+    gross_unstaked: BigInt,
+}
+
+/// Gross staked and unstaked amounts as stored on `DelegatorData`, zero without transactions.
+fn gross_amounts(totals: Option<&TransactionTotals>) -> (String, String) {
+    totals.map_or_else(
+        || ("0".to_string(), "0".to_string()),
+        |totals| {
+            (
+                totals.gross_staked.to_string(),
+                totals.gross_unstaked.to_string(),
+            )
+        },
+    )
+}
+
+fn calculate_epoch_transaction_totals(
+    transactions: &[&Transaction],
+) -> HashMap<String, TransactionTotals> {
     let mut totals = HashMap::new();
 
     for tx in transactions {
         let delegator = &tx.delegator_address;
-        let amount = BigInt::from_str(&tx.amount).unwrap_or_else(|_| BigInt::zero());
+        let amount = match BigInt::from_str(&tx.amount) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!(
+                    "Failed to parse amount {} for transaction {}: {}",
+                    tx.amount, tx.transaction_hash, e
+                );
+                continue;
+            }
+        };
 
-        let total = totals
-            .entry(delegator.clone())
-            .or_insert_with(|| BigInt::zero());
+        let total: &mut TransactionTotals = totals.entry(delegator.clone()).or_default();
 
         match tx.type_.as_str() {
-            "stake" => *total += amount,
-            "unstake" => *total -= amount,
+            "stake" => {
+                total.net += &amount;
+                total.gross_staked += amount;
+            }
+            "unstake" => {
+                total.net -= &amount;
+                total.gross_unstaked += amount;
+            }
+            // Withdrawals don't change the stake, see calculate_initial_stakes
             _ => {}
         }
     }
@@ -148,9 +506,107 @@ fn calculate_epoch_transaction_totals(transactions: &[&Transaction]) -> HashMap<
     totals
 }
 
+/// Indices of the epochs that have no stored data ending at their final block, oldest first.
+/// The last epoch is still open, so it is always included.
+pub fn unprocessed_epoch_indices(
+    epoch_data: &[EpochInfo],
+    stored_end_blocks: &HashMap<String, u64>,
+) -> Vec<usize> {
+    (0..epoch_data.len())
+        .filter(|&index| match epoch_data.get(index + 1) {
+            Some(next) => {
+                stored_end_blocks.get(&epoch_data[index].epoch_id) != Some(&(next.start_block - 1))
+            }
+            None => true,
+        })
+        .collect()
+}
+
+/// Processes the epochs at `indices` of the full, ordered epoch list in parallel.
+/// Epoch numbers and end blocks are derived from each epoch's position in that list.
+/// Returns the ids of the epochs that failed.
+pub async fn process_epochs(
+    config: Arc<Config>,
+    db: Database,
+    clients: Arc<(RpcClient, RpcClient)>,
+    epoch_data: Arc<Vec<EpochInfo>>,
+    indices: Vec<usize>,
+    transactions: Arc<Vec<Transaction>>,
+) -> Vec<String> {
+    let parallel_limit = config.parallel_limit;
+    let process_epoch_tasks = stream::iter(stake_carries(indices))
+        .map(move |(index, carry)| {
+            let clients = Arc::clone(&clients);
+            let transactions = Arc::clone(&transactions);
+            let epoch_data = Arc::clone(&epoch_data);
+            let db = db.clone();
+            let config = Arc::clone(&config);
+            async move {
+                let epoch = &epoch_data[index];
+                info!("Processing epoch {}: {:?}", index + 1, epoch);
+                let next_epoch = epoch_data.get(index + 1);
+
+                let result = async {
+                    // The final epoch is still open, so bound it by the current chain head.
+                    let end_block = match next_epoch {
+                        Some(next) => next.start_block - 1,
+                        None => near_rpc::get_settled_block_height(&clients.0, &clients.1).await?,
+                    };
+
+                    let span = tracing::info_span!(
+                        "process_delegator_data",
+                        epoch_id = %epoch.epoch_id,
+                        start_block = epoch.start_block,
+                        delegator_count = tracing::field::Empty,
+                        rpc_calls = tracing::field::Empty,
+                    );
+                    let (result, rpc_calls) = telemetry::count_rpc_calls(process_delegator_data(
+                        &clients.0,
+                        &clients.1,
+                        &config.validator_account_id,
+                        epoch.start_block,
+                        end_block,
+                        &transactions,
+                        index as u64 + 1,
+                        &epoch.epoch_id,
+                        epoch.timestamp.timestamp_millis() as u64,
+                        next_epoch.is_none(),
+                        carry,
+                        &db,
+                        &config,
+                    ))
+                    .instrument(span.clone())
+                    .await;
+                    span.record("rpc_calls", rpc_calls);
+                    result
+                }
+                .await;
+                (epoch.epoch_id.clone(), result)
+            }
+        })
+        .buffer_unordered(parallel_limit)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut failed_epochs = Vec::new();
+    for (epoch_id, result) in process_epoch_tasks {
+        match result {
+            Ok(()) => metrics::record_epoch_processed(),
+            Err(e) => {
+                error!("Error processing epoch: {:?}", e);
+                error_summary::record_error(format!("epoch {}", epoch_id), &e);
+                failed_epochs.push(epoch_id);
+            }
+        }
+    }
+
+    failed_epochs
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn process_delegator_data(
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
     validator_account_id: &str,
     start_block_height: u64,
     end_block_height: u64,
@@ -158,13 +614,16 @@ pub async fn process_delegator_data(
     epoch_number: u64,
     epoch_id: &str,
     epoch_timestamp: u64,
+    is_open_epoch: bool,
+    carry: StakeCarry,
     db: &Database,
     config: &Config,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), IndexerError> {
     info!("processDelegatorData called with: start_block_height: {}, end_block_height: {}, epoch_number: {}, epoch_id: {}, epoch_timestamp: {}",
           start_block_height, end_block_height, epoch_number, epoch_id, epoch_timestamp);
 
     let mut delegator_data = HashMap::new();
+    let mut reward_events = Vec::new();
     let mut total_stake = BigInt::zero();
     let mut total_rewards = BigInt::zero();
 
@@ -189,6 +648,7 @@ pub async fn process_delegator_data(
         start_block_height,
         transactions,
         db,
+        config,
     )
     .await?;
 
@@ -205,36 +665,128 @@ pub async fn process_delegator_data(
 
     let epoch_transaction_totals = calculate_epoch_transaction_totals(&epoch_transactions);
 
-    // Process accounts and calculate rewards/APY
-    let accounts = match near_rpc::get_accounts(
+    // Lifetime totals build on each delegator's latest record from an earlier epoch, so
+    // reprocessing an epoch never adds its own rewards twice
+    let previous_records =
+        delegator_repository::get_latest_before_epoch(db, validator_account_id, epoch_number)
+            .await?;
+
+    let (epochs_per_year, apy_provisional) = epoch_annualization(
         primary_client,
         secondary_client,
-        validator_account_id,
-        start_block_height,
+        epoch_timestamp,
+        is_open_epoch,
+        config,
     )
-    .await
-    {
-        Ok(accounts) => accounts,
-        Err(e) => return Err(e.into()),
+    .await?;
+
+    // Process accounts and calculate rewards/APY. Rewards accrue over the epoch, so with
+    // BALANCE_SAMPLE=end or both the rewards and auto-compounded stake use the end balance.
+    let start_balances = if config.balance_sample == BalanceSample::End {
+        None
+    } else {
+        Some(
+            get_staked_balances(
+                primary_client,
+                secondary_client,
+                validator_account_id,
+                start_block_height,
+                config,
+            )
+            .await?,
+        )
+    };
+    let end_balances = if config.balance_sample == BalanceSample::Start {
+        None
+    } else {
+        Some(
+            get_staked_balances(
+                primary_client,
+                secondary_client,
+                validator_account_id,
+                end_block_height,
+                config,
+            )
+            .await?,
+        )
+    };
+    let (balances, balance_block) = sampled_balance(
+        start_balances.as_ref(),
+        end_balances.as_ref(),
+        start_block_height,
+        end_block_height,
+    );
+    if let Some(next) = carry.next {
+        let _ = next.send(balances.clone());
+    }
+    // A predecessor that failed before sampling its balances drops the sender, and its
+    // stored record, if any, is used instead
+    let carried = match carry.previous {
+        Some(previous) => previous.await.ok(),
+        None => None,
     };
 
-    for account in accounts {
-        let account_id = account["account_id"].as_str().unwrap().to_string();
-        let staked_balance = account["staked_balance"].as_str().unwrap().to_string();
+    for (account_id, staked_balance) in accounts_to_process(balances, config.include_zero_stake) {
+        let account_id = account_id.clone();
+        let staked_balance = staked_balance.clone();
 
         let initial_stake = initial_stakes
             .get(&account_id)
             .cloned()
-            .unwrap_or_else(|| BigInt::zero())
+            .unwrap_or_else(BigInt::zero)
             .to_string();
 
-        let rewards = calculate_rewards(
-            &staked_balance,
-            prev_epoch_stakes.get(&account_id),
-            epoch_transaction_totals.get(&account_id),
+        let previous_record = previous_records.get(&account_id);
+        let transaction_totals = epoch_transaction_totals.get(&account_id);
+        let transaction_total = transaction_totals.map(|totals| &totals.net);
+        let (gross_staked, gross_unstaked) = gross_amounts(transaction_totals);
+        let previous_stake = previous_stake(
+            &account_id,
+            epoch_number,
+            transaction_total.is_some(),
+            carried.as_ref(),
+            previous_record,
+            &prev_epoch_stakes,
+        );
+        let rewards = calculate_rewards(&staked_balance, previous_stake, transaction_total);
+        if config.record_reward_events {
+            reward_events.push(RewardEvent {
+                delegator_id: account_id.clone(),
+                validator_account_id: validator_account_id.to_string(),
+                epoch: epoch_number,
+                epoch_id: epoch_id.to_string(),
+                current_stake: staked_balance.clone(),
+                previous_stake: previous_stake.cloned(),
+                tx_total: transaction_total
+                    .cloned()
+                    .unwrap_or_else(BigInt::zero)
+                    .to_string(),
+                rewards: rewards.to_string(),
+                recorded_at: Utc::now(),
+            });
+        }
+        let (rewards, negative_rewards) = apply_negative_policy(
+            rewards,
+            config.reward_negative_policy,
+            &account_id,
+            epoch_id,
+        )?;
+
+        let apy = epochs_per_year
+            .map(|epochs_per_year| calculate_apy(&rewards, &staked_balance, epochs_per_year));
+        let cumulative_rewards = calculate_cumulative_rewards(
+            previous_record.map(|record| record.cumulative_rewards.as_str()),
+            &rewards,
         );
 
-        let apy = calculate_apy(&rewards, &staked_balance);
+        let beneficiary_id = resolve_beneficiary(
+            primary_client,
+            secondary_client,
+            &account_id,
+            balance_block,
+            config,
+        )
+        .await?;
 
         total_stake += BigInt::from_str(&staked_balance).unwrap_or_else(|_| BigInt::zero());
         total_rewards += BigInt::from_str(&rewards).unwrap_or_else(|_| BigInt::zero());
@@ -250,62 +802,401 @@ pub async fn process_delegator_data(
                 timestamp: epoch_timestamp,
                 initial_stake,
                 auto_compounded_stake: staked_balance,
-                last_update_block: start_block_height,
+                last_update_block: balance_block,
                 epoch_id: epoch_id.to_string(),
                 rewards,
-                apy: apy.to_string(),
+                apy,
+                cumulative_rewards,
+                stake_at_start: start_balances
+                    .as_ref()
+                    .and_then(|balances| balances.get(&account_id).cloned()),
+                stake_at_end: end_balances
+                    .as_ref()
+                    .and_then(|balances| balances.get(&account_id).cloned()),
+                negative_rewards,
+                beneficiary_id,
+                gross_staked,
+                gross_unstaked,
             },
         );
     }
 
-    // Calculate validator-wide APY
-    let validator_apy = calculate_apy(&total_rewards.to_string(), &total_stake.to_string());
+    tracing::Span::current().record("delegator_count", delegator_data.len());
 
-    // Save all data
-    epoch_repository::save_epoch_data(
-        db,
-        epoch_number,
-        epoch_id,
-        &delegator_data,
-        validator_account_id,
-        start_block_height,
-        end_block_height,
-        &epoch_transactions,
-        epoch_timestamp,
-    )
-    .await?;
+    let rewards_verified = config
+        .rewards_verify_tolerance
+        .map(|tolerance| verify_rewards(&delegator_data, &epoch_transactions, tolerance, epoch_id));
 
-    validator_repository::save_validator_metrics(
-        db,
+    // Calculate validator-wide APY. Balances are stale for epochs the validator was kicked
+    // out of the active set, so no APY is reported for them.
+    let kicked_out = is_kicked_out(
+        primary_client,
+        secondary_client,
         validator_account_id,
-        epoch_number,
         epoch_id,
-        &delegator_data,
-        epoch_timestamp,
-        validator_apy.to_string(),
+        &config.http_client,
     )
-    .await?;
+    .await;
+    let validator_apy = if kicked_out {
+        warn!(
+            "Validator {} is not in the active set for epoch {}, skipping APY",
+            validator_account_id, epoch_id
+        );
+        None
+    } else {
+        epochs_per_year.map(|epochs_per_year| {
+            calculate_apy(
+                &total_rewards.to_string(),
+                &total_stake.to_string(),
+                epochs_per_year,
+            )
+        })
+    };
+
+    // A node that just restarted can return an empty or partial account list; saving it
+    // would overwrite a good epoch with zero delegators and stake
+    if below_thresholds(config, delegator_data.len(), &total_stake) {
+        warn!(
+            "Epoch {} (ID: {}) has {} delegators and total stake {}, below MIN_DELEGATORS {} or MIN_TOTAL_STAKE {}, not saving it",
+            epoch_number,
+            epoch_id,
+            delegator_data.len(),
+            total_stake,
+            config.min_delegators,
+            config.min_total_stake
+        );
+        return Ok(());
+    }
+
+    if config.dry_run {
+        info!(
+            "Dry run: epoch {} (ID: {}) - {} delegators, total stake {}, total rewards {}, validator APY {:?}%",
+            epoch_number,
+            epoch_id,
+            delegator_data.len(),
+            total_stake,
+            total_rewards,
+            validator_apy
+        );
+        return Ok(());
+    }
 
-    let delegator_data_vec: Vec<DelegatorData> = delegator_data.values().cloned().collect();
-    delegator_repository::save_delegator_data(db, &delegator_data_vec, config.delegator_batch_size)
+    // Save all data. With MONGO_TRANSACTIONS the three writes commit or abort together, so a
+    // crash can't leave an epoch with metrics but no delegator rows.
+    let mut session = if config.mongo_transactions {
+        let mut session = db.client().start_session().await?;
+        session.start_transaction().await?;
+        Some(session)
+    } else {
+        None
+    };
+
+    let delegator_data_vec = sorted_records(&delegator_data);
+    let write_result = async {
+        epoch_repository::save_epoch_data(
+            db,
+            epoch_number,
+            epoch_id,
+            &delegator_data,
+            validator_account_id,
+            start_block_height,
+            end_block_height,
+            &epoch_transactions,
+            epoch_timestamp,
+            rewards_verified,
+            session.as_mut(),
+        )
         .await?;
 
+        validator_repository::save_validator_metrics(
+            db,
+            validator_account_id,
+            epoch_number,
+            epoch_id,
+            &delegator_data,
+            epoch_timestamp,
+            validator_apy,
+            apy_provisional,
+            kicked_out,
+            config.validator_history_limit,
+            session.as_mut(),
+        )
+        .await?;
+
+        delegator_repository::save_delegator_data(
+            db,
+            &delegator_data_vec,
+            config.delegator_batch_size,
+            session.as_mut(),
+        )
+        .await
+    }
+    .await;
+
+    if let Some(session) = session.as_mut() {
+        match &write_result {
+            Ok(()) => session.commit_transaction().await?,
+            Err(_) => {
+                if let Err(e) = session.abort_transaction().await {
+                    warn!(
+                        "Failed to abort transaction for epoch {}: {:?}",
+                        epoch_id, e
+                    );
+                }
+            }
+        }
+    }
+    write_result?;
+
+    // Outside the epoch transaction: the events are an append-only trail of what was computed
+    for event in &reward_events {
+        reward_event_repository::record_reward_event(db, event).await?;
+    }
+
     info!(
-        "Processed epoch {} (ID: {}). Validator APY: {}%",
+        "Processed epoch {} (ID: {}). Validator APY: {:?}%",
         epoch_number, epoch_id, validator_apy
     );
 
+    let pool = statsd::pool_segment(validator_account_id);
+    statsd::count(&format!("{}.epochs_processed", pool), 1);
+    if let Some(apy) = validator_apy {
+        statsd::gauge(&format!("{}.validator_apy", pool), apy);
+    }
+    // In NEAR rather than yoctoNEAR, which doesn't fit a float gauge exactly
+    statsd::gauge(
+        &format!("{}.total_staked", pool),
+        total_stake.to_f64().unwrap_or(0.0) / 1e24,
+    );
+
+    if let Some(webhook_url) = &config.webhook_url {
+        webhook::notify_epoch(
+            config.http_client.clone(),
+            webhook_url,
+            EpochNotification {
+                validator_account_id: validator_account_id.to_string(),
+                epoch_id: epoch_id.to_string(),
+                epoch: epoch_number,
+                apy: validator_apy,
+                total_staked: total_stake.to_string(),
+            },
+        );
+    }
+
     Ok(())
 }
 
+/// Whether the validator was missing from the epoch's active set. An unavailable validator
+/// set is treated as active so a flaky RPC doesn't drop APY for the epoch.
+async fn is_kicked_out(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    validator_account_id: &str,
+    epoch_id: &str,
+    http_client: &reqwest::Client,
+) -> bool {
+    match near_rpc::get_validators_info(
+        primary_client,
+        secondary_client,
+        Some(epoch_id),
+        http_client,
+    )
+    .await
+    {
+        Ok(validators_info) => {
+            match near_rpc::is_current_validator(&validators_info, validator_account_id) {
+                Some(is_current) => !is_current,
+                None => {
+                    warn!(
+                        "No validator set returned for epoch {}: {}",
+                        epoch_id, validators_info
+                    );
+                    false
+                }
+            }
+        }
+        Err(e) => {
+            warn!("Failed to fetch validators for epoch {}: {:?}", epoch_id, e);
+            false
+        }
+    }
+}
+
+/// Recomputes one delegator's record for an epoch from that account's own balance, without
+/// reading the rest of the pool. Mirrors the per-delegator part of `process_delegator_data`;
+/// `previous_record` is the delegator's latest record from an earlier epoch. `None` if the
+/// account had no stake and no transactions in the epoch.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_single_delegator(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    delegator_id: &str,
+    epoch: &EpochInfo,
+    epoch_number: u64,
+    end_block_height: u64,
+    is_open_epoch: bool,
+    transactions: &[Transaction],
+    previous_record: Option<&DelegatorData>,
+    config: &Config,
+) -> Result<Option<DelegatorData>, IndexerError> {
+    let validator_account_id = &config.validator_account_id;
+    let start_block_height = epoch.start_block;
+    let epoch_timestamp = epoch.timestamp.timestamp_millis() as u64;
+
+    let epoch_transactions: Vec<_> = transactions
+        .iter()
+        .filter(|tx| {
+            tx.delegator_address == delegator_id
+                && tx.block_height >= start_block_height
+                && tx.block_height <= end_block_height
+        })
+        .collect();
+    let transaction_totals =
+        calculate_epoch_transaction_totals(&epoch_transactions).remove(delegator_id);
+    let (gross_staked, gross_unstaked) = gross_amounts(transaction_totals.as_ref());
+    let transaction_total = transaction_totals.map(|totals| totals.net);
+    let initial_stake = calculate_initial_stakes(&epoch_transactions)
+        .remove(delegator_id)
+        .unwrap_or_else(BigInt::zero)
+        .to_string();
+
+    let start_balance = if config.balance_sample == BalanceSample::End {
+        None
+    } else {
+        Some(
+            near_rpc::get_account_stake_balance(
+                primary_client,
+                secondary_client,
+                validator_account_id,
+                delegator_id,
+                start_block_height,
+            )
+            .await?,
+        )
+    };
+    let end_balance = if config.balance_sample == BalanceSample::Start {
+        None
+    } else {
+        Some(
+            near_rpc::get_account_stake_balance(
+                primary_client,
+                secondary_client,
+                validator_account_id,
+                delegator_id,
+                end_block_height,
+            )
+            .await?,
+        )
+    };
+    let (staked_balance, balance_block) = sampled_balance(
+        start_balance.as_ref(),
+        end_balance.as_ref(),
+        start_block_height,
+        end_block_height,
+    );
+    let staked_balance = staked_balance.clone();
+    if staked_balance == "0" && (transaction_total.is_none() || !config.include_zero_stake) {
+        return Ok(None);
+    }
+
+    // Same previous stake as `process_delegator_data`: the stored stake when carried over
+    // from the epoch before, otherwise the balance at the pool's last transaction before it
+    let previous_stake = match previous_record {
+        Some(record) if transaction_total.is_none() && record.epoch + 1 == epoch_number => {
+            Some(record.auto_compounded_stake.clone())
+        }
+        _ => {
+            let prev_block = transactions
+                .iter()
+                .filter(|tx| tx.block_height < start_block_height)
+                .map(|tx| tx.block_height)
+                .max()
+                .unwrap_or(0);
+            if prev_block == 0 {
+                None
+            } else {
+                Some(
+                    near_rpc::get_account_stake_balance(
+                        primary_client,
+                        secondary_client,
+                        validator_account_id,
+                        delegator_id,
+                        prev_block,
+                    )
+                    .await?,
+                )
+            }
+        }
+    };
+
+    let rewards = calculate_rewards(
+        &staked_balance,
+        previous_stake.as_ref(),
+        transaction_total.as_ref(),
+    );
+    let (rewards, negative_rewards) = apply_negative_policy(
+        rewards,
+        config.reward_negative_policy,
+        delegator_id,
+        &epoch.epoch_id,
+    )?;
+
+    let (epochs_per_year, _) = epoch_annualization(
+        primary_client,
+        secondary_client,
+        epoch_timestamp,
+        is_open_epoch,
+        config,
+    )
+    .await?;
+    let apy = epochs_per_year
+        .map(|epochs_per_year| calculate_apy(&rewards, &staked_balance, epochs_per_year));
+    let cumulative_rewards = calculate_cumulative_rewards(
+        previous_record.map(|record| record.cumulative_rewards.as_str()),
+        &rewards,
+    );
+
+    let beneficiary_id = resolve_beneficiary(
+        primary_client,
+        secondary_client,
+        delegator_id,
+        balance_block,
+        config,
+    )
+    .await?;
+
+    Ok(Some(DelegatorData {
+        delegator_id: delegator_id.to_string(),
+        validator_account_id: validator_account_id.to_string(),
+        epoch: epoch_number,
+        start_block_height,
+        end_block_height,
+        timestamp: epoch_timestamp,
+        initial_stake,
+        auto_compounded_stake: staked_balance,
+        last_update_block: balance_block,
+        epoch_id: epoch.epoch_id.clone(),
+        rewards,
+        apy,
+        cumulative_rewards,
+        stake_at_start: start_balance,
+        stake_at_end: end_balance,
+        negative_rewards,
+        beneficiary_id,
+        gross_staked,
+        gross_unstaked,
+    }))
+}
+
 async fn get_previous_epoch_data(
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
     validator_account_id: &str,
     current_start_block: u64,
     transactions: &[Transaction],
     _db: &Database,
-) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    config: &Config,
+) -> Result<HashMap<String, String>, IndexerError> {
     // Find the first transaction before the current epoch start
     let prev_block = transactions
         .iter()
@@ -318,20 +1209,668 @@ async fn get_previous_epoch_data(
         return Ok(HashMap::new());
     }
 
-    let accounts = near_rpc::get_accounts(
+    get_staked_balances(
         primary_client,
         secondary_client,
         validator_account_id,
         prev_block,
+        config,
+    )
+    .await
+}
+
+/// Staked balance of every delegator of the pool at `block_height`. Accounts without an
+/// `account_id` or `staked_balance` are logged and skipped; more than
+/// `MAX_MALFORMED_ACCOUNTS` of them fails the epoch.
+async fn get_staked_balances(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    validator_account_id: &str,
+    block_height: u64,
+    config: &Config,
+) -> Result<HashMap<String, String>, IndexerError> {
+    let accounts = near_rpc::get_accounts(
+        primary_client,
+        secondary_client,
+        validator_account_id,
+        block_height,
+        config.accounts_page_size,
     )
     .await?;
+    account_balances(
+        accounts,
+        validator_account_id,
+        block_height,
+        config.max_malformed_accounts,
+    )
+}
 
-    let mut prev_stakes = HashMap::new();
+/// Balances of `get_accounts` entries, skipping malformed ones unless there are more than
+/// `max_malformed` of them.
+fn account_balances(
+    accounts: Vec<serde_json::Value>,
+    validator_account_id: &str,
+    block_height: u64,
+    max_malformed: f64,
+) -> Result<HashMap<String, String>, IndexerError> {
+    let total = accounts.len();
+    let mut balances = HashMap::new();
+    let mut malformed = 0;
     for account in accounts {
-        let account_id = account["account_id"].as_str().unwrap().to_string();
-        let staked_balance = account["staked_balance"].as_str().unwrap().to_string();
-        prev_stakes.insert(account_id, staked_balance);
+        match parse_account_balance(&account) {
+            Some((account_id, staked_balance)) => {
+                balances.insert(account_id, staked_balance);
+            }
+            None => {
+                warn!(
+                    "Skipping malformed account of {} at block {}: {}",
+                    validator_account_id, block_height, account
+                );
+                malformed += 1;
+            }
+        }
+    }
+
+    if malformed > 0 && malformed as f64 > total as f64 * max_malformed {
+        return Err(IndexerError::MalformedAccounts {
+            validator_account_id: validator_account_id.to_string(),
+            block_height,
+            malformed,
+            total,
+        });
     }
 
-    Ok(prev_stakes)
+    Ok(balances)
+}
+
+/// Reads an account's id and staked balance, also accepting the balance as a number or
+/// nested under `account`, as some staking contract versions return it.
+fn parse_account_balance(account: &serde_json::Value) -> Option<(String, String)> {
+    let account_id = account["account_id"].as_str()?;
+    let staked_balance = [
+        &account["staked_balance"],
+        &account["account"]["staked_balance"],
+    ]
+    .into_iter()
+    .find_map(|value| staking_parser::parse_balance_field(value).ok())?;
+    Some((account_id.to_string(), staked_balance))
+}
+
+/// Whether `epoch`'s boundary still has to be checked against the chain on each run.
+fn needs_boundary_check(epoch: &EpochInfo) -> bool {
+    !epoch.finalized
+}
+
+/// Whether `epoch` started more than `epoch_blocks` before `current_block`, so its boundary
+/// is too deep to move. Only the start is used: the latest epoch is still open, and the end
+/// stored with it is just the tip at the time it was synced.
+fn boundary_settled(epoch: &EpochInfo, epoch_blocks: u64, current_block: u64) -> bool {
+    epoch.start_block + epoch_blocks <= current_block
+}
+
+/// Re-verifies the latest stored epoch unless it is finalized: its start block must still
+/// have the stored hash and epoch id, and the block before it must belong to another epoch.
+/// Only the latest epoch can start near the chain tip, so it's the only one a reorg or late
+/// finalization could have moved. Once it started more than `epoch_blocks` before the tip and
+/// still checks out, it is marked finalized and not checked again.
+pub async fn verify_latest_epoch(
+    db: &Database,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    epoch_blocks: u64,
+    dry_run: bool,
+) -> Result<bool, IndexerError> {
+    let Some(latest) = epoch_sync_repository::get_latest_epoch_sync(db).await? else {
+        return Ok(true);
+    };
+    if !needs_boundary_check(&latest) {
+        return Ok(true);
+    }
+
+    let verified = epoch_hash_matches(&latest, primary_client, secondary_client).await?
+        && epoch_boundary_matches(&latest, primary_client, secondary_client).await?;
+    let current_block = near_rpc::get_latest_block_height(primary_client, secondary_client).await?;
+    if verified && boundary_settled(&latest, epoch_blocks, current_block) && !dry_run {
+        info!(
+            "Epoch {} is old enough, marking it finalized",
+            latest.epoch_id
+        );
+        epoch_sync_repository::mark_finalized(db, &latest.epoch_id).await?;
+    }
+    Ok(verified)
+}
+
+/// Whether the epoch still starts at its stored start block.
+async fn epoch_boundary_matches(
+    epoch: &EpochInfo,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<bool, IndexerError> {
+    let start_epoch_id =
+        near_rpc::get_block_by_height(primary_client, secondary_client, epoch.start_block)
+            .await?
+            .map(|block| block.header.epoch_id.to_string());
+    if start_epoch_id.as_deref() != Some(epoch.epoch_id.as_str()) {
+        warn!(
+            "Block {} belongs to epoch {:?}, not the stored epoch {}",
+            epoch.start_block, start_epoch_id, epoch.epoch_id
+        );
+        return Ok(false);
+    }
+
+    let Some(previous_height) = epoch.start_block.checked_sub(1) else {
+        return Ok(true);
+    };
+    let previous_epoch_id =
+        near_rpc::get_block_by_height(primary_client, secondary_client, previous_height)
+            .await?
+            .map(|block| block.header.epoch_id.to_string());
+    if previous_epoch_id.as_deref() == Some(epoch.epoch_id.as_str()) {
+        warn!(
+            "Block {} also belongs to epoch {}, so it starts before the stored block {}",
+            previous_height, epoch.epoch_id, epoch.start_block
+        );
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Whether the epoch's start block hash is still the canonical block at that height. Epochs
+/// stored without a hash are treated as matching.
+async fn epoch_hash_matches(
+    latest: &EpochInfo,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<bool, IndexerError> {
+    let Some(stored_hash) = &latest.block_hash else {
+        return Ok(true);
+    };
+
+    let canonical_hash =
+        near_rpc::get_block_by_height(primary_client, secondary_client, latest.start_block)
+            .await?
+            .map(|block| block.header.hash.to_string());
+    let matches = canonical_hash.as_deref() == Some(stored_hash.as_str());
+    if !matches {
+        warn!(
+            "Epoch {} starts at block {} with hash {}, but the canonical block has hash {:?}",
+            latest.epoch_id, latest.start_block, stored_hash, canonical_hash
+        );
+    }
+    Ok(matches)
+}
+
+/// Balances in account order rather than RPC or hash order, so logs and writes of two runs
+/// over the same epoch can be diffed.
+fn sorted_accounts(balances: &EpochStakes) -> Vec<(&String, &String)> {
+    let mut accounts: Vec<_> = balances.iter().collect();
+    accounts.sort_unstable_by_key(|(account_id, _)| *account_id);
+    accounts
+}
+
+/// Accounts to process in account order, without those with no staked balance unless
+/// INCLUDE_ZERO_STAKE is on.
+fn accounts_to_process(
+    balances: &EpochStakes,
+    include_zero_stake: bool,
+) -> Vec<(&String, &String)> {
+    let mut accounts = sorted_accounts(balances);
+    if !include_zero_stake {
+        let count = accounts.len();
+        accounts.retain(|(_, staked_balance)| staked_balance.as_str() != "0");
+        info!(
+            "Skipping {} accounts with no staked balance",
+            count - accounts.len()
+        );
+    }
+    accounts
+}
+
+/// Delegator records in account order, see `sorted_accounts`.
+fn sorted_records(delegator_data: &HashMap<String, DelegatorData>) -> Vec<DelegatorData> {
+    let mut records: Vec<DelegatorData> = delegator_data.values().cloned().collect();
+    records.sort_unstable_by(|a, b| a.delegator_id.cmp(&b.delegator_id));
+    records
+}
+
+/// Whether an epoch has fewer delegators or less total stake than MIN_DELEGATORS and
+/// MIN_TOTAL_STAKE allow.
+fn below_thresholds(config: &Config, delegators: usize, total_stake: &BigInt) -> bool {
+    delegators < config.min_delegators || *total_stake < config.min_total_stake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    const EPOCH_MILLIS: u64 = 43_200_000;
+
+    #[test]
+    fn half_elapsed_epoch_doubles_the_annualization_factor() {
+        let fraction = elapsed_epoch_fraction(0, EPOCH_MILLIS / 2, 730.0, 43_200);
+        assert!((fraction - 0.5).abs() < 1e-9);
+        let factor = annualization_factor(730.0, Some(fraction), PartialEpochApy::Scale).unwrap();
+        assert!((factor - 1460.0).abs() < 1e-6);
+
+        // Half an epoch's rewards annualized over the elapsed half match a full epoch's APY
+        let full_epoch_apy = calculate_apy("100", "100000", 730.0);
+        let half_epoch_apy = calculate_apy("50", "100000", factor);
+        assert!((half_epoch_apy - full_epoch_apy).abs() < 0.01);
+    }
+
+    #[test]
+    fn elapsed_fraction_is_capped_at_a_full_epoch() {
+        assert_eq!(
+            elapsed_epoch_fraction(0, EPOCH_MILLIS * 3, 730.0, 43_200),
+            1.0
+        );
+    }
+
+    #[test]
+    fn epoch_under_one_block_old_has_no_apy_yet() {
+        let fraction = elapsed_epoch_fraction(1_000, 1_000, 730.0, 43_200);
+        assert_eq!(fraction, 0.0);
+        assert_eq!(
+            annualization_factor(730.0, Some(fraction), PartialEpochApy::Scale),
+            None
+        );
+    }
+
+    fn epoch(epoch_id: &str, start_block: u64, end_block: Option<u64>) -> EpochInfo {
+        EpochInfo {
+            start_block,
+            end_block,
+            epoch_id: epoch_id.to_string(),
+            timestamp: Utc::now(),
+            block_hash: None,
+            finalized: false,
+            boundary_verified: true,
+        }
+    }
+
+    #[test]
+    fn near_tip_epoch_is_reverified_until_settled() {
+        // Stored while open, so its end block is the tip at the time, not its real end
+        let near_tip = epoch("near-tip", 1_000_000, Some(1_000_100));
+        assert!(needs_boundary_check(&near_tip));
+        assert!(!boundary_settled(&near_tip, 43_200, 1_000_500));
+        assert!(boundary_settled(&near_tip, 43_200, 1_043_200));
+    }
+
+    #[test]
+    fn finalized_epoch_is_not_checked_again() {
+        let old = EpochInfo {
+            finalized: true,
+            ..epoch("old", 100, Some(43_299))
+        };
+        assert!(!needs_boundary_check(&old));
+    }
+
+    fn record(epoch: u64, rewards: &str) -> DelegatorData {
+        DelegatorData {
+            delegator_id: "alice.near".to_string(),
+            validator_account_id: "pool.poolv1.near".to_string(),
+            epoch,
+            start_block_height: epoch * 43_200,
+            end_block_height: (epoch + 1) * 43_200 - 1,
+            timestamp: 0,
+            initial_stake: "0".to_string(),
+            auto_compounded_stake: "0".to_string(),
+            last_update_block: 0,
+            epoch_id: format!("epoch-{}", epoch),
+            rewards: rewards.to_string(),
+            apy: None,
+            cumulative_rewards: String::new(),
+            stake_at_start: None,
+            stake_at_end: None,
+            negative_rewards: false,
+            beneficiary_id: None,
+            gross_staked: String::new(),
+            gross_unstaked: String::new(),
+        }
+    }
+
+    fn cumulative(records: &[DelegatorData]) -> Vec<&str> {
+        records
+            .iter()
+            .map(|record| record.cumulative_rewards.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn cumulative_rewards_run_across_three_epochs() {
+        let records = vec![record(1, "100"), record(2, "250"), record(3, "50")];
+        let chained = chain_cumulative_rewards(None, records);
+        assert_eq!(cumulative(&chained), ["100", "350", "400"]);
+
+        // Chaining again changes nothing
+        assert!(chain_cumulative_rewards(None, chained).is_empty());
+    }
+
+    #[test]
+    fn reprocessed_epoch_shifts_later_totals() {
+        let mut records =
+            chain_cumulative_rewards(Some("1000"), vec![record(2, "10"), record(3, "20")]);
+        assert_eq!(cumulative(&records), ["1010", "1030"]);
+
+        // Epoch 1 was reprocessed and its total went from 1000 to 1500
+        records[0].rewards = "10".to_string();
+        let changed = chain_cumulative_rewards(Some("1500"), records);
+        assert_eq!(cumulative(&changed), ["1510", "1530"]);
+    }
+
+    #[test]
+    fn epochs_stored_before_a_boundary_resync_are_requeued() {
+        let epochs = vec![
+            epoch("a", 100, None),
+            epoch("b", 200, None),
+            epoch("c", 300, None),
+        ];
+        let stored_end_blocks = HashMap::from([
+            ("a".to_string(), 199),
+            // Stored before the boundary of "c" was resynced
+            ("b".to_string(), 250),
+        ]);
+        // The open epoch is always reprocessed
+        assert_eq!(
+            unprocessed_epoch_indices(&epochs, &stored_end_blocks),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn negative_rewards_follow_the_policy() {
+        let rewards = BigInt::from(-5);
+        assert_eq!(
+            apply_negative_policy(rewards.clone(), RewardNegativePolicy::Zero, "a", "e").unwrap(),
+            ("0".to_string(), false)
+        );
+        assert_eq!(
+            apply_negative_policy(rewards.clone(), RewardNegativePolicy::Record, "a", "e").unwrap(),
+            ("-5".to_string(), true)
+        );
+        assert!(matches!(
+            apply_negative_policy(rewards, RewardNegativePolicy::Error, "a", "e"),
+            Err(IndexerError::NegativeRewards { .. })
+        ));
+        assert_eq!(
+            apply_negative_policy(BigInt::from(5), RewardNegativePolicy::Error, "a", "e").unwrap(),
+            ("5".to_string(), false)
+        );
+    }
+
+    #[test]
+    fn excluded_open_epoch_has_no_apy() {
+        assert_eq!(
+            annualization_factor(730.0, Some(0.5), PartialEpochApy::Exclude),
+            None
+        );
+        assert_eq!(
+            annualization_factor(730.0, None, PartialEpochApy::Exclude),
+            Some(730.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn idle_delegator_accrues_per_epoch_rewards_across_concurrent_epochs() {
+        // No transactions across epochs 1-3, so the balance at the delegator's last
+        // transaction stays 100 while auto-compounding raises it to 105, 110 and 116
+        let last_transaction_stakes =
+            HashMap::from([("alice.near".to_string(), "100".to_string())]);
+        let stored = DelegatorData {
+            auto_compounded_stake: "100".to_string(),
+            ..record(0, "0")
+        };
+        let balances = [(1, "105"), (2, "110"), (3, "116")];
+
+        // Finished out of order, as under buffer_unordered, with none of the run's records
+        // stored yet when the next epoch reads them
+        let mut carries = stake_carries(vec![2, 0, 1]);
+        assert!(carries[0].1.previous.is_none() && carries[2].1.next.is_none());
+        let handles: Vec<_> = carries
+            .drain(..)
+            .rev()
+            .map(|(index, carry)| {
+                let (epoch_number, balance) = balances[index];
+                let stored = stored.clone();
+                let last_transaction_stakes = last_transaction_stakes.clone();
+                tokio::spawn(async move {
+                    let sampled = HashMap::from([("alice.near".to_string(), balance.to_string())]);
+                    if let Some(next) = carry.next {
+                        next.send(sampled).unwrap();
+                    }
+                    let carried = match carry.previous {
+                        Some(previous) => previous.await.ok(),
+                        None => None,
+                    };
+                    let previous = previous_stake(
+                        "alice.near",
+                        epoch_number,
+                        false,
+                        carried.as_ref(),
+                        Some(&stored),
+                        &last_transaction_stakes,
+                    );
+                    (balance, calculate_rewards(balance, previous, None))
+                })
+            })
+            .collect();
+
+        let mut results = Vec::new();
+        for handle in handles.into_iter().rev() {
+            results.push(handle.await.unwrap());
+        }
+        let stakes: Vec<u64> = results
+            .iter()
+            .map(|(stake, _)| stake.parse().unwrap())
+            .collect();
+        assert!(stakes.windows(2).all(|pair| pair[0] < pair[1]));
+        let rewards: Vec<BigInt> = results.into_iter().map(|(_, rewards)| rewards).collect();
+        assert_eq!(rewards, [5, 5, 6].map(BigInt::from));
+    }
+
+    #[test]
+    fn transactions_measure_from_the_last_transaction_balance() {
+        let carried = HashMap::from([("alice.near".to_string(), "105".to_string())]);
+        let last_transaction_stakes =
+            HashMap::from([("alice.near".to_string(), "200".to_string())]);
+        let previous = previous_stake(
+            "alice.near",
+            2,
+            true,
+            Some(&carried),
+            None,
+            &last_transaction_stakes,
+        );
+        assert_eq!(previous.map(String::as_str), Some("200"));
+    }
+
+    #[test]
+    fn end_sampling_credits_the_epoch_its_own_rewards() {
+        // Grew from 90 to 100 over the previous epoch and from 100 to 120 over this one
+        let (previous_start, previous_end) = ("90".to_string(), "100".to_string());
+        let (start, end) = ("100".to_string(), "120".to_string());
+
+        let (previous, _) = sampled_balance(Some(&previous_start), None, 0, 0);
+        let (current, block) = sampled_balance(Some(&start), None, 43_200, 86_399);
+        assert_eq!(block, 43_200);
+        // Sampled at the start, the epoch is credited with the previous epoch's rewards
+        assert_eq!(
+            calculate_rewards(current, Some(previous), None),
+            BigInt::from(10)
+        );
+
+        let (previous, _) = sampled_balance(Some(&previous_start), Some(&previous_end), 0, 0);
+        let (current, block) = sampled_balance(Some(&start), Some(&end), 43_200, 86_399);
+        assert_eq!(block, 86_399);
+        assert_eq!(
+            calculate_rewards(current, Some(previous), None),
+            BigInt::from(20)
+        );
+    }
+
+    #[test]
+    fn empty_accounts_response_is_rejected_while_a_normal_one_is_saved() {
+        let mut config = Config::from_env();
+        config.min_delegators = 1;
+        config.min_total_stake = BigInt::from(10u64).pow(24);
+
+        assert!(below_thresholds(&config, 0, &BigInt::from(0)));
+        let normal_stake = BigInt::from(5u64) * BigInt::from(10u64).pow(27);
+        assert!(!below_thresholds(&config, 120, &normal_stake));
+        // A partial list: delegators are present but their stake is far below the pool's
+        assert!(below_thresholds(&config, 3, &BigInt::from(1_000)));
+    }
+
+    #[test]
+    fn malformed_accounts_are_skipped_up_to_the_tolerated_fraction() {
+        let accounts = vec![
+            serde_json::json!({ "account_id": "alice.near", "staked_balance": "100" }),
+            serde_json::json!({ "account_id": "bob.near", "staked_balance": 200 }),
+            serde_json::json!({ "account_id": "carol.near", "account": { "staked_balance": "300" } }),
+            serde_json::json!({ "account_id": "dave.near" }),
+        ];
+
+        let balances = account_balances(accounts.clone(), "pool.poolv1.near", 100, 0.25).unwrap();
+        assert_eq!(balances.len(), 3);
+        assert_eq!(balances["alice.near"], "100");
+        assert_eq!(balances["bob.near"], "200");
+        assert_eq!(balances["carol.near"], "300");
+
+        assert!(matches!(
+            account_balances(accounts, "pool.poolv1.near", 100, 0.1),
+            Err(IndexerError::MalformedAccounts {
+                malformed: 1,
+                total: 4,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn accounts_are_processed_in_account_id_order() {
+        let ids = ["carol.near", "alice.near", "dave.near", "bob.near"];
+        let stakes = |ids: Vec<&str>| -> EpochStakes {
+            ids.into_iter()
+                .map(|id| (id.to_string(), "100".to_string()))
+                .collect()
+        };
+        let balances = stakes(ids.to_vec());
+        let order: Vec<&str> = sorted_accounts(&balances)
+            .into_iter()
+            .map(|(account_id, _)| account_id.as_str())
+            .collect();
+        assert_eq!(order, ["alice.near", "bob.near", "carol.near", "dave.near"]);
+
+        // A map filled in a different order yields the same sequence
+        let reversed = stakes(ids.iter().rev().copied().collect());
+        assert_eq!(sorted_accounts(&reversed), sorted_accounts(&balances));
+    }
+
+    fn transaction(
+        hash: &str,
+        method: &str,
+        action: &str,
+        amount: &str,
+        block_height: u64,
+    ) -> Transaction {
+        Transaction {
+            transaction_hash: hash.to_string(),
+            amount: amount.to_string(),
+            method: method.to_string(),
+            action: action.to_string(),
+            type_: staking_parser::determine_type(action, method),
+            block_height,
+            timestamp: Utc::now(),
+            delegator_address: "alice.near".to_string(),
+            validator_account_id: "pool.poolv1.near".to_string(),
+            reward_amount: None,
+            explorer_url: None,
+        }
+    }
+
+    #[test]
+    fn withdraw_after_an_unstake_does_not_reduce_the_stake_again() {
+        let transactions = [
+            transaction("a", "deposit_and_stake", "stake", "100", 10),
+            transaction("b", "unstake", "unstake", "40", 20),
+            transaction("c", "withdraw_all", "withdraw", "40", 30),
+        ];
+        assert_eq!(transactions[2].type_, "withdraw");
+        let transactions: Vec<&Transaction> = transactions.iter().collect();
+
+        let stakes = calculate_initial_stakes(&transactions);
+        assert_eq!(stakes["alice.near"], BigInt::from(60));
+
+        let totals = calculate_epoch_transaction_totals(&transactions);
+        let totals = &totals["alice.near"];
+        assert_eq!(totals.net, BigInt::from(60));
+        assert_eq!(totals.gross_staked, BigInt::from(100));
+        assert_eq!(totals.gross_unstaked, BigInt::from(40));
+    }
+
+    #[test]
+    fn zero_balance_account_is_skipped_without_include_zero_stake() {
+        let balances = EpochStakes::from([
+            ("alice.near".to_string(), "100".to_string()),
+            ("bob.near".to_string(), "0".to_string()),
+        ]);
+        let account_ids = |include_zero_stake| -> Vec<&str> {
+            accounts_to_process(&balances, include_zero_stake)
+                .into_iter()
+                .map(|(account_id, _)| account_id.as_str())
+                .collect()
+        };
+        assert_eq!(account_ids(false), ["alice.near"]);
+        assert_eq!(account_ids(true), ["alice.near", "bob.near"]);
+    }
+
+    #[tokio::test]
+    async fn lockup_delegator_is_attributed_to_its_owner() {
+        // get_owner_account_id returns the owner as a JSON string
+        let (url, requests) = near_rpc::mock::serve(
+            0,
+            serde_json::json!({
+                "result": b"\"owner.near\"".to_vec(),
+                "logs": [],
+                "block_height": 100,
+                "block_hash": "FciD4i2WPEYinnKaCzFZAPTUsRxTCpJM6FyQmezmkkoj",
+            }),
+        )
+        .await;
+        let client = RpcClient::new(near_jsonrpc_client::JsonRpcClient::connect(&url));
+        let mut config = Config::from_env();
+        config.resolve_lockup_owners = true;
+        config.lockup_account_suffix = ".lockup.near".to_string();
+        let lockup = "3e2a1b0c9d8f7e6a5b4c3d2e1f0a9b8c7d6e5f40.lockup.near";
+
+        for _ in 0..2 {
+            let owner = resolve_beneficiary(&client, &client, lockup, 100, &config).await;
+            assert_eq!(owner.unwrap().as_deref(), Some("owner.near"));
+        }
+        // The owner is looked up once and reused
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let owner = resolve_beneficiary(&client, &client, "alice.near", 100, &config).await;
+        assert_eq!(owner.unwrap(), None);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn offsetting_unstake_and_stake_show_gross_churn_with_no_net_change() {
+        let transactions = [
+            transaction("a", "unstake", "unstake", "100", 10),
+            transaction("b", "deposit_and_stake", "stake", "100", 20),
+        ];
+        let transactions: Vec<&Transaction> = transactions.iter().collect();
+
+        let totals = calculate_epoch_transaction_totals(&transactions);
+        let totals = &totals["alice.near"];
+        assert!(totals.net.is_zero());
+        assert_eq!(
+            gross_amounts(Some(totals)),
+            ("100".to_string(), "100".to_string())
+        );
+    }
 }
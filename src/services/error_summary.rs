@@ -0,0 +1,178 @@
+use crate::error::IndexerError;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::sync::{LazyLock, Mutex};
+
+const MAX_EXAMPLES_PER_CATEGORY: usize = 3;
+
+static RUN_ERRORS: LazyLock<Mutex<ErrorSummary>> =
+    LazyLock::new(|| Mutex::new(ErrorSummary::default()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Rpc,
+    RateLimited,
+    Mongo,
+    Parse,
+    Other,
+}
+
+impl ErrorCategory {
+    pub fn of(error: &(dyn Error + 'static)) -> Self {
+        if let Some(error) = error.downcast_ref::<IndexerError>() {
+            return match error {
+                IndexerError::Rpc(_) | IndexerError::Lake(_) | IndexerError::BlockNotFound(_) => {
+                    ErrorCategory::Rpc
+                }
+                IndexerError::RateLimited(_) => ErrorCategory::RateLimited,
+                IndexerError::Mongo(_) => ErrorCategory::Mongo,
+                IndexerError::Parse(_) | IndexerError::MalformedAccounts { .. } => {
+                    ErrorCategory::Parse
+                }
+                IndexerError::Config(_)
+                | IndexerError::NotAStakingPool { .. }
+                | IndexerError::NegativeRewards { .. }
+                | IndexerError::RetryBudgetExhausted { .. }
+                | IndexerError::BeyondAsOfBlock { .. } => ErrorCategory::Other,
+            };
+        }
+        if error.is::<mongodb::error::Error>() {
+            return ErrorCategory::Mongo;
+        }
+        if error.is::<serde_json::Error>()
+            || error.is::<num_bigint::ParseBigIntError>()
+            || error.is::<std::num::ParseIntError>()
+        {
+            return ErrorCategory::Parse;
+        }
+        Self::of_message(&error.to_string())
+    }
+
+    pub fn of_message(message: &str) -> Self {
+        if message.contains("TooManyRequests") || message.contains("rate limit") {
+            ErrorCategory::RateLimited
+        } else if message.contains("JsonRpcError")
+            || message.contains("TransportError")
+            || message.contains("ServerError")
+        {
+            ErrorCategory::Rpc
+        } else {
+            ErrorCategory::Other
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CategorySummary {
+    pub count: usize,
+    pub affected: BTreeSet<String>,
+    pub examples: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ErrorSummary {
+    pub total: usize,
+    pub categories: BTreeMap<ErrorCategory, CategorySummary>,
+}
+
+impl ErrorSummary {
+    /// Records an error against the thing it affected, e.g. `"epoch <id>"` or `"rpc <url>"`.
+    pub fn record(&mut self, category: ErrorCategory, affected: String, message: String) {
+        self.total += 1;
+        let summary = self.categories.entry(category).or_default();
+        summary.count += 1;
+        summary.affected.insert(affected);
+        if summary.examples.len() < MAX_EXAMPLES_PER_CATEGORY {
+            summary.examples.push(message);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+}
+
+pub fn record_error(affected: String, error: &(dyn Error + 'static)) {
+    let category = ErrorCategory::of(error);
+    RUN_ERRORS
+        .lock()
+        .unwrap()
+        .record(category, affected, error.to_string());
+}
+
+pub fn record(category: ErrorCategory, affected: String, message: String) {
+    RUN_ERRORS
+        .lock()
+        .unwrap()
+        .record(category, affected, message);
+}
+
+/// Returns the errors collected since the last call and starts a fresh summary.
+pub fn take() -> ErrorSummary {
+    std::mem::take(&mut *RUN_ERRORS.lock().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_grouped_by_category_and_counted() {
+        let mut summary = ErrorSummary::default();
+        let errors = [
+            ("epoch a", IndexerError::Rpc("timeout".to_string())),
+            ("epoch b", IndexerError::BlockNotFound(100)),
+            ("epoch a", IndexerError::RateLimited("429".to_string())),
+            ("epoch a", IndexerError::Parse("bad amount".to_string())),
+            ("epoch c", IndexerError::Rpc("timeout".to_string())),
+        ];
+        for (affected, error) in &errors {
+            summary.record(
+                ErrorCategory::of(error),
+                affected.to_string(),
+                error.to_string(),
+            );
+        }
+
+        assert_eq!(summary.total, 5);
+        let counts: Vec<_> = summary
+            .categories
+            .iter()
+            .map(|(category, summary)| (*category, summary.count))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                (ErrorCategory::Rpc, 3),
+                (ErrorCategory::RateLimited, 1),
+                (ErrorCategory::Parse, 1)
+            ]
+        );
+        let rpc = &summary.categories[&ErrorCategory::Rpc];
+        assert_eq!(
+            rpc.affected.iter().map(String::as_str).collect::<Vec<_>>(),
+            ["epoch a", "epoch b", "epoch c"]
+        );
+        assert_eq!(rpc.examples.len(), MAX_EXAMPLES_PER_CATEGORY);
+    }
+
+    #[test]
+    fn take_starts_a_fresh_summary() {
+        // Other tests record into the same process-wide summary, so only look for this one's
+        let affected = "error_summary take test".to_string();
+        let recorded = |summary: &ErrorSummary| {
+            summary
+                .categories
+                .get(&ErrorCategory::Other)
+                .is_some_and(|category| category.affected.contains(&affected))
+        };
+        record(ErrorCategory::Other, affected.clone(), "failed".to_string());
+
+        let first_run = take();
+        assert!(recorded(&first_run));
+        assert!(!first_run.is_empty());
+        assert!(!recorded(&take()));
+    }
+}
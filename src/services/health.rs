@@ -0,0 +1,30 @@
+use axum::http::StatusCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static MONGO_READY: AtomicBool = AtomicBool::new(false);
+static RPC_READY: AtomicBool = AtomicBool::new(false);
+
+pub fn mark_mongo_ready() {
+    MONGO_READY.store(true, Ordering::Relaxed);
+}
+
+pub fn mark_rpc_ready() {
+    RPC_READY.store(true, Ordering::Relaxed);
+}
+
+/// Ready once MongoDB and at least one RPC endpoint have been reached.
+pub fn is_ready() -> bool {
+    MONGO_READY.load(Ordering::Relaxed) && RPC_READY.load(Ordering::Relaxed)
+}
+
+pub async fn liveness_handler() -> StatusCode {
+    StatusCode::OK
+}
+
+pub async fn readiness_handler() -> StatusCode {
+    if is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
@@ -0,0 +1,310 @@
+use crate::config::Config;
+use crate::error::IndexerError;
+use crate::models::NearblocksTxn;
+use crate::repositories::scan_cursor_repository;
+use crate::services::block_stream::{self, STREAM_CURSOR};
+use crate::services::near_rpc::{self, RpcClient};
+use crate::transaction_fetcher;
+use aws_sdk_s3::types::RequestPayer;
+use aws_sdk_s3::Client as S3Client;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use log::info;
+use mongodb::Database;
+use near_primitives::views::SignedTransactionView;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+/// How often the bucket is listed again once the stream has caught up.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Blocks listed per step, the most one S3 listing returns.
+const MAX_BLOCKS_PER_STEP: i32 = 1000;
+
+/// Reads blocks from NEAR Lake's S3 bucket and stores the staking transactions of every
+/// configured pool, through the same analysis and cursor as `block_stream`. The bucket is
+/// requester-pays, so AWS credentials are read from the environment as usual for the AWS SDK.
+/// RPC is still used to analyze the transactions found and to find the chain head when there
+/// is nothing to resume from.
+pub async fn run_stream(
+    config: Arc<Config>,
+    db: Database,
+    clients: Arc<(RpcClient, RpcClient)>,
+    from_block: Option<u64>,
+) -> Result<(), IndexerError> {
+    let (primary_client, secondary_client) = (&clients.0, &clients.1);
+    let bucket = config.lake_bucket.clone().ok_or_else(|| {
+        IndexerError::Config("LAKE_BUCKET is required for this NETWORK".to_string())
+    })?;
+    let aws_config = aws_config::from_env()
+        .region(aws_sdk_s3::config::Region::new(config.lake_region.clone()))
+        .load()
+        .await;
+    let s3 = S3Client::new(&aws_config);
+
+    let mut next_block = match block_stream::start_block(&db, from_block).await? {
+        Some(block) => block,
+        None => near_rpc::get_latest_block_height(primary_client, secondary_client).await?,
+    };
+    info!(
+        "Streaming transactions from block {} of s3://{}",
+        next_block, bucket
+    );
+
+    let mut first_failed_block: Option<u64> = None;
+    loop {
+        let heights = list_block_heights(&s3, &bucket, next_block).await?;
+        let Some(&to_block) = heights.last() else {
+            sleep(POLL_INTERVAL).await;
+            continue;
+        };
+
+        let transactions: Vec<Vec<NearblocksTxn>> = stream::iter(heights)
+            .map(|height| block_transactions(&s3, &bucket, height, &config.validator_account_ids))
+            .buffered(config.parallel_limit.max(1))
+            .try_collect()
+            .await?;
+        let failed = block_stream::store_streamed(
+            &config,
+            &db,
+            transactions.concat(),
+            primary_client,
+            secondary_client,
+        )
+        .await?;
+        if let Some(block) = failed {
+            first_failed_block = Some(first_failed_block.map_or(block, |first| first.min(block)));
+        }
+
+        if !config.dry_run {
+            scan_cursor_repository::save_scanned_height(
+                &db,
+                STREAM_CURSOR,
+                block_stream::streamed_height(to_block, first_failed_block),
+            )
+            .await?;
+        }
+        info!("Streamed blocks {} to {}", next_block, to_block);
+        next_block = to_block + 1;
+    }
+}
+
+/// Heights of the blocks in the bucket from `from_block` on. Each block is a prefix named after
+/// its zero-padded height; heights without a block have none.
+async fn list_block_heights(
+    s3: &S3Client,
+    bucket: &str,
+    from_block: u64,
+) -> Result<Vec<u64>, IndexerError> {
+    let mut request = s3
+        .list_objects_v2()
+        .bucket(bucket)
+        .delimiter("/")
+        .max_keys(MAX_BLOCKS_PER_STEP)
+        .request_payer(RequestPayer::Requester);
+    if let Some(previous) = from_block.checked_sub(1) {
+        request = request.start_after(block_prefix(previous));
+    }
+    let output = request
+        .send()
+        .await
+        .map_err(|e| IndexerError::Lake(format!("listing s3://{}: {}", bucket, e)))?;
+    Ok(prefix_heights(
+        output
+            .common_prefixes()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|prefix| prefix.prefix()),
+    ))
+}
+
+fn block_prefix(height: u64) -> String {
+    format!("{:012}", height)
+}
+
+/// Block heights of `<height>/` prefixes, in ascending order.
+fn prefix_heights<'a>(prefixes: impl Iterator<Item = &'a str>) -> Vec<u64> {
+    let mut heights: Vec<u64> = prefixes
+        .filter_map(|prefix| prefix.trim_end_matches('/').parse().ok())
+        .collect();
+    heights.sort_unstable();
+    heights
+}
+
+/// Transactions sent to any of `receivers` in the block at `height`, in the nearblocks
+/// stake-txns shape.
+async fn block_transactions(
+    s3: &S3Client,
+    bucket: &str,
+    height: u64,
+    receivers: &[String],
+) -> Result<Vec<NearblocksTxn>, IndexerError> {
+    let block = get_json(s3, bucket, &format!("{}/block.json", block_prefix(height))).await?;
+    let timestamp_nanos = block["header"]["timestamp_nanosec"]
+        .as_str()
+        .and_then(|timestamp| timestamp.parse().ok())
+        .ok_or_else(|| IndexerError::Parse(format!("block {} has no timestamp", height)))?;
+
+    let mut transactions = Vec::new();
+    for shard_id in included_shards(&block, height) {
+        let key = format!("{}/shard_{}.json", block_prefix(height), shard_id);
+        let shard = get_json(s3, bucket, &key).await?;
+        transactions.extend(shard_transactions(
+            &shard,
+            receivers,
+            height,
+            timestamp_nanos,
+        )?);
+    }
+    Ok(transactions)
+}
+
+/// Shards whose chunk was produced in this block. Chunks missing from a block are repeated
+/// from an earlier one.
+fn included_shards(block: &Value, height: u64) -> Vec<u64> {
+    block["chunks"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter(|chunk| chunk["height_included"].as_u64() == Some(height))
+        .filter_map(|chunk| chunk["shard_id"].as_u64())
+        .collect()
+}
+
+/// Transactions of a Lake shard file sent to any of `receivers`. Others are skipped before
+/// they are parsed, so actions this version of nearcore doesn't know can't fail the block.
+fn shard_transactions(
+    shard: &Value,
+    receivers: &[String],
+    block_height: u64,
+    block_timestamp_nanos: u64,
+) -> Result<Vec<NearblocksTxn>, IndexerError> {
+    shard["chunk"]["transactions"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|entry| &entry["transaction"])
+        .filter(|tx| {
+            receivers
+                .iter()
+                .any(|receiver| tx["receiver_id"].as_str() == Some(receiver.as_str()))
+        })
+        .map(|tx| {
+            let tx: SignedTransactionView = serde_json::from_value(tx.clone())?;
+            Ok(transaction_fetcher::rpc_transaction_to_txn(
+                &tx,
+                block_height,
+                block_timestamp_nanos,
+            ))
+        })
+        .collect()
+}
+
+async fn get_json(s3: &S3Client, bucket: &str, key: &str) -> Result<Value, IndexerError> {
+    let lake_error = |e: &dyn std::fmt::Display| {
+        IndexerError::Lake(format!("reading s3://{}/{}: {}", bucket, key, e))
+    };
+    let object = s3
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .request_payer(RequestPayer::Requester)
+        .send()
+        .await
+        .map_err(|e| lake_error(&e))?;
+    let bytes = object
+        .body
+        .collect()
+        .await
+        .map_err(|e| lake_error(&e))?
+        .into_bytes();
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn listing_resumes_after_the_previous_block() {
+        assert_eq!(block_prefix(123_456_789), "000123456789");
+        let heights =
+            prefix_heights(["000000000012/", "000000000010/", "not-a-block/"].into_iter());
+        assert_eq!(heights, [10, 12]);
+    }
+
+    #[test]
+    fn only_chunks_produced_in_the_block_are_read() {
+        let block = json!({
+            "chunks": [
+                { "shard_id": 0, "height_included": 100 },
+                { "shard_id": 1, "height_included": 99 },
+                { "shard_id": 2, "height_included": 100 },
+            ],
+        });
+        assert_eq!(included_shards(&block, 100), [0, 2]);
+    }
+
+    #[test]
+    fn shard_transactions_to_the_pool_are_extracted() {
+        let transaction = |hash: &str, receiver_id: &str| {
+            json!({
+                "transaction": {
+                    "signer_id": "alice.near",
+                    "public_key": "ed25519:Gq69vzrMBSooNK53o8TnnCEzFk6CSNRJAfrsxNFgSmST",
+                    "nonce": 1,
+                    "receiver_id": receiver_id,
+                    "actions": [{
+                        "FunctionCall": {
+                            "method_name": "deposit_and_stake",
+                            "args": "e30=",
+                            "gas": 125_000_000_000_000u64,
+                            "deposit": "5000000000000000000000000",
+                        },
+                    }],
+                    "signature": "ed25519:2AXDGYSE4f2sz7tvMMzyHvUfcoJmxudvdhBcmiUSo6ijskFdN6YH28jTi9qxPiVmshwHGHSDNqRxx2zhC1prj4K",
+                    "hash": hash,
+                },
+                "outcome": {},
+            })
+        };
+        let shard = json!({
+            "shard_id": 0,
+            "chunk": {
+                "transactions": [
+                    transaction("HUZyX8rKmzFCrUdKCku86ZRbJyhduNW3tPjW76E8L8Cj", "pool.poolv1.near"),
+                    transaction("FciD4i2WPEYinnKaCzFZAPTUsRxTCpJM6FyQmezmkkoj", "app.near"),
+                ],
+            },
+        });
+
+        let transactions = shard_transactions(
+            &shard,
+            &["pool.poolv1.near".to_string()],
+            100,
+            1_700_000_000,
+        )
+        .unwrap();
+        assert_eq!(transactions.len(), 1);
+        let txn = &transactions[0];
+        assert_eq!(
+            txn.transaction_hash,
+            "HUZyX8rKmzFCrUdKCku86ZRbJyhduNW3tPjW76E8L8Cj"
+        );
+        assert_eq!(txn.block_height(), Some(100));
+        assert_eq!(txn.timestamp_nanos(), Some(1_700_000_000));
+        assert_eq!(txn.method(), Some("deposit_and_stake"));
+        assert_eq!(txn.deposit(), Some("5000000000000000000000000"));
+    }
+
+    #[test]
+    fn shard_without_a_chunk_has_no_transactions() {
+        let shard = json!({ "shard_id": 3, "chunk": null });
+        let transactions =
+            shard_transactions(&shard, &["pool.poolv1.near".to_string()], 100, 0).unwrap();
+        assert!(transactions.is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+use crate::services::health;
+use axum::{http::header, response::IntoResponse, routing::get, Router};
+use log::{error, info};
+use prometheus::{
+    register_int_counter, register_int_counter_vec, register_int_gauge, Encoder, IntCounter,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use std::sync::LazyLock;
+
+static RPC_CALLS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "rpc_calls_total",
+        "Number of NEAR RPC calls by endpoint and result",
+        &["endpoint", "result"]
+    )
+    .unwrap()
+});
+
+static RPC_RATE_LIMITED_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "rpc_rate_limited_total",
+        "Number of NEAR RPC calls rejected with TooManyRequests"
+    )
+    .unwrap()
+});
+
+static EPOCHS_PROCESSED_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "epochs_processed_total",
+        "Number of epochs processed successfully"
+    )
+    .unwrap()
+});
+
+static TRANSACTIONS_PROCESSED_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "transactions_processed_total",
+        "Number of staking transactions processed"
+    )
+    .unwrap()
+});
+
+static CURRENT_BLOCK_HEIGHT: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("current_block_height", "Latest block height seen on chain").unwrap()
+});
+
+pub fn record_rpc_call(endpoint: &str, success: bool) {
+    let result = if success { "success" } else { "error" };
+    RPC_CALLS_TOTAL.with_label_values(&[endpoint, result]).inc();
+}
+
+pub fn record_rate_limited() {
+    RPC_RATE_LIMITED_TOTAL.inc();
+}
+
+pub fn record_epoch_processed() {
+    EPOCHS_PROCESSED_TOTAL.inc();
+}
+
+pub fn record_transactions_processed(count: usize) {
+    TRANSACTIONS_PROCESSED_TOTAL.inc_by(count as u64);
+}
+
+pub fn set_current_block_height(height: u64) {
+    CURRENT_BLOCK_HEIGHT.set(height as i64);
+}
+
+async fn metrics_handler() -> impl IntoResponse {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!("Failed to encode metrics: {}", e);
+    }
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buffer,
+    )
+}
+
+pub async fn serve(port: u16) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(health::liveness_handler))
+        .route("/readyz", get(health::readiness_handler));
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("Metrics server listening on port {}", port);
+    axum::serve(listener, app).await
+}
@@ -1,3 +1,16 @@
+pub mod api;
+pub mod block_stream;
+pub mod circuit_breaker;
 pub mod database;
 pub mod epoch_processor;
+pub mod error_summary;
+pub mod health;
+pub mod lake_stream;
+pub mod metrics;
 pub mod near_rpc;
+pub mod retry_budget;
+pub mod staking_parser;
+pub mod statsd;
+pub mod telemetry;
+pub mod throttle;
+pub mod webhook;
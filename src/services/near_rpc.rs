@@ -1,17 +1,101 @@
+use crate::config::TipFinality;
+use crate::error::IndexerError;
 use crate::models::EpochInfo;
+use crate::services::circuit_breaker::CircuitBreaker;
+use crate::services::error_summary::{self, ErrorCategory};
+use crate::services::throttle::AdaptiveThrottle;
+use crate::services::{health, metrics, retry_budget, staking_parser, statsd, telemetry};
 use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
 use log::{error, info, warn};
+use lru::LruCache;
 use near_jsonrpc_client::{methods, JsonRpcClient};
+use near_jsonrpc_primitives::types::blocks::RpcBlockError;
+use near_jsonrpc_primitives::types::chunks::ChunkReference;
 use near_jsonrpc_primitives::types::query::QueryResponseKind as JsonRpcQueryResponseKind;
-use near_primitives::types::{BlockReference, Finality, FunctionArgs};
-use near_primitives::views::BlockView;
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use near_primitives::hash::CryptoHash;
+use near_primitives::types::{AccountId, BlockReference, Finality, FunctionArgs};
+use near_primitives::views::{BlockView, ChunkView};
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::Duration;
+
+static TIP_FINALITY: OnceLock<TipFinality> = OnceLock::new();
+
+/// Blocks kept between the chain tip and the end of the open epoch, see `set_tip_lag`.
+static TIP_LAG_BLOCKS: OnceLock<u64> = OnceLock::new();
+
+/// Height the chain is read as of, see `set_as_of_block`.
+static AS_OF_BLOCK: OnceLock<u64> = OnceLock::new();
+
+/// Failure threshold and cooldown of the per-endpoint circuit breakers.
+static BREAKER_SETTINGS: OnceLock<(u32, Duration)> = OnceLock::new();
+
+/// Entries kept by each block cache before the least recently used are evicted.
+const BLOCK_CACHE_CAPACITY: usize = 10_000;
+
+/// Highest final block seen, see `is_final`.
+static FINAL_HEAD: AtomicU64 = AtomicU64::new(0);
+
+/// Height and timestamp (ns) of the first block at or after a probed height, shared by every
+/// `height_at_timestamp` search. Only final blocks are cached, as they never change.
+static PROBED_BLOCKS: LazyLock<Mutex<LruCache<u64, (u64, u64)>>> =
+    LazyLock::new(|| Mutex::new(block_cache()));
+
+/// Height and epoch id of the first block at or after a height probed by the boundary search.
+/// Shared by every search, so the overlapping segments of a parallel sync reuse each other's
+/// lookups. Only final blocks are cached, as they never change.
+static SEARCHED_BLOCKS: LazyLock<Mutex<LruCache<u64, (u64, String)>>> =
+    LazyLock::new(|| Mutex::new(block_cache()));
+
+/// Paces the epoch boundary search and the transaction analysis when
+/// `RPC_INTER_REQUEST_DELAY_MS` is unset. Shared by every caller so concurrent requests back
+/// off together.
+static BLOCK_THROTTLE: LazyLock<Mutex<AdaptiveThrottle>> = LazyLock::new(|| {
+    Mutex::new(AdaptiveThrottle::new(
+        Duration::ZERO,
+        Duration::from_secs(5),
+        Duration::from_millis(20),
+    ))
+});
+
+/// The configured fixed delay, or the adaptive throttle's current one.
+fn inter_request_delay(inter_request_delay_ms: Option<u64>) -> Duration {
+    match inter_request_delay_ms {
+        Some(delay_ms) => Duration::from_millis(delay_ms),
+        None => BLOCK_THROTTLE.lock().unwrap().delay(),
+    }
+}
+
+/// Waits the configured fixed delay, or the adaptive throttle's current one, before a request
+/// made outside the boundary search.
+pub async fn pace_request(inter_request_delay_ms: Option<u64>) {
+    let delay = inter_request_delay(inter_request_delay_ms);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Feeds the outcome of a request paced by `pace_request` to the adaptive throttle.
+pub fn record_paced_result(rate_limited: bool) {
+    let mut throttle = BLOCK_THROTTLE.lock().unwrap();
+    if rate_limited {
+        throttle.on_rate_limited();
+    } else {
+        throttle.on_success();
+    }
+}
 
 // Replace your get_validators_info function with this one
 pub async fn get_validators_info(
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
     epoch_id: Option<&str>,
-) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    client: &reqwest::Client,
+) -> Result<serde_json::Value, IndexerError> {
     info!("Fetching validators info for epoch_id: {:?}", epoch_id);
 
     let params = match epoch_id {
@@ -28,7 +112,6 @@ pub async fn get_validators_info(
     });
 
     // Try primary first, fall back to secondary with retry logic
-    let client = reqwest::Client::new();
     let max_retries = 3;
     let mut retry_count = 0;
     let mut backoff_time = 5; // Start with 1 second backoff
@@ -94,13 +177,16 @@ pub async fn get_validators_info(
         // Both primary and secondary failed, implement backoff and retry
         retry_count += 1;
         if retry_count >= max_retries {
-            return Err(format!(
+            return Err(IndexerError::RateLimited(format!(
                 "Failed to fetch validators info after {} retries",
                 max_retries
-            )
-            .into());
+            )));
         }
 
+        retry_budget::reserve(
+            Duration::from_secs(backoff_time),
+            &format!("fetching validators info for epoch {:?}", epoch_id),
+        )?;
         info!(
             "Both RPCs rate limited, backing off for {} seconds before retry",
             backoff_time
@@ -110,36 +196,333 @@ pub async fn get_validators_info(
     }
 }
 
+/// Whether `account_id` is in `current_validators` of a `validators` RPC response.
+/// Returns `None` when the response carries no validator set, e.g. an RPC error.
+pub fn is_current_validator(validators_info: &serde_json::Value, account_id: &str) -> Option<bool> {
+    let current_validators = validators_info["result"]["current_validators"].as_array()?;
+    Some(
+        current_validators
+            .iter()
+            .any(|validator| validator["account_id"].as_str() == Some(account_id)),
+    )
+}
+
 pub async fn create_near_connections(
     primary_rpc: &str,
     secondary_rpc: &str,
-) -> (JsonRpcClient, JsonRpcClient) {
+    http_client: reqwest::Client,
+) -> (RpcClient, RpcClient) {
     info!("Connecting to NEAR...");
-    let primary_client = JsonRpcClient::connect(primary_rpc);
-    let secondary_client = JsonRpcClient::connect(secondary_rpc);
-    info!("NEAR connections established");
+    let connector = JsonRpcClient::with(http_client);
+    let primary_client = RpcClient::new(connector.connect(primary_rpc));
+    let secondary_client = RpcClient::new(connector.connect(secondary_rpc));
+
+    // Probe the endpoints so the readiness probe only passes once one of them answers.
+    let mut reachable = false;
+    for (name, client) in [
+        ("primary", &primary_client),
+        ("secondary", &secondary_client),
+    ] {
+        match client.call(methods::status::RpcStatusRequest).await {
+            Ok(_) => {
+                reachable = true;
+                break;
+            }
+            Err(e) => warn!("{} RPC endpoint is unreachable: {:?}", name, e),
+        }
+    }
+    if reachable {
+        health::mark_rpc_ready();
+        info!("NEAR connections established");
+    } else {
+        error!("Neither NEAR RPC endpoint is reachable");
+    }
     (primary_client, secondary_client)
 }
 pub async fn get_latest_block_height(
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<u64, Box<dyn std::error::Error>> {
-    let block_request = methods::block::RpcBlockRequest {
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<u64, IndexerError> {
+    let block = get_latest_block(primary_client, secondary_client).await?;
+    Ok(block.header.height)
+}
+
+/// Sets the finality of the chain tip read by `get_latest_block`; `Final` until called.
+pub fn set_tip_finality(finality: TipFinality) {
+    if TIP_FINALITY.set(finality).is_err() {
+        warn!(
+            "Tip finality was already set, keeping {:?}",
+            TIP_FINALITY.get()
+        );
+    }
+}
+
+/// Sets how many blocks below the tip the open epoch ends, so its balances are read from
+/// state that has settled; 0 until called.
+pub fn set_tip_lag(lag_blocks: u64) {
+    if TIP_LAG_BLOCKS.set(lag_blocks).is_err() {
+        warn!(
+            "Tip lag was already set, keeping {:?}",
+            TIP_LAG_BLOCKS.get()
+        );
+    }
+}
+
+/// Reads the chain as of `as_of_block`: the tip is clamped to it and account state above it is
+/// rejected, so a re-run reproduces what the indexer computed back then. The live tip until
+/// called.
+pub fn set_as_of_block(as_of_block: Option<u64>) {
+    let Some(as_of_block) = as_of_block else {
+        return;
+    };
+    if AS_OF_BLOCK.set(as_of_block).is_err() {
+        warn!(
+            "As-of block was already set, keeping {:?}",
+            AS_OF_BLOCK.get()
+        );
+        return;
+    }
+    info!("Reading the chain as of block {}", as_of_block);
+}
+
+/// The configured as-of block, if any.
+pub fn as_of_block() -> Option<u64> {
+    AS_OF_BLOCK.get().copied()
+}
+
+/// Fails with `BeyondAsOfBlock` if `block_height` is above the configured as-of block.
+fn check_as_of(block_height: u64) -> Result<(), IndexerError> {
+    match AS_OF_BLOCK.get() {
+        Some(&as_of_block) if block_height > as_of_block => Err(IndexerError::BeyondAsOfBlock {
+            block_height,
+            as_of_block,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The latest block height minus the configured tip lag, used as the end of the open epoch.
+pub async fn get_settled_block_height(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<u64, IndexerError> {
+    let latest_height = get_latest_block_height(primary_client, secondary_client).await?;
+    Ok(settled_height(
+        latest_height,
+        TIP_LAG_BLOCKS.get().copied().unwrap_or(0),
+    ))
+}
+
+fn settled_height(latest_height: u64, lag_blocks: u64) -> u64 {
+    latest_height.saturating_sub(lag_blocks)
+}
+
+/// Sets the failure threshold and cooldown of the RPC circuit breakers; 5 failures and 30s
+/// until called.
+pub fn set_circuit_breaker(failure_threshold: u32, cooldown: Duration) {
+    if BREAKER_SETTINGS.set((failure_threshold, cooldown)).is_err() {
+        warn!("Circuit breaker settings were already set");
+    }
+}
+
+/// An RPC endpoint's client with its circuit breaker. Derefs to the `JsonRpcClient`.
+pub struct RpcClient {
+    client: JsonRpcClient,
+    breaker: Mutex<CircuitBreaker>,
+}
+
+impl RpcClient {
+    /// Wraps `client` with a closed breaker using the `set_circuit_breaker` settings.
+    pub fn new(client: JsonRpcClient) -> Self {
+        let (failure_threshold, cooldown) = BREAKER_SETTINGS
+            .get()
+            .copied()
+            .unwrap_or((5, Duration::from_secs(30)));
+        Self {
+            client,
+            breaker: Mutex::new(CircuitBreaker::new(failure_threshold, cooldown)),
+        }
+    }
+
+    fn allow_request(&self) -> bool {
+        self.breaker.lock().unwrap().allow_request()
+    }
+
+    fn record_result(&self, success: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if success {
+            breaker.on_success();
+        } else if breaker.on_failure() {
+            warn!(
+                "RPC endpoint {} failed repeatedly, skipping it until the cooldown passes",
+                self.server_addr()
+            );
+        }
+    }
+}
+
+impl std::ops::Deref for RpcClient {
+    type Target = JsonRpcClient;
+
+    fn deref(&self) -> &JsonRpcClient {
+        &self.client
+    }
+}
+
+fn block_cache<V>() -> LruCache<u64, V> {
+    LruCache::new(NonZeroUsize::new(BLOCK_CACHE_CAPACITY).unwrap())
+}
+
+/// Whether the block at `height` is final, so what was read from it can be cached. The final
+/// head is only looked up again for heights above the highest one seen.
+async fn is_final(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    height: u64,
+) -> Result<bool, IndexerError> {
+    if height <= FINAL_HEAD.load(Ordering::Relaxed) {
+        return Ok(true);
+    }
+    let block_request = || methods::block::RpcBlockRequest {
         block_reference: BlockReference::Finality(Finality::Final),
     };
+    let head = query_rpc(
+        primary_client,
+        secondary_client,
+        block_request(),
+        block_request,
+    )
+    .await?
+    .header
+    .height;
+    FINAL_HEAD.fetch_max(head, Ordering::Relaxed);
+    Ok(height <= head)
+}
 
-    let block = query_rpc(primary_client, secondary_client, block_request, || {
-        methods::block::RpcBlockRequest {
-            block_reference: BlockReference::Finality(Finality::Final),
+/// Latest block at the configured finality. `Final` blocks can't be reverted. `NearFinal`
+/// (doomslug) blocks are usually a couple of blocks closer to the tip, so indexing lags less,
+/// but they can in rare cases still be replaced by a fork. With an as-of block below the tip,
+/// the last block produced at or before it.
+pub async fn get_latest_block(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<BlockView, IndexerError> {
+    let block_request = || methods::block::RpcBlockRequest {
+        block_reference: BlockReference::Finality(
+            match TIP_FINALITY.get().copied().unwrap_or(TipFinality::Final) {
+                TipFinality::Final => Finality::Final,
+                TipFinality::NearFinal => Finality::DoomSlug,
+            },
+        ),
+    };
+
+    let block = query_rpc(
+        primary_client,
+        secondary_client,
+        block_request(),
+        block_request,
+    )
+    .await?;
+    if TIP_FINALITY.get() != Some(&TipFinality::NearFinal) {
+        FINAL_HEAD.fetch_max(block.header.height, Ordering::Relaxed);
+    }
+
+    let block = match AS_OF_BLOCK.get() {
+        Some(&as_of_block) if block.header.height > as_of_block => {
+            as_of_block_view(primary_client, secondary_client, as_of_block).await?
         }
-    })
+        _ => block,
+    };
+    metrics::set_current_block_height(block.header.height);
+    Ok(block)
+}
+
+/// Last block produced at or before `as_of_block`.
+async fn as_of_block_view(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    as_of_block: u64,
+) -> Result<BlockView, IndexerError> {
+    let mut height = as_of_block;
+    loop {
+        if let Some(block) = get_block_by_height(primary_client, secondary_client, height).await? {
+            return Ok(block);
+        }
+        if height == 0 {
+            return Err(IndexerError::BlockNotFound(as_of_block));
+        }
+        height -= 1;
+    }
+}
+/// Reads the current `epoch_length` from the protocol config.
+pub async fn get_epoch_length(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<u64, IndexerError> {
+    let config_request = || methods::EXPERIMENTAL_protocol_config::RpcProtocolConfigRequest {
+        block_reference: BlockReference::Finality(Finality::Final),
+    };
+
+    let protocol_config = query_rpc(
+        primary_client,
+        secondary_client,
+        config_request(),
+        config_request,
+    )
     .await?;
+    Ok(protocol_config.epoch_length)
+}
 
-    Ok(block.header.height)
+/// Returns the block at `height`, or `None` if no block was produced at that height.
+pub async fn get_block_by_height(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    height: u64,
+) -> Result<Option<BlockView>, IndexerError> {
+    let block_request = || methods::block::RpcBlockRequest {
+        block_reference: BlockReference::BlockId(near_primitives::types::BlockId::Height(height)),
+    };
+
+    match query_rpc(
+        primary_client,
+        secondary_client,
+        block_request(),
+        block_request,
+    )
+    .await
+    {
+        Ok(block) => Ok(Some(block)),
+        Err(e) => match e.handler_error() {
+            Some(RpcBlockError::UnknownBlock { .. }) => Ok(None),
+            _ => Err(e.into()),
+        },
+    }
 }
+
+pub async fn get_chunk(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    chunk_hash: CryptoHash,
+) -> Result<ChunkView, IndexerError> {
+    let chunk_request = || methods::chunk::RpcChunkRequest {
+        chunk_reference: ChunkReference::ChunkHash {
+            chunk_id: chunk_hash,
+        },
+    };
+
+    let chunk = query_rpc(
+        primary_client,
+        secondary_client,
+        chunk_request(),
+        chunk_request,
+    )
+    .await?;
+    Ok(chunk)
+}
+
 pub async fn query_rpc<M, F>(
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
     method: M,
     fallback: F,
 ) -> Result<M::Response, near_jsonrpc_client::errors::JsonRpcError<M::Error>>
@@ -149,18 +532,42 @@ where
     M::Error: std::fmt::Debug,
 {
     info!("Querying RPC: {}", std::any::type_name::<M>());
+    telemetry::record_rpc_call();
+    // An open breaker fails straight over to the secondary. The secondary is always tried as
+    // the last resort, so its breaker is only tracked.
+    if !primary_client.allow_request() {
+        warn!("Primary RPC circuit is open, using secondary");
+        let response = secondary_client.call(method).await;
+        metrics::record_rpc_call(secondary_client.server_addr(), response.is_ok());
+        secondary_client.record_result(response.is_ok());
+        if let Err(e) = &response {
+            record_rpc_error(secondary_client.server_addr(), e);
+            error!(
+                "Secondary RPC failed with the primary circuit open: {:?}",
+                e
+            );
+        }
+        return response;
+    }
     match primary_client.call(method).await {
         Ok(response) => {
+            metrics::record_rpc_call(primary_client.server_addr(), true);
+            primary_client.record_result(true);
             info!(
                 "RPC query successful on primary: {}",
                 std::any::type_name::<M>()
             );
             Ok(response)
         }
-        Err(_) => {
+        Err(e) => {
+            metrics::record_rpc_call(primary_client.server_addr(), false);
+            primary_client.record_result(false);
+            record_rpc_error(primary_client.server_addr(), &e);
             warn!("Primary RPC failed, trying secondary");
             match secondary_client.call(fallback()).await {
                 Ok(response) => {
+                    metrics::record_rpc_call(secondary_client.server_addr(), true);
+                    secondary_client.record_result(true);
                     info!(
                         "RPC query successful on secondary: {}",
                         std::any::type_name::<M>()
@@ -168,6 +575,9 @@ where
                     Ok(response)
                 }
                 Err(e) => {
+                    metrics::record_rpc_call(secondary_client.server_addr(), false);
+                    secondary_client.record_result(false);
+                    record_rpc_error(secondary_client.server_addr(), &e);
                     error!("Both RPCs failed: {:?}", e);
                     Err(e)
                 }
@@ -176,27 +586,198 @@ where
     }
 }
 
+fn record_rpc_error<E: std::fmt::Debug>(
+    endpoint: &str,
+    error: &near_jsonrpc_client::errors::JsonRpcError<E>,
+) {
+    let message = format!("{:?}", error);
+    let category = if message.contains("TooManyRequests") {
+        ErrorCategory::RateLimited
+    } else {
+        ErrorCategory::Rpc
+    };
+    error_summary::record(category, format!("rpc {}", endpoint), message);
+    statsd::count("rpc_errors", 1);
+}
+
 pub async fn get_accounts(
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
     validator_account_id: &str,
     block_height: u64,
-) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    page_size: u64,
+) -> Result<Vec<serde_json::Value>, IndexerError> {
+    check_as_of(block_height)?;
+    let account_id: AccountId = validator_account_id.parse()?;
     let mut all_accounts = Vec::new();
     let mut from_index = 0;
-    let limit = 1000;
 
     loop {
         info!(
             "Fetching accounts for block height {}, from_index: {}",
             block_height, from_index
         );
-        let query_request = methods::query::RpcQueryRequest {
+        let accounts = get_accounts_page(
+            primary_client,
+            secondary_client,
+            &account_id,
+            block_height,
+            from_index,
+            page_size,
+        )
+        .await?;
+        let page_len = accounts.len();
+        all_accounts.extend(accounts);
+
+        if page_len < page_size as usize {
+            break;
+        }
+
+        from_index += page_size;
+    }
+
+    Ok(all_accounts)
+}
+
+/// Staked balance of one delegator of the pool at `block_height`, via the pool's
+/// `get_account_staked_balance` view method. Zero for accounts that never delegated.
+pub async fn get_account_stake_balance(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    validator_account_id: &str,
+    account_id: &str,
+    block_height: u64,
+) -> Result<String, IndexerError> {
+    check_as_of(block_height)?;
+    let pool_id: AccountId = validator_account_id.parse()?;
+    let args = serde_json::json!({ "account_id": account_id })
+        .to_string()
+        .into_bytes();
+    let request = || methods::query::RpcQueryRequest {
+        block_reference: BlockReference::BlockId(near_primitives::types::BlockId::Height(
+            block_height,
+        )),
+        request: near_primitives::views::QueryRequest::CallFunction {
+            account_id: pool_id.clone(),
+            method_name: "get_account_staked_balance".to_string(),
+            args: FunctionArgs::from(args.clone()),
+        },
+    };
+
+    let response = query_rpc(primary_client, secondary_client, request(), request).await?;
+    match response.kind {
+        JsonRpcQueryResponseKind::CallResult(result) => {
+            let balance: serde_json::Value = serde_json::from_slice(&result.result)?;
+            Ok(staking_parser::parse_balance_field(&balance)?)
+        }
+        _ => Err(IndexerError::Rpc(format!(
+            "Unexpected response to get_account_staked_balance for {} at block {}",
+            account_id, block_height
+        ))),
+    }
+}
+
+/// The pool's `get_account` view of `account_id` at `block_height`, holding its staked and
+/// unstaked balances.
+pub async fn get_pool_account(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    validator_account_id: &str,
+    account_id: &str,
+    block_height: u64,
+) -> Result<serde_json::Value, IndexerError> {
+    check_as_of(block_height)?;
+    let pool_id: AccountId = validator_account_id.parse()?;
+    let args = serde_json::json!({ "account_id": account_id })
+        .to_string()
+        .into_bytes();
+    let request = || methods::query::RpcQueryRequest {
+        block_reference: BlockReference::BlockId(near_primitives::types::BlockId::Height(
+            block_height,
+        )),
+        request: near_primitives::views::QueryRequest::CallFunction {
+            account_id: pool_id.clone(),
+            method_name: "get_account".to_string(),
+            args: FunctionArgs::from(args.clone()),
+        },
+    };
+
+    let response = query_rpc(primary_client, secondary_client, request(), request).await?;
+    match response.kind {
+        JsonRpcQueryResponseKind::CallResult(result) => Ok(serde_json::from_slice(&result.result)?),
+        _ => Err(IndexerError::Rpc(format!(
+            "Unexpected response to get_account for {} at block {}",
+            account_id, block_height
+        ))),
+    }
+}
+
+/// Owner of a lockup contract at `block_height`, via its `get_owner_account_id` view method.
+/// `None` when `lockup_account_id` has no contract or the contract isn't a lockup.
+pub async fn get_lockup_owner(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    lockup_account_id: &str,
+    block_height: u64,
+) -> Result<Option<String>, IndexerError> {
+    check_as_of(block_height)?;
+    let lockup_id: AccountId = lockup_account_id.parse()?;
+    let request = || methods::query::RpcQueryRequest {
+        block_reference: BlockReference::BlockId(near_primitives::types::BlockId::Height(
+            block_height,
+        )),
+        request: near_primitives::views::QueryRequest::CallFunction {
+            account_id: lockup_id.clone(),
+            method_name: "get_owner_account_id".to_string(),
+            args: FunctionArgs::from(b"{}".to_vec()),
+        },
+    };
+
+    let response = match query_rpc(primary_client, secondary_client, request(), request).await {
+        Ok(response) => response,
+        Err(e) => {
+            if let Some(reason) = not_a_staking_pool_reason(e.handler_error()) {
+                warn!(
+                    "{} is not a lockup contract at block {}: {}",
+                    lockup_account_id, block_height, reason
+                );
+                return Ok(None);
+            }
+            return Err(e.into());
+        }
+    };
+    match response.kind {
+        JsonRpcQueryResponseKind::CallResult(result) => {
+            Ok(Some(serde_json::from_slice(&result.result)?))
+        }
+        _ => Err(IndexerError::Rpc(format!(
+            "Unexpected response to get_owner_account_id for {} at block {}",
+            lockup_account_id, block_height
+        ))),
+    }
+}
+
+/// Fetches one page of `get_accounts`, retrying with exponential backoff so a transient
+/// failure doesn't discard the pages already fetched.
+async fn get_accounts_page(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    account_id: &AccountId,
+    block_height: u64,
+    from_index: u64,
+    limit: u64,
+) -> Result<Vec<serde_json::Value>, IndexerError> {
+    let max_retries = 5;
+    let mut retry_count = 0;
+    let mut backoff_time = 1; // Start with 1 second
+
+    loop {
+        let query_request = || methods::query::RpcQueryRequest {
             block_reference: BlockReference::BlockId(near_primitives::types::BlockId::Height(
                 block_height,
             )),
             request: near_primitives::views::QueryRequest::CallFunction {
-                account_id: validator_account_id.parse()?,
+                account_id: account_id.clone(),
                 method_name: "get_accounts".to_string(),
                 args: FunctionArgs::from(
                     serde_json::json!({ "from_index": from_index, "limit": limit })
@@ -206,47 +787,82 @@ pub async fn get_accounts(
             },
         };
 
-        let result = query_rpc(primary_client, secondary_client, query_request, || {
-            methods::query::RpcQueryRequest {
-                block_reference: BlockReference::BlockId(near_primitives::types::BlockId::Height(
-                    block_height,
-                )),
-                request: near_primitives::views::QueryRequest::CallFunction {
-                    account_id: validator_account_id.parse().unwrap(),
-                    method_name: "get_accounts".to_string(),
-                    args: FunctionArgs::from(
-                        serde_json::json!({ "from_index": from_index, "limit": limit })
-                            .to_string()
-                            .into_bytes(),
-                    ),
-                },
-            }
-        })
-        .await?;
-
-        match result.kind {
-            JsonRpcQueryResponseKind::CallResult(call_result) => {
-                let accounts: Vec<serde_json::Value> = serde_json::from_slice(&call_result.result)?;
-                all_accounts.extend(accounts.clone());
-
-                if accounts.len() < limit as usize {
-                    break;
+        let e = match query_rpc(
+            primary_client,
+            secondary_client,
+            query_request(),
+            query_request,
+        )
+        .await
+        {
+            Ok(result) => match result.kind {
+                JsonRpcQueryResponseKind::CallResult(call_result) => {
+                    return Ok(serde_json::from_slice(&call_result.result)?);
+                }
+                _ => {
+                    return Err(IndexerError::Rpc(
+                        "Unexpected query response kind".to_string(),
+                    ))
                 }
+            },
+            Err(e) => e,
+        };
 
-                from_index += limit;
-            }
-            _ => return Err("Unexpected query response kind".into()),
+        let e = match not_a_staking_pool_reason(e.handler_error()) {
+            Some(reason) => IndexerError::NotAStakingPool {
+                account_id: account_id.to_string(),
+                reason,
+            },
+            None => IndexerError::from(e),
+        };
+        if !e.is_retryable() {
+            return Err(e);
+        }
+        if let IndexerError::RateLimited(_) = e {
+            metrics::record_rate_limited();
         }
+        retry_count += 1;
+        if retry_count >= max_retries {
+            error!(
+                "Failed to fetch accounts page from_index {} at block {} after {} retries",
+                from_index, block_height, max_retries
+            );
+            return Err(e);
+        }
+
+        retry_budget::reserve(
+            Duration::from_secs(backoff_time),
+            &format!(
+                "fetching accounts page from_index {} at block {}",
+                from_index, block_height
+            ),
+        )?;
+        info!(
+            "Error fetching accounts page from_index {} ({}/{}). Backing off for {} seconds: {:?}",
+            from_index, retry_count, max_retries, backoff_time, e
+        );
+        tokio::time::sleep(tokio::time::Duration::from_secs(backoff_time)).await;
+        backoff_time *= 2; // Exponential backoff
     }
+}
 
-    Ok(all_accounts)
+fn not_a_staking_pool_reason(error: Option<&RpcQueryError>) -> Option<String> {
+    match error? {
+        RpcQueryError::NoContractCode { .. } => Some("no contract deployed".to_string()),
+        RpcQueryError::ContractExecutionError { vm_error, .. }
+            if vm_error.contains("MethodNotFound") =>
+        {
+            Some("get_accounts method not found".to_string())
+        }
+        _ => None,
+    }
 }
 
 pub async fn get_block_info(
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
     height: u64,
-) -> Result<(u64, BlockView), Box<dyn std::error::Error>> {
+) -> Result<(u64, BlockView), IndexerError> {
     let max_retries = 5;
     let mut retry_count = 0;
     let mut backoff_time = 1; // Start with 1 second
@@ -277,16 +893,25 @@ pub async fn get_block_info(
                     "Successfully retrieved block info for height: {}",
                     current_height
                 );
+                BLOCK_THROTTLE.lock().unwrap().on_success();
                 return Ok((current_height, block));
             }
             Err(e) => {
-                if e.to_string().contains("UNKNOWN_BLOCK") {
+                let e = match e.handler_error() {
+                    Some(RpcBlockError::UnknownBlock { .. }) => {
+                        IndexerError::BlockNotFound(current_height)
+                    }
+                    _ => IndexerError::from(e),
+                };
+                if let IndexerError::BlockNotFound(_) = e {
                     info!("Block {} not found, trying next block.", current_height);
                     current_height += 1;
                     retry_count = 0; // Reset retry count for new block
                     backoff_time = 1; // Reset backoff time for new block
-                } else if e.to_string().contains("TooManyRequests") {
+                } else if let IndexerError::RateLimited(_) = e {
                     // Rate limit hit - back off and retry the same block
+                    metrics::record_rate_limited();
+                    BLOCK_THROTTLE.lock().unwrap().on_rate_limited();
                     retry_count += 1;
                     if retry_count >= max_retries {
                         info!(
@@ -299,6 +924,10 @@ pub async fn get_block_info(
                         continue;
                     }
 
+                    retry_budget::reserve(
+                        Duration::from_secs(backoff_time),
+                        &format!("getting block {}", current_height),
+                    )?;
                     info!("Rate limit hit when getting block {}. Backing off for {} seconds (retry {}/{})", 
                           current_height, backoff_time, retry_count, max_retries);
                     tokio::time::sleep(tokio::time::Duration::from_secs(backoff_time)).await;
@@ -320,6 +949,10 @@ pub async fn get_block_info(
                         continue;
                     }
 
+                    retry_budget::reserve(
+                        Duration::from_secs(backoff_time),
+                        &format!("getting block {}", current_height),
+                    )?;
                     info!(
                         "Retrying after error ({}/{}). Waiting {} seconds",
                         retry_count, max_retries, backoff_time
@@ -331,13 +964,113 @@ pub async fn get_block_info(
         }
     }
 }
+
+/// Converts a block timestamp in nanoseconds, keeping millisecond precision.
+pub fn block_timestamp(timestamp_nanos: u64) -> DateTime<Utc> {
+    DateTime::<Utc>::from_timestamp_millis((timestamp_nanos / 1_000_000) as i64)
+        .expect("block timestamp out of range")
+}
+
+/// First block at or after `target`, or the latest block if `target` is in the future. Binary
+/// searches block heights between the node's earliest block and the tip, like
+/// `find_epoch_boundary`, relying on block timestamps increasing with height.
+pub async fn height_at_timestamp(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    target: DateTime<Utc>,
+) -> Result<u64, IndexerError> {
+    let target_nanos = target
+        .timestamp_nanos_opt()
+        .ok_or_else(|| IndexerError::Config(format!("Timestamp {} is out of range", target)))?
+        .max(0) as u64;
+
+    let latest_block = get_latest_block(primary_client, secondary_client).await?;
+    if latest_block.header.timestamp < target_nanos {
+        warn!(
+            "{} is after the latest block {}, using it",
+            target, latest_block.header.height
+        );
+        return Ok(latest_block.header.height);
+    }
+
+    let status = query_rpc(
+        primary_client,
+        secondary_client,
+        methods::status::RpcStatusRequest,
+        || methods::status::RpcStatusRequest,
+    )
+    .await?;
+    let earliest_height = status.sync_info.earliest_block_height.unwrap_or(0);
+    let (earliest_height, earliest_nanos) =
+        probe_block(primary_client, secondary_client, earliest_height).await?;
+    if earliest_nanos >= target_nanos {
+        return Ok(earliest_height);
+    }
+
+    // Invariants: every block at or below `low` is before the target, `high` is a block at or
+    // after it, and there is no block in `limit..high`
+    let mut low = earliest_height;
+    let mut high = latest_block.header.height;
+    let mut limit = high;
+    while limit.saturating_sub(low) > 1 {
+        let mid = low + (limit - low) / 2;
+        let (height, timestamp_nanos) = probe_block(primary_client, secondary_client, mid).await?;
+        if timestamp_nanos >= target_nanos {
+            high = height;
+            limit = mid;
+        } else {
+            low = height;
+        }
+    }
+
+    info!("First block at or after {} is {}", target, high);
+    Ok(high)
+}
+
+/// Height and timestamp of the first block at or after `height`, through `PROBED_BLOCKS`.
+async fn probe_block(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    height: u64,
+) -> Result<(u64, u64), IndexerError> {
+    if let Some(probed) = PROBED_BLOCKS.lock().unwrap().get(&height) {
+        return Ok(*probed);
+    }
+    let (actual_height, block) = get_block_info(primary_client, secondary_client, height).await?;
+    let probed = (actual_height, block.header.timestamp);
+    if is_final(primary_client, secondary_client, actual_height).await? {
+        PROBED_BLOCKS.lock().unwrap().put(height, probed);
+    }
+    Ok(probed)
+}
+
+/// Start of an epoch found by the boundary search.
+struct Boundary {
+    height: u64,
+    epoch_id: String,
+    timestamp_nanos: u64,
+    block_hash: String,
+    /// Whether the epoch change was actually seen, see `find_epoch_boundary`.
+    verified: bool,
+}
+
+/// Segments of a parallel sync span at least this many epochs, so the boundary search of the
+/// first, partial epoch of each segment stays a small share of its work.
+const MIN_SEGMENT_EPOCHS: u64 = 10;
+
+/// Finds the epochs from `start_block_height` to the settled tip. With `parallel_segments`,
+/// the range is split into that many segments searched concurrently and their boundaries
+/// merged; otherwise the boundaries are searched one after another.
+#[tracing::instrument(skip_all, fields(start_block = start_block_height))]
 pub async fn get_epoch_data(
     start_block_height: u64,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-    batch_size: usize,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    _batch_size: usize,
     epoch_blocks: u64,
-) -> Result<Vec<EpochInfo>, Box<dyn std::error::Error>> {
+    inter_request_delay_ms: Option<u64>,
+    parallel_segments: Option<usize>,
+) -> Result<Vec<EpochInfo>, IndexerError> {
     info!("===== EPOCH DATA GENERATION STARTED =====");
     info!(
         "Starting epoch data generation from block height: {}",
@@ -345,7 +1078,7 @@ pub async fn get_epoch_data(
     );
 
     // Get current block to know when to stop
-    let current_block = get_latest_block_height(primary_client, secondary_client).await?;
+    let current_block = get_settled_block_height(primary_client, secondary_client).await?;
     info!("Current block height: {}", current_block);
 
     // Get initial block and its epoch ID
@@ -357,22 +1090,185 @@ pub async fn get_epoch_data(
         start_block_height, initial_epoch_id
     );
 
+    let boundaries = match parallel_segments {
+        Some(segments) => {
+            find_boundaries_segmented(
+                start_block_height,
+                current_block,
+                segments,
+                primary_client,
+                secondary_client,
+                epoch_blocks,
+                inter_request_delay_ms,
+            )
+            .await?
+        }
+        None => {
+            find_boundaries(
+                start_block_height,
+                initial_epoch_id,
+                current_block,
+                current_block,
+                primary_client,
+                secondary_client,
+                epoch_blocks,
+                inter_request_delay_ms,
+            )
+            .await?
+        }
+    };
+
+    let first = Boundary {
+        height: start_block_height,
+        epoch_id: initial_block.header.epoch_id.to_string(),
+        timestamp_nanos: initial_block.header.timestamp,
+        block_hash: initial_block.header.hash.to_string(),
+        verified: true,
+    };
+    let epochs = epochs_between(first, boundaries, current_block, epoch_blocks);
+
+    info!("===== EPOCH DATA GENERATION COMPLETE =====");
+    info!("Generated {} epoch boundaries", epochs.len());
+
+    for (i, epoch) in epochs.iter().enumerate() {
+        info!(
+            "Epoch {} (ID: {}) - Start: {}, End: {}",
+            i + 1,
+            epoch.epoch_id,
+            epoch.start_block,
+            epoch.end_block.unwrap_or(0)
+        );
+    }
+
+    Ok(epochs)
+}
+
+/// Epochs from `first` on, split at `boundaries`. The last one is still running and ends at
+/// `current_block`.
+fn epochs_between(
+    first: Boundary,
+    boundaries: Vec<Boundary>,
+    current_block: u64,
+    epoch_blocks: u64,
+) -> Vec<EpochInfo> {
     // Initialize data structures to track epochs
     let mut epochs = Vec::new();
-    let mut current_height = start_block_height;
-    let mut current_epoch_id = initial_epoch_id;
-    let mut epoch_start_block = current_height;
-    let mut epoch_timestamp = DateTime::<Utc>::from_utc(
-        chrono::NaiveDateTime::from_timestamp_opt(
-            (initial_block.header.timestamp / 1_000_000_000) as i64,
-            0,
-        )
-        .unwrap(),
-        Utc,
+    let mut current_epoch_id = first.epoch_id;
+    let mut epoch_start_block = first.height;
+    let mut epoch_timestamp = block_timestamp(first.timestamp_nanos);
+    let mut epoch_block_hash = first.block_hash;
+
+    for boundary in boundaries {
+        // Record the current epoch
+        epochs.push(EpochInfo {
+            start_block: epoch_start_block,
+            end_block: Some(boundary.height - 1),
+            epoch_id: current_epoch_id,
+            timestamp: epoch_timestamp,
+            block_hash: Some(epoch_block_hash),
+            finalized: boundary.height - 1 + epoch_blocks <= current_block,
+            boundary_verified: boundary.verified,
+        });
+
+        // Update tracking variables for next epoch
+        current_epoch_id = boundary.epoch_id;
+        epoch_start_block = boundary.height;
+        epoch_timestamp = block_timestamp(boundary.timestamp_nanos);
+        epoch_block_hash = boundary.block_hash;
+    }
+
+    // Add the final epoch (partial) that reaches to the current block
+    if epoch_start_block < current_block {
+        info!(
+            "Adding final partial epoch {} from {} to {}",
+            current_epoch_id, epoch_start_block, current_block
+        );
+
+        epochs.push(EpochInfo {
+            start_block: epoch_start_block,
+            end_block: Some(current_block),
+            epoch_id: current_epoch_id,
+            timestamp: epoch_timestamp,
+            block_hash: Some(epoch_block_hash),
+            finalized: false,
+            boundary_verified: true,
+        });
+    }
+    epochs
+}
+
+/// Splits `start_block..current_block` into up to `segments` ranges and searches each for its
+/// own boundaries concurrently. A segment's last boundary can lie past its end, where the
+/// next segment finds it again, so boundaries are deduplicated by epoch id.
+async fn find_boundaries_segmented(
+    start_block: u64,
+    current_block: u64,
+    segments: usize,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    epoch_blocks: u64,
+    inter_request_delay_ms: Option<u64>,
+) -> Result<Vec<Boundary>, IndexerError> {
+    let segments = segments.max(1);
+    let segment_blocks = current_block
+        .saturating_sub(start_block)
+        .div_ceil(segments as u64)
+        .max(epoch_blocks * MIN_SEGMENT_EPOCHS);
+    let segment_starts: Vec<u64> = (start_block..current_block)
+        .step_by(segment_blocks as usize)
+        .collect();
+    info!(
+        "Searching epoch boundaries in {} segments of {} blocks",
+        segment_starts.len(),
+        segment_blocks
     );
 
-    // Process epochs until we reach current block
-    while current_height < current_block {
+    let segment_boundaries: Vec<Vec<Boundary>> = stream::iter(segment_starts)
+        .map(|segment_start| async move {
+            let (height, block) =
+                get_block_info(primary_client, secondary_client, segment_start).await?;
+            find_boundaries(
+                height,
+                block.header.epoch_id.to_string(),
+                (segment_start + segment_blocks).min(current_block),
+                current_block,
+                primary_client,
+                secondary_client,
+                epoch_blocks,
+                inter_request_delay_ms,
+            )
+            .await
+        })
+        .buffered(segments)
+        .try_collect()
+        .await?;
+
+    let mut boundaries: Vec<Boundary> = segment_boundaries.into_iter().flatten().collect();
+    boundaries.sort_by_key(|boundary| boundary.height);
+    let mut seen = HashSet::new();
+    boundaries.retain(|boundary| seen.insert(boundary.epoch_id.clone()));
+    Ok(boundaries)
+}
+
+/// Walks epoch by epoch from `from_block`, which is in `from_epoch_id`, and returns the
+/// boundaries found until one reaches `segment_end`. Stops earlier once the next epoch would
+/// end past `current_block`.
+#[allow(clippy::too_many_arguments)]
+async fn find_boundaries(
+    from_block: u64,
+    from_epoch_id: String,
+    segment_end: u64,
+    current_block: u64,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    epoch_blocks: u64,
+    inter_request_delay_ms: Option<u64>,
+) -> Result<Vec<Boundary>, IndexerError> {
+    let mut boundaries = Vec::new();
+    let mut current_height = from_block;
+    let mut current_epoch_id = from_epoch_id;
+
+    while current_height < segment_end {
         // Estimate next epoch boundary using approximate epoch length
         let estimated_next_epoch_start = current_height + epoch_blocks;
 
@@ -381,14 +1277,23 @@ pub async fn get_epoch_data(
             break;
         }
 
-        let boundary = find_epoch_boundary(
+        let (boundary, boundary_verified) = find_epoch_boundary(
             current_height,
             estimated_next_epoch_start + epoch_blocks / 2, // Add some buffer
             &current_epoch_id,
             primary_client,
             secondary_client,
+            inter_request_delay_ms,
         )
         .await?;
+        if !boundary_verified {
+            warn!(
+                "No epoch change found after block {}, approximating the end of epoch {} at block {}",
+                current_height,
+                current_epoch_id,
+                boundary - 1
+            );
+        }
 
         info!(
             "Found epoch boundary: Current epoch {} ends at block {}",
@@ -399,76 +1304,59 @@ pub async fn get_epoch_data(
         // Get the new epoch ID from the boundary block
         let (_, boundary_block) =
             get_block_info(primary_client, secondary_client, boundary).await?;
-        let next_epoch_id = boundary_block.header.epoch_id.to_string();
-
-        // Record the current epoch
-        epochs.push(EpochInfo {
-            start_block: epoch_start_block,
-            end_block: Some(boundary - 1),
-            epoch_id: current_epoch_id,
-            timestamp: epoch_timestamp,
-        });
-
-        // Update tracking variables for next epoch
+        current_epoch_id = boundary_block.header.epoch_id.to_string();
         current_height = boundary;
-        current_epoch_id = next_epoch_id;
-        epoch_start_block = boundary;
-        epoch_timestamp = DateTime::<Utc>::from_utc(
-            chrono::NaiveDateTime::from_timestamp_opt(
-                (boundary_block.header.timestamp / 1_000_000_000) as i64,
-                0,
-            )
-            .unwrap(),
-            Utc,
-        );
-
         info!(
             "New epoch {} starts at block {}",
-            current_epoch_id, epoch_start_block
+            current_epoch_id, current_height
         );
+        boundaries.push(Boundary {
+            height: boundary,
+            epoch_id: current_epoch_id.clone(),
+            timestamp_nanos: boundary_block.header.timestamp,
+            block_hash: boundary_block.header.hash.to_string(),
+            verified: boundary_verified,
+        });
 
         // Add a small delay to avoid rate limiting
-        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        tokio::time::sleep(inter_request_delay(inter_request_delay_ms)).await;
     }
 
-    // Add the final epoch (partial) that reaches to the current block
-    if epoch_start_block < current_block {
-        info!(
-            "Adding final partial epoch {} from {} to {}",
-            current_epoch_id, epoch_start_block, current_block
-        );
+    Ok(boundaries)
+}
 
-        epochs.push(EpochInfo {
-            start_block: epoch_start_block,
-            end_block: Some(current_block),
-            epoch_id: current_epoch_id,
-            timestamp: epoch_timestamp,
-        });
+/// `get_block_info` for the boundary search, keeping only the height and epoch id, through
+/// `SEARCHED_BLOCKS`.
+async fn searched_block_epoch(
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    height: u64,
+) -> Result<(u64, String), IndexerError> {
+    if let Some(searched) = SEARCHED_BLOCKS.lock().unwrap().get(&height) {
+        return Ok(searched.clone());
     }
-
-    info!("===== EPOCH DATA GENERATION COMPLETE =====");
-    info!("Generated {} epoch boundaries", epochs.len());
-
-    for (i, epoch) in epochs.iter().enumerate() {
-        info!(
-            "Epoch {} (ID: {}) - Start: {}, End: {}",
-            i + 1,
-            epoch.epoch_id,
-            epoch.start_block,
-            epoch.end_block.unwrap_or(0)
-        );
+    let (actual_height, block) = get_block_info(primary_client, secondary_client, height).await?;
+    let searched = (actual_height, block.header.epoch_id.to_string());
+    if is_final(primary_client, secondary_client, actual_height).await? {
+        SEARCHED_BLOCKS
+            .lock()
+            .unwrap()
+            .put(height, searched.clone());
     }
-
-    Ok(epochs)
+    Ok(searched)
 }
 
-async fn find_epoch_boundary(
+/// First block after `current_epoch_id` between `start_block` and `end_block`, and whether it
+/// was found. When it wasn't, the block after the searched window is returned as an estimate.
+#[tracing::instrument(skip_all, fields(start_block = start_block, end_block = end_block))]
+pub async fn find_epoch_boundary(
     start_block: u64,
     end_block: u64,
     current_epoch_id: &str,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<u64, Box<dyn std::error::Error>> {
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    inter_request_delay_ms: Option<u64>,
+) -> Result<(u64, bool), IndexerError> {
     info!(
         "Binary searching for epoch boundary between blocks {} and {}",
         start_block, end_block
@@ -486,6 +1374,7 @@ async fn find_epoch_boundary(
                 current_epoch_id,
                 primary_client,
                 secondary_client,
+                inter_request_delay_ms,
             )
             .await;
         }
@@ -494,10 +1383,8 @@ async fn find_epoch_boundary(
         info!("Checking block {}", mid);
 
         // Get epoch ID for the middle block
-        match get_block_info(primary_client, secondary_client, mid).await {
-            Ok((actual_height, block)) => {
-                let mid_epoch_id = block.header.epoch_id.to_string();
-
+        match searched_block_epoch(primary_client, secondary_client, mid).await {
+            Ok((actual_height, mid_epoch_id)) => {
                 if mid_epoch_id == current_epoch_id {
                     // Still in the same epoch, boundary is higher
                     low = actual_height + 1;
@@ -514,19 +1401,21 @@ async fn find_epoch_boundary(
         }
 
         // Add a small delay to avoid rate limiting
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        tokio::time::sleep(inter_request_delay(inter_request_delay_ms)).await;
     }
 
-    // If we get here, low is the boundary
-    Ok(low)
+    // Only reached when block lookups failed all the way up, so low is a guess
+    Ok((low, false))
 }
+
 async fn find_boundary_linear(
     start_block: u64,
     end_block: u64,
     current_epoch_id: &str,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<u64, Box<dyn std::error::Error>> {
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    inter_request_delay_ms: Option<u64>,
+) -> Result<(u64, bool), IndexerError> {
     info!(
         "Linear searching for exact boundary between blocks {} and {}",
         start_block, end_block
@@ -535,13 +1424,11 @@ async fn find_boundary_linear(
     let mut current = start_block;
 
     while current <= end_block {
-        match get_block_info(primary_client, secondary_client, current).await {
-            Ok((_, block)) => {
-                let block_epoch_id = block.header.epoch_id.to_string();
-
+        match searched_block_epoch(primary_client, secondary_client, current).await {
+            Ok((_, block_epoch_id)) => {
                 if block_epoch_id != current_epoch_id {
                     // Found the boundary
-                    return Ok(current);
+                    return Ok((current, true));
                 }
             }
             Err(_) => {
@@ -553,30 +1440,16 @@ async fn find_boundary_linear(
         current += 1;
 
         // Add a small delay to avoid rate limiting
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        tokio::time::sleep(inter_request_delay(inter_request_delay_ms)).await;
     }
 
     // If we didn't find a boundary, return the block after the end
-    Ok(end_block + 1)
-}
-
-async fn batch_query_blocks(
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-    heights: Vec<u64>,
-) -> Vec<(u64, BlockView)> {
-    let mut results = Vec::new();
-    for height in heights {
-        if let Ok(result) = get_block_info(primary_client, secondary_client, height).await {
-            results.push(result);
-        }
-    }
-    results
+    Ok((end_block + 1, false))
 }
 
 // async fn find_epoch_start_blocks(
-//     primary_client: &JsonRpcClient,
-//     secondary_client: &JsonRpcClient,
+//     primary_client: &RpcClient,
+//     secondary_client: &RpcClient,
 //     start_block_height: u64,
 //     batch_size: usize,
 //     epoch_blocks: u64,
@@ -610,7 +1483,8 @@ async fn batch_query_blocks(
 //         }
 
 //         let batch_results =
-//             batch_query_blocks(primary_client, secondary_client, batch_heights).await;
+//             batch_query_blocks(primary_client, secondary_client, batch_heights, batch_size)
+//                 .await;
 
 //         for (i, (height, block)) in batch_results.iter().enumerate() {
 //             if i == 0 || block.header.epoch_id != batch_results[i - 1].1.header.epoch_id {
@@ -647,3 +1521,184 @@ async fn batch_query_blocks(
 
 //     Ok(epoch_start_blocks)
 // }
+
+/// Local JSON-RPC endpoint for tests.
+#[cfg(test)]
+pub mod mock {
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Answers every JSON-RPC request with `result`, except the first `rate_limited` ones with
+    /// 429. Returns the server's URL and the number of requests it received.
+    pub async fn serve(rate_limited: usize, result: Value) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = axum::Router::new().route(
+            "/",
+            axum::routing::post(move || {
+                let counter = counter.clone();
+                let result = result.clone();
+                async move {
+                    if counter.fetch_add(1, Ordering::SeqCst) < rate_limited {
+                        return Err(axum::http::StatusCode::TOO_MANY_REQUESTS);
+                    }
+                    Ok(axum::Json(json!({
+                        "jsonrpc": "2.0",
+                        "id": "dontcare",
+                        "result": result,
+                    })))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}", addr), requests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clients for an endpoint nothing listens on, so any call that reaches RPC fails.
+    fn unreachable_client() -> RpcClient {
+        RpcClient::new(JsonRpcClient::connect("http://127.0.0.1:9"))
+    }
+
+    #[tokio::test]
+    async fn cached_probe_skips_the_rpc_call() {
+        let client = unreachable_client();
+        let height = u64::MAX - 1_575;
+        PROBED_BLOCKS
+            .lock()
+            .unwrap()
+            .put(height, (height + 1, 1_700_000_000_000_000_000));
+
+        let (probed, rpc_calls) =
+            telemetry::count_rpc_calls(probe_block(&client, &client, height)).await;
+        assert_eq!(probed.unwrap(), (height + 1, 1_700_000_000_000_000_000));
+        assert_eq!(rpc_calls, 0);
+    }
+
+    #[tokio::test]
+    async fn cached_boundary_lookup_skips_the_rpc_call() {
+        let client = unreachable_client();
+        let height = u64::MAX - 1_596;
+        let searched = (height, "epoch-id".to_string());
+        SEARCHED_BLOCKS
+            .lock()
+            .unwrap()
+            .put(height, searched.clone());
+
+        let (result, rpc_calls) =
+            telemetry::count_rpc_calls(searched_block_epoch(&client, &client, height)).await;
+        assert_eq!(result.unwrap(), searched);
+        assert_eq!(rpc_calls, 0);
+    }
+
+    #[test]
+    fn each_client_has_its_own_breaker() {
+        // Same endpoint, but breakers belong to the clients rather than the address
+        let failing = unreachable_client();
+        let other = unreachable_client();
+        for _ in 0..5 {
+            failing.record_result(false);
+        }
+        assert!(!failing.allow_request());
+        assert!(other.allow_request());
+    }
+
+    #[test]
+    fn validator_is_found_in_current_validators() {
+        let validators_info = serde_json::json!({
+            "jsonrpc": "2.0",
+            "result": {
+                "current_validators": [
+                    { "account_id": "other.poolv1.near", "stake": "1" },
+                    { "account_id": "pool.poolv1.near", "stake": "2" },
+                ],
+                "next_validators": [{ "account_id": "kicked.poolv1.near" }],
+            },
+        });
+        assert_eq!(
+            is_current_validator(&validators_info, "pool.poolv1.near"),
+            Some(true)
+        );
+        // Only in the next epoch's set, so kicked out of this one
+        assert_eq!(
+            is_current_validator(&validators_info, "kicked.poolv1.near"),
+            Some(false)
+        );
+
+        let error = serde_json::json!({ "jsonrpc": "2.0", "error": { "code": -32000 } });
+        assert_eq!(is_current_validator(&error, "pool.poolv1.near"), None);
+    }
+
+    #[test]
+    fn fractional_block_timestamp_keeps_its_milliseconds() {
+        // Stored as `epoch.timestamp.timestamp_millis() as u64`
+        let timestamp = block_timestamp(1_717_000_000_123_456_789);
+        assert_eq!(timestamp.timestamp_millis() as u64, 1_717_000_000_123);
+    }
+
+    #[test]
+    fn open_epoch_ends_tip_lag_blocks_below_the_tip() {
+        let boundary = |height: u64, epoch_id: &str| Boundary {
+            height,
+            epoch_id: epoch_id.to_string(),
+            timestamp_nanos: 1_717_000_000_000_000_000,
+            block_hash: format!("hash-{}", height),
+            verified: true,
+        };
+        let current_block = settled_height(1_100, 30);
+        assert_eq!(current_block, 1_070);
+
+        let epochs = epochs_between(
+            boundary(900, "epoch-1"),
+            vec![boundary(1_000, "epoch-2")],
+            current_block,
+            100,
+        );
+        let ends: Vec<_> = epochs.iter().map(|epoch| epoch.end_block).collect();
+        assert_eq!(ends, [Some(999), Some(1_070)]);
+        assert_eq!(epochs[1].epoch_id, "epoch-2");
+        assert_eq!(settled_height(20, 30), 0);
+    }
+
+    #[tokio::test]
+    async fn boundary_missing_from_the_window_is_flagged_as_estimated() {
+        let client = unreachable_client();
+        let start = u64::MAX - 1_595_000;
+        for height in start..=start + 3 {
+            SEARCHED_BLOCKS
+                .lock()
+                .unwrap()
+                .put(height, (height, "epoch-1".to_string()));
+        }
+
+        let (boundary, verified) =
+            find_epoch_boundary(start, start + 3, "epoch-1", &client, &client, Some(0))
+                .await
+                .unwrap();
+        assert_eq!(boundary, start + 4);
+        assert!(!verified);
+
+        let boundary_at = |height: u64, epoch_id: &str, verified: bool| Boundary {
+            height,
+            epoch_id: epoch_id.to_string(),
+            timestamp_nanos: 0,
+            block_hash: String::new(),
+            verified,
+        };
+        let epochs = epochs_between(
+            boundary_at(start - 100, "epoch-1", true),
+            vec![boundary_at(boundary, "epoch-2", verified)],
+            start + 50,
+            1_000,
+        );
+        assert!(!epochs[0].boundary_verified);
+        assert!(epochs[1].boundary_verified);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::error::IndexerError;
+use log::{info, warn};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Backoff time left in the current run, set up by `init`.
+static BUDGET: OnceLock<Mutex<RetryBudget>> = OnceLock::new();
+
+/// Caps the total time the backoff loops sleep, so a persistently failing endpoint fails the
+/// run instead of stalling it for hours.
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    limit: Duration,
+    spent: Duration,
+}
+
+impl RetryBudget {
+    pub fn new(limit: Duration) -> Self {
+        Self {
+            limit,
+            spent: Duration::ZERO,
+        }
+    }
+
+    /// Takes `wait` out of the budget, or returns false if it doesn't fit in what's left.
+    pub fn reserve(&mut self, wait: Duration) -> bool {
+        if self.spent + wait > self.limit {
+            return false;
+        }
+        self.spent += wait;
+        true
+    }
+
+    pub fn reset(&mut self) {
+        self.spent = Duration::ZERO;
+    }
+}
+
+/// Limits each run's backoff sleeps to `limit_secs` seconds in total. Without a limit the
+/// backoff loops only stop at their own retry counts.
+pub fn init(limit_secs: Option<u64>) {
+    let Some(limit_secs) = limit_secs else {
+        return;
+    };
+    if BUDGET
+        .set(Mutex::new(RetryBudget::new(Duration::from_secs(
+            limit_secs,
+        ))))
+        .is_err()
+    {
+        warn!("Retry budget was already set");
+        return;
+    }
+    info!("Retry backoff limited to {} seconds per run", limit_secs);
+}
+
+/// Starts a new run with the full budget.
+pub fn reset() {
+    if let Some(budget) = BUDGET.get() {
+        budget.lock().unwrap().reset();
+    }
+}
+
+/// Called before a backoff sleep of `wait`. Fails with `RetryBudgetExhausted` once the run has
+/// used up its budget, naming the `operation` that was about to retry.
+pub fn reserve(wait: Duration, operation: &str) -> Result<(), IndexerError> {
+    let Some(budget) = BUDGET.get() else {
+        return Ok(());
+    };
+    let mut budget = budget.lock().unwrap();
+    if budget.reserve(wait) {
+        Ok(())
+    } else {
+        Err(IndexerError::RetryBudgetExhausted {
+            operation: operation.to_string(),
+            spent_secs: budget.spent.as_secs(),
+            limit_secs: budget.limit.as_secs(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_beyond_the_limit_are_refused() {
+        let mut budget = RetryBudget::new(Duration::from_secs(10));
+        assert!(budget.reserve(Duration::from_secs(4)));
+        assert!(budget.reserve(Duration::from_secs(6)));
+        assert!(!budget.reserve(Duration::from_secs(1)));
+
+        budget.reset();
+        assert!(budget.reserve(Duration::from_secs(8)));
+        assert!(!budget.reserve(Duration::from_secs(4)));
+    }
+}
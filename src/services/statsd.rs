@@ -0,0 +1,47 @@
+use log::{info, warn};
+use std::net::UdpSocket;
+use std::sync::OnceLock;
+
+static SOCKET: OnceLock<UdpSocket> = OnceLock::new();
+
+const PREFIX: &str = "near_indexer";
+
+/// Sends StatsD metrics over UDP to `addr` (`host:port`). Without an address, or if the socket
+/// can't be set up, the emitters below do nothing.
+pub fn init(addr: Option<&str>) {
+    let Some(addr) = addr else {
+        return;
+    };
+    let socket = UdpSocket::bind("0.0.0.0:0").and_then(|socket| {
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    });
+    match socket {
+        Ok(socket) => {
+            let _ = SOCKET.set(socket);
+            info!("Sending StatsD metrics to {}", addr);
+        }
+        Err(e) => warn!("Failed to set up StatsD at {}: {}", addr, e),
+    }
+}
+
+pub fn count(name: &str, value: u64) {
+    send(&format!("{}.{}:{}|c", PREFIX, name, value));
+}
+
+pub fn gauge(name: &str, value: f64) {
+    send(&format!("{}.{}:{}|g", PREFIX, name, value));
+}
+
+/// Metric name segment for a pool; the dots of account ids would otherwise nest the metric.
+pub fn pool_segment(validator_account_id: &str) -> String {
+    validator_account_id.replace('.', "_")
+}
+
+fn send(line: &str) {
+    // Metrics are best effort, a dropped datagram must never fail indexing
+    if let Some(socket) = SOCKET.get() {
+        let _ = socket.send(line.as_bytes());
+    }
+}
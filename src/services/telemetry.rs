@@ -0,0 +1,73 @@
+use log::{info, warn};
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use std::cell::Cell;
+use std::future::Future;
+use tracing_subscriber::layer::SubscriberExt;
+
+tokio::task_local! {
+    static RPC_CALLS: Cell<u64>;
+}
+
+/// Exports tracing spans over OTLP/gRPC to `endpoint`. Without an endpoint no subscriber is
+/// installed, so the spans cost next to nothing. Logging stays with env_logger either way.
+pub fn init(endpoint: Option<&str>) {
+    let Some(endpoint) = endpoint else {
+        return;
+    };
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                "near-indexer",
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio);
+    let tracer = match tracer {
+        Ok(tracer) => tracer,
+        Err(e) => {
+            warn!(
+                "Failed to set up the OTLP exporter, tracing is disabled: {:?}",
+                e
+            );
+            return;
+        }
+    };
+
+    // `set_global_default` rather than `init`, which would also try to take over the `log`
+    // facade that env_logger already owns
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    match tracing::subscriber::set_global_default(subscriber) {
+        Ok(()) => info!("Exporting traces to {}", endpoint),
+        Err(e) => warn!("Failed to install the tracing subscriber: {:?}", e),
+    }
+}
+
+/// Flushes the spans that haven't been exported yet.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Runs `future` and returns its output with the number of RPC calls made while it ran.
+pub async fn count_rpc_calls<F: Future>(future: F) -> (F::Output, u64) {
+    RPC_CALLS
+        .scope(Cell::new(0), async move {
+            let output = future.await;
+            (output, RPC_CALLS.with(Cell::get))
+        })
+        .await
+}
+
+/// Counts an RPC call towards the enclosing `count_rpc_calls`, if any.
+pub fn record_rpc_call() {
+    let _ = RPC_CALLS.try_with(|calls| calls.set(calls.get() + 1));
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+/// Delay between requests that adapts to the endpoint: it doubles whenever a request is rate
+/// limited and shrinks by a fixed step after each success (AIMD), so a generous provider is
+/// queried at full speed while a strict one is backed off from.
+#[derive(Debug, Clone)]
+pub struct AdaptiveThrottle {
+    delay: Duration,
+    min_delay: Duration,
+    max_delay: Duration,
+    decrease_step: Duration,
+}
+
+impl AdaptiveThrottle {
+    pub const fn new(min_delay: Duration, max_delay: Duration, decrease_step: Duration) -> Self {
+        Self {
+            delay: min_delay,
+            min_delay,
+            max_delay,
+            decrease_step,
+        }
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    pub fn on_success(&mut self) {
+        self.delay = self
+            .delay
+            .saturating_sub(self.decrease_step)
+            .max(self.min_delay);
+    }
+
+    pub fn on_rate_limited(&mut self) {
+        // Doubling from zero would never back off, so grow from at least one step
+        self.delay = (self.delay * 2).max(self.decrease_step).min(self.max_delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_on_rate_limits_and_recovers_on_success() {
+        let mut throttle = AdaptiveThrottle::new(
+            Duration::ZERO,
+            Duration::from_millis(100),
+            Duration::from_millis(20),
+        );
+        throttle.on_rate_limited();
+        assert_eq!(throttle.delay(), Duration::from_millis(20));
+        throttle.on_rate_limited();
+        throttle.on_rate_limited();
+        assert_eq!(throttle.delay(), Duration::from_millis(80));
+        throttle.on_rate_limited();
+        assert_eq!(throttle.delay(), Duration::from_millis(100));
+
+        throttle.on_success();
+        assert_eq!(throttle.delay(), Duration::from_millis(80));
+        for _ in 0..10 {
+            throttle.on_success();
+        }
+        assert_eq!(throttle.delay(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,56 @@
+use log::{info, warn};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const MAX_ATTEMPTS: u32 = 4;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sent to `WEBHOOK_URL` after an epoch has been saved.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpochNotification {
+    pub validator_account_id: String,
+    pub epoch_id: String,
+    pub epoch: u64,
+    pub apy: Option<f64>,
+    pub total_staked: String,
+}
+
+/// Posts the notification in the background so a slow or unreachable webhook never holds up
+/// indexing. Failed attempts are retried with exponential backoff and then dropped.
+pub fn notify_epoch(client: reqwest::Client, url: &str, notification: EpochNotification) {
+    let url = url.to_string();
+    tokio::spawn(async move {
+        for attempt in 0..MAX_ATTEMPTS {
+            let result = client
+                .post(&url)
+                .timeout(REQUEST_TIMEOUT)
+                .json(&notification)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => {
+                    info!(
+                        "Notified webhook of epoch {} for {}",
+                        notification.epoch_id, notification.validator_account_id
+                    );
+                    return;
+                }
+                Err(e) if attempt + 1 < MAX_ATTEMPTS => {
+                    let delay = Duration::from_secs(1 << attempt);
+                    warn!(
+                        "Webhook notification for epoch {} failed, retrying in {:?}: {:?}",
+                        notification.epoch_id, delay, e
+                    );
+                    sleep(delay).await;
+                }
+                Err(e) => warn!(
+                    "Giving up on webhook notification for epoch {} after {} attempts: {:?}",
+                    notification.epoch_id, MAX_ATTEMPTS, e
+                ),
+            }
+        }
+    });
+}
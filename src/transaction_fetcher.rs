@@ -1,68 +1,232 @@
-use crate::config::Config;
-use crate::models::Transaction;
-use crate::repositories::transaction_repository;
+use crate::config::{Config, TxnSource};
+use crate::error::IndexerError;
+use crate::models::{Action, ActionsAgg, Block, NearblocksTxn, Transaction};
+use crate::repositories::{
+    raw_transaction_repository, scan_cursor_repository, transaction_repository,
+};
+use crate::services::near_rpc::RpcClient;
+use crate::services::staking_parser::{
+    self, determine_type, safe_parse_amount, CallAmount, StakingAction, StakingCall,
+};
+use crate::services::{error_summary, metrics, near_rpc, retry_budget};
 use chrono::{DateTime, Utc};
-use log::{info, warn};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use log::{debug, error, info, warn};
 use mongodb::Database;
-use near_jsonrpc_client::{methods, JsonRpcClient};
-use near_jsonrpc_primitives::types::query::QueryResponseKind;
-use near_primitives::types::{BlockReference, FunctionArgs};
-use num_bigint::BigInt;
+use near_jsonrpc_client::methods;
+use near_jsonrpc_primitives::types::transactions::RpcTransactionError;
+use near_primitives::views::{ActionView, BlockView, SignedTransactionView};
+use rand::Rng;
 use serde_json::Value;
-use std::error::Error;
+use std::collections::HashSet;
 use std::str::FromStr;
+use std::time::Instant;
 use tokio::time::{sleep, Duration};
 
+/// Staking transactions found in a fetch, and how many of them were newly written to MongoDB.
+#[derive(Debug, Default)]
+pub struct StoredTransactions {
+    pub transactions: Vec<Transaction>,
+    pub saved: usize,
+    /// Lowest block height of a transaction that failed analysis and wasn't stored, if any
+    pub first_failed_block: Option<u64>,
+}
+
+#[tracing::instrument(skip_all, fields(validator = %config.validator_account_id))]
 pub async fn fetch_and_process_transactions(
     config: &Config,
     db: &Database,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let last_transaction = transaction_repository::get_latest_transaction(db).await?;
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<StoredTransactions, IndexerError> {
+    let last_transaction =
+        transaction_repository::get_latest_transaction(db, &config.validator_account_id).await?;
     let last_block_height = last_transaction.map(|t| t.block_height).unwrap_or(0); // Default to 0 if no transactions exist
 
+    // Re-scan a window below the last stored height so transactions that nearblocks indexed
+    // late are still picked up. Ones already stored are dropped here, and anything that slips
+    // through is skipped by save_transactions via the unique transaction_hash index.
+    // FETCH_FROM_BLOCK replaces the derived height to re-pull a known-bad window.
+    if config.fetch_from_block.is_some() {
+        info!(
+            "FETCH_FROM_BLOCK is set, ignoring the latest stored block height {}",
+            last_block_height
+        );
+    }
+    let from_block_height = refetch_from(
+        last_block_height,
+        config.fetch_from_block,
+        config.refetch_overlap_blocks,
+    );
+
     info!(
         "Fetching transactions from block height: {}",
-        last_block_height
+        from_block_height
     );
-    let transactions =
-        fetch_new_transactions(&config.validator_account_id, last_block_height).await?;
+    // The RPC scan resumes after the last scanned block, which on a quiet pool is far past its
+    // latest transaction. The cursor only moves once the scanned range is stored.
+    let (mut transactions, scanned_height) = match config.txn_source {
+        TxnSource::Nearblocks => (
+            fetch_new_transactions(config, from_block_height).await?,
+            None,
+        ),
+        TxnSource::Rpc => {
+            let scanned_height = match config.fetch_from_block {
+                Some(_) => None,
+                None => {
+                    scan_cursor_repository::get_scanned_height(db, &rpc_scan_cursor(config)).await?
+                }
+            };
+            let from_block_height = scanned_height.map_or(from_block_height, |height| {
+                from_block_height.max(height.saturating_sub(config.refetch_overlap_blocks))
+            });
+            let (transactions, to_height) =
+                fetch_via_rpc(config, primary_client, secondary_client, from_block_height).await?;
+            (transactions, Some(to_height))
+        }
+    };
 
     info!("Fetched {} raw transactions", transactions.len());
 
-    let processed_transactions =
-        process_transactions(transactions, config, primary_client, secondary_client).await?;
+    let fetched = transactions.len();
+    transactions = drop_duplicates(transactions);
+    if transactions.len() < fetched {
+        info!(
+            "Dropped {} duplicate transactions",
+            fetched - transactions.len()
+        );
+    }
+
+    if from_block_height < last_block_height {
+        let stored = transaction_repository::get_transaction_hashes_from_block(
+            db,
+            &config.validator_account_id,
+            from_block_height,
+        )
+        .await?;
+        let fetched = transactions.len();
+        transactions = drop_stored(transactions, &stored);
+        info!(
+            "Dropped {} already stored transactions from the overlap window",
+            fetched - transactions.len()
+        );
+    }
+
+    let stored =
+        store_transactions(config, db, transactions, primary_client, secondary_client).await?;
+    match (scanned_height, stored.first_failed_block) {
+        // Stop the cursor short of a failed transaction so the next run retries it
+        (Some(scanned_height), first_failed_block) if !config.dry_run => {
+            let scanned_height = first_failed_block.map_or(scanned_height, |block| {
+                scanned_height.min(block.saturating_sub(1))
+            });
+            scan_cursor_repository::save_scanned_height(
+                db,
+                &rpc_scan_cursor(config),
+                scanned_height,
+            )
+            .await?;
+        }
+        (None, Some(first_failed_block)) => warn!(
+            "Later runs only refetch REFETCH_OVERLAP_BLOCKS below the latest stored transaction; \
+             set FETCH_FROM_BLOCK={} to retry the failed transactions",
+            first_failed_block.saturating_sub(1)
+        ),
+        _ => {}
+    }
+    Ok(stored)
+}
+
+/// Height the fetch starts at: FETCH_FROM_BLOCK when set, else `overlap` blocks below the
+/// latest stored transaction.
+fn refetch_from(last_block_height: u64, fetch_from_block: Option<u64>, overlap: u64) -> u64 {
+    fetch_from_block.unwrap_or_else(|| last_block_height.saturating_sub(overlap))
+}
+
+/// nearblocks can return the same transaction on two pages, so each hash is kept once
+/// rather than fetching its receipts again.
+fn drop_duplicates(mut transactions: Vec<NearblocksTxn>) -> Vec<NearblocksTxn> {
+    let mut seen = HashSet::new();
+    transactions.retain(|tx| seen.insert(tx.transaction_hash.clone()));
+    transactions
+}
+
+/// Transactions of the overlap window that aren't stored yet.
+fn drop_stored(transactions: Vec<NearblocksTxn>, stored: &HashSet<String>) -> Vec<NearblocksTxn> {
+    transactions
+        .into_iter()
+        .filter(|tx| !stored.contains(&tx.transaction_hash))
+        .collect()
+}
+
+/// Scan cursor of `config.validator_account_id` under TXN_SOURCE=rpc.
+fn rpc_scan_cursor(config: &Config) -> String {
+    format!("rpc:{}", config.validator_account_id)
+}
+
+/// Analyzes raw stake transactions of `config.validator_account_id` and saves the staking ones.
+pub async fn store_transactions(
+    config: &Config,
+    db: &Database,
+    transactions: Vec<NearblocksTxn>,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<StoredTransactions, IndexerError> {
+    let (mut processed_transactions, first_failed_block) =
+        process_transactions(transactions, config, db, primary_client, secondary_client).await;
 
     info!("Processed {} transactions", processed_transactions.len());
 
-    if !processed_transactions.is_empty() {
-        transaction_repository::save_transactions(db, &processed_transactions).await?;
+    if let Some(explorer_base_url) = &config.explorer_base_url {
+        for tx in processed_transactions.iter_mut() {
+            tx.explorer_url = Some(Transaction::explorer_url_for(
+                explorer_base_url,
+                &tx.transaction_hash,
+            ));
+        }
+    }
+
+    let mut saved = 0;
+    if config.dry_run {
         info!(
-            "Saved {} new transactions to the database",
+            "Dry run: not saving {} new transactions",
             processed_transactions.len()
         );
+    } else if !processed_transactions.is_empty() {
+        saved = transaction_repository::save_transactions(db, &processed_transactions).await?;
+        info!("Saved {} new transactions to the database", saved);
     } else {
         info!("No new transactions to save");
     }
 
-    Ok(processed_transactions)
+    Ok(StoredTransactions {
+        transactions: processed_transactions,
+        saved,
+        first_failed_block,
+    })
 }
 
 async fn fetch_new_transactions(
-    validator_account: &str,
+    config: &Config,
     last_block_height: u64,
-) -> Result<Vec<Value>, Box<dyn Error>> {
-    let client = reqwest::Client::new();
+) -> Result<Vec<NearblocksTxn>, IndexerError> {
+    let validator_account = &config.validator_account_id;
+    let client = &config.http_client;
     let mut all_transactions = Vec::new();
     let mut current_page = 1;
-    let per_page = 25;
+    let per_page = config.nearblocks_per_page;
     let max_retries = 5;
+    let total = fetch_transaction_count(config, last_block_height).await;
 
     'outer: loop {
         let url = format!(
-            "https://api.nearblocks.io/v1/account/{}/stake-txns?per_page={}&order=asc&page={}&after_block={}",
-            validator_account, per_page, current_page, last_block_height
+            "{}/v1/account/{}/stake-txns?per_page={}&order={}&page={}&after_block={}",
+            config.nearblocks_base_url.trim_end_matches('/'),
+            validator_account,
+            per_page,
+            config.nearblocks_order.as_str(),
+            current_page,
+            last_block_height
         );
 
         for attempt in 0..max_retries {
@@ -72,11 +236,21 @@ async fn fetch_new_transactions(
                 attempt + 1
             );
 
-            let response = client.get(&url).send().await?;
+            let mut request = client.get(&url);
+            if let Some(api_key) = &config.nearblocks_api_key {
+                request = request.bearer_auth(api_key);
+            }
+            let response = request.send().await?;
 
             if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                warn!("Rate limit reached. Waiting for 60 seconds before retrying...");
-                sleep(Duration::from_secs(60)).await;
+                let delay = retry_after(response.headers())
+                    .unwrap_or_else(|| rate_limit_backoff(config, attempt));
+                retry_budget::reserve(delay, "fetching transactions from nearblocks")?;
+                warn!(
+                    "Rate limit reached. Waiting for {:?} before retrying...",
+                    delay
+                );
+                sleep(delay).await;
                 continue;
             }
 
@@ -88,8 +262,13 @@ async fn fetch_new_transactions(
                     .unwrap_or("")
                     .contains("exceeded your API request limit")
                 {
-                    warn!("Rate limit reached. Waiting for 60 seconds before retrying...");
-                    sleep(Duration::from_secs(60)).await;
+                    let delay = rate_limit_backoff(config, attempt);
+                    retry_budget::reserve(delay, "fetching transactions from nearblocks")?;
+                    warn!(
+                        "Rate limit reached. Waiting for {:?} before retrying...",
+                        delay
+                    );
+                    sleep(delay).await;
                     continue;
                 }
             }
@@ -99,402 +278,952 @@ async fn fetch_new_transactions(
                     info!("No more transactions to fetch");
                     break 'outer;
                 }
-                info!(
-                    "Fetched {} transactions on page {}",
-                    txns.len(),
-                    current_page
-                );
-                all_transactions.extend(txns.clone());
+                all_transactions.extend(txns.iter().filter_map(parse_nearblocks_txn));
+                match total {
+                    Some(total) => info!(
+                        "Fetched {} transactions on page {} ({}/{})",
+                        txns.len(),
+                        current_page,
+                        all_transactions.len(),
+                        total
+                    ),
+                    None => info!(
+                        "Fetched {} transactions on page {}",
+                        txns.len(),
+                        current_page
+                    ),
+                }
                 current_page += 1;
                 break;
             } else {
                 warn!("Unexpected response format from API: {:?}", json);
                 if attempt == max_retries - 1 {
-                    return Err("Max retries reached with unexpected response format".into());
+                    return Err(IndexerError::Rpc(
+                        "Max retries reached with unexpected response format".to_string(),
+                    ));
                 }
+                retry_budget::reserve(
+                    Duration::from_secs(60),
+                    "fetching transactions from nearblocks",
+                )?;
                 sleep(Duration::from_secs(60)).await;
             }
         }
     }
 
     info!("Total transactions fetched: {}", all_transactions.len());
+    if let Some(total) = total {
+        if (all_transactions.len() as u64) < total {
+            warn!(
+                "nearblocks reported {} transactions but only {} were fetched, pagination may have ended early",
+                total,
+                all_transactions.len()
+            );
+        }
+    }
     Ok(all_transactions)
 }
 
-fn safe_parse_amount(amount_str: &str) -> Result<String, Box<dyn Error>> {
-    let cleaned_str = amount_str
-        .trim()
-        .trim_matches('"')
-        .split('.')
-        .next()
-        .unwrap_or("0")
-        .to_string();
-
-    BigInt::from_str(&cleaned_str)
-        .map(|n| n.to_string())
-        .map_err(|e| Box::new(e) as Box<dyn Error>)
+/// Number of stake transactions after `last_block_height` according to nearblocks' count
+/// endpoint, used to report progress. `None` if it can't be read.
+async fn fetch_transaction_count(config: &Config, last_block_height: u64) -> Option<u64> {
+    let url = format!(
+        "{}/v1/account/{}/stake-txns/count?after_block={}",
+        config.nearblocks_base_url.trim_end_matches('/'),
+        config.validator_account_id,
+        last_block_height
+    );
+    let mut request = config.http_client.get(&url);
+    if let Some(api_key) = &config.nearblocks_api_key {
+        request = request.bearer_auth(api_key);
+    }
+
+    let json: Value = match request.send().await {
+        Ok(response) => response.json().await.ok()?,
+        Err(e) => {
+            warn!("Failed to fetch the transaction count: {}", e);
+            return None;
+        }
+    };
+    let count = transaction_count(&json);
+    if count.is_none() {
+        warn!("Unexpected transaction count response: {}", json);
+    }
+    count
 }
 
-async fn process_transactions(
-    transactions: Vec<Value>,
+/// The count is a string in `txns[0].count`, with a top-level `total` as a fallback.
+fn transaction_count(json: &Value) -> Option<u64> {
+    json["txns"][0]["count"]
+        .as_str()
+        .and_then(|count| count.parse().ok())
+        .or_else(|| json["txns"][0]["count"].as_u64())
+        .or_else(|| json["total"].as_u64())
+}
+
+/// Exponential backoff from `NEARBLOCKS_BACKOFF_BASE_SECS`, capped at
+/// `NEARBLOCKS_BACKOFF_MAX_SECS`, with up to half of the delay randomized so concurrent
+/// instances don't retry in lockstep.
+fn rate_limit_backoff(config: &Config, attempt: u32) -> Duration {
+    let exponential = config
+        .nearblocks_backoff_base_secs
+        .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX))
+        .min(config.nearblocks_backoff_max_secs);
+    let delay_millis = exponential * 1000;
+    let jitter = rand::thread_rng().gen_range(0..=delay_millis / 2);
+    Duration::from_millis(delay_millis - jitter)
+}
+
+/// Parses a `Retry-After` header given either as seconds or as an HTTP date.
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let retry_at = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (retry_at.with_timezone(&Utc) - Utc::now())
+        .to_std()
+        .ok()
+        .or(Some(Duration::ZERO))
+}
+
+/// Scans every block after `last_block_height` up to the final head and returns the
+/// transactions sent to the validator, shaped like nearblocks stake-txns entries, together
+/// with the last scanned height.
+async fn fetch_via_rpc(
     config: &Config,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let mut processed_transactions = Vec::new();
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    last_block_height: u64,
+) -> Result<(Vec<NearblocksTxn>, u64), IndexerError> {
+    if last_block_height == 0 && config.fetch_from_block.is_none() {
+        return Err(IndexerError::Config(
+            "TXN_SOURCE=rpc has nothing to resume from: no transactions or scanned blocks are \
+             stored for this pool. Set FETCH_FROM_BLOCK to the height to start scanning from, \
+             e.g. the pool's creation block"
+                .to_string(),
+        ));
+    }
 
-    for tx in transactions {
-        if let Some(result) =
-            analyze_staking_transaction(&tx, config, primary_client, secondary_client).await?
+    let latest_height = near_rpc::get_latest_block_height(primary_client, secondary_client).await?;
+    let transactions = scan_blocks(
+        config,
+        primary_client,
+        secondary_client,
+        last_block_height + 1,
+        latest_height,
+        std::slice::from_ref(&config.validator_account_id),
+    )
+    .await?;
+    Ok((transactions, latest_height))
+}
+
+/// Collects the transactions sent to any of `receivers` in blocks `from_height..=to_height`,
+/// in the nearblocks stake-txns shape. `receiver_account_id` tells the pools apart.
+pub async fn scan_blocks(
+    config: &Config,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    from_height: u64,
+    to_height: u64,
+    receivers: &[String],
+) -> Result<Vec<NearblocksTxn>, IndexerError> {
+    let heights: Vec<u64> = (from_height..=to_height).collect();
+    info!(
+        "Scanning {} blocks ({} to {}) for transactions to {}",
+        heights.len(),
+        from_height,
+        to_height,
+        receivers.join(", ")
+    );
+
+    let mut all_transactions = Vec::new();
+    for window in heights.chunks(config.batch_size.max(1)) {
+        let blocks: Vec<Option<BlockView>> = stream::iter(window.iter().copied())
+            .map(|height| near_rpc::get_block_by_height(primary_client, secondary_client, height))
+            .buffered(config.parallel_limit.max(1))
+            .try_collect()
+            .await?;
+
+        for block in blocks.into_iter().flatten() {
+            for chunk_header in &block.chunks {
+                // Chunks missing from this block are repeated from an earlier one
+                if chunk_header.height_included != block.header.height {
+                    continue;
+                }
+                let chunk =
+                    near_rpc::get_chunk(primary_client, secondary_client, chunk_header.chunk_hash)
+                        .await?;
+                all_transactions.extend(
+                    chunk
+                        .transactions
+                        .iter()
+                        .filter(|tx| receivers.iter().any(|r| r == tx.receiver_id.as_str()))
+                        .map(|tx| {
+                            rpc_transaction_to_txn(
+                                tx,
+                                block.header.height,
+                                block.header.timestamp_nanosec,
+                            )
+                        }),
+                );
+            }
+        }
+    }
+
+    info!(
+        "Total transactions found over RPC: {}",
+        all_transactions.len()
+    );
+    Ok(all_transactions)
+}
+
+/// Converts a chunk transaction to the nearblocks stake-txns shape the analysis expects.
+pub fn rpc_transaction_to_txn(
+    tx: &SignedTransactionView,
+    block_height: u64,
+    block_timestamp_nanos: u64,
+) -> NearblocksTxn {
+    let mut deposit: u128 = 0;
+    let mut actions = Vec::new();
+    for action in &tx.actions {
+        if let ActionView::FunctionCall {
+            method_name,
+            deposit: action_deposit,
+            ..
+        } = action
         {
-            processed_transactions.push(result);
+            deposit += action_deposit;
+            actions.push(Action {
+                action: Some("FUNCTION_CALL".to_string()),
+                method: Some(method_name.clone()),
+            });
+        }
+    }
+
+    NearblocksTxn {
+        transaction_hash: tx.hash.to_string(),
+        block: Some(Block {
+            block_height: Some(block_height),
+        }),
+        block_height: Some(block_height),
+        block_timestamp: Some(block_timestamp_nanos.to_string()),
+        predecessor_account_id: Some(tx.signer_id.to_string()),
+        signer_id: Some(tx.signer_id.to_string()),
+        receiver_account_id: Some(tx.receiver_id.to_string()),
+        actions,
+        actions_agg: Some(ActionsAgg {
+            deposit: Some(deposit.to_string()),
+        }),
+        extra: Default::default(),
+    }
+}
+
+/// Parses one entry of a stake-txns page. Entries that don't match the expected shape are
+/// logged and skipped rather than failing the page.
+fn parse_nearblocks_txn(entry: &Value) -> Option<NearblocksTxn> {
+    match serde_json::from_value(entry.clone()) {
+        Ok(txn) => Some(txn),
+        Err(e) => {
+            warn!("Skipping malformed nearblocks transaction {}: {}", entry, e);
+            None
         }
     }
+}
 
-    Ok(processed_transactions)
+/// Analyzes the transactions `PARALLEL_LIMIT` at a time, paced by the shared RPC throttle. A
+/// transaction that fails is logged and left out rather than failing the batch; the lowest
+/// block height among them is returned so the caller can retry from there.
+async fn process_transactions(
+    transactions: Vec<NearblocksTxn>,
+    config: &Config,
+    db: &Database,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> (Vec<Transaction>, Option<u64>) {
+    let started = Instant::now();
+    let count = transactions.len();
+
+    let results: Vec<_> = stream::iter(transactions)
+        .map(|tx| async move {
+            let result =
+                analyze_staking_transaction(&tx, config, db, primary_client, secondary_client)
+                    .await;
+            (tx, result)
+        })
+        .buffer_unordered(config.parallel_limit.max(1))
+        .collect()
+        .await;
+
+    info!(
+        "Analyzed {} transactions in {:.1}s",
+        count,
+        started.elapsed().as_secs_f64()
+    );
+    collect_analyzed(results)
+}
+
+/// Splits analysis results into the staking transactions to store and the lowest block height
+/// among the failures. A failure without a height can't hold the cursor back, so it is
+/// reported on its own rather than as block 0.
+fn collect_analyzed(
+    results: Vec<(NearblocksTxn, Result<Option<Transaction>, IndexerError>)>,
+) -> (Vec<Transaction>, Option<u64>) {
+    let count = results.len();
+    let mut processed_transactions = Vec::new();
+    let mut failed = 0;
+    let mut first_failed_block: Option<u64> = None;
+    let mut without_height = Vec::new();
+    for (tx, result) in results {
+        match result {
+            Ok(Some(transaction)) => processed_transactions.push(transaction),
+            Ok(None) => {}
+            Err(e) => {
+                error!(
+                    "Failed to analyze transaction {}: {}",
+                    tx.transaction_hash, e
+                );
+                error_summary::record_error(format!("transaction {}", tx.transaction_hash), &e);
+                failed += 1;
+                match tx.block_height() {
+                    Some(block_height) => {
+                        first_failed_block = Some(
+                            first_failed_block
+                                .map_or(block_height, |block| block.min(block_height)),
+                        )
+                    }
+                    None => without_height.push(tx.transaction_hash),
+                }
+            }
+        }
+    }
+
+    if !without_height.is_empty() {
+        warn!(
+            "{} transactions without a block height failed analysis and were not stored: {}",
+            without_height.len(),
+            without_height.join(", ")
+        );
+    }
+    if let Some(first_failed_block) = first_failed_block {
+        warn!(
+            "{} of {} transactions failed analysis and were not stored, the first at block {}",
+            failed, count, first_failed_block
+        );
+    }
+
+    (processed_transactions, first_failed_block)
 }
 
 async fn analyze_staking_transaction(
-    tx: &Value,
+    tx: &NearblocksTxn,
     config: &Config,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<Option<Transaction>, Box<dyn Error>> {
-    let tx_hash = tx["transaction_hash"].as_str().unwrap_or_default();
-    let tx_data = get_transaction_receipts(tx_hash, primary_client, secondary_client).await?;
+    db: &Database,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<Option<Transaction>, IndexerError> {
+    let tx_hash = tx.transaction_hash.as_str();
+    let Some(tx_data) =
+        get_transaction_receipts(tx_hash, config, primary_client, secondary_client).await?
+    else {
+        return Ok(None);
+    };
+
+    if config.save_raw_txns && !config.dry_run {
+        raw_transaction_repository::save_raw_transaction(
+            db,
+            tx_hash,
+            &serde_json::to_value(tx)?,
+            &tx_data,
+        )
+        .await?;
+    }
 
     if let Some(result) =
         analyze_receipts(&tx_data, tx, config, primary_client, secondary_client).await?
     {
         let type_ = determine_type(&result.action, &result.method);
-        let block_height = tx["block"]["block_height"].as_u64().unwrap_or_default();
-        let timestamp = tx["block_timestamp"].as_str().unwrap_or_default();
-        let delegator_address = tx["predecessor_account_id"].as_str().unwrap_or_default();
+        let block_height = tx.block_height().unwrap_or_default();
+        let delegator_address = tx.predecessor_account_id.as_deref().unwrap_or_default();
 
-        let timestamp_nanos = timestamp.parse::<i64>()?;
-        let datetime = DateTime::<Utc>::from_timestamp(timestamp_nanos / 1_000_000_000, 0)
-            .unwrap_or_else(|| Utc::now());
+        let Some(timestamp_nanos) =
+            transaction_timestamp(tx, block_height, primary_client, secondary_client).await
+        else {
+            warn!(
+                "Transaction {} has no usable block timestamp, skipping it",
+                tx_hash
+            );
+            return Ok(None);
+        };
+        let datetime = near_rpc::block_timestamp(timestamp_nanos);
 
         let amount = safe_parse_amount(&result.amount)?;
+        let reward_amount = safe_parse_amount(&result.reward_amount)?;
 
         Ok(Some(Transaction {
             transaction_hash: tx_hash.to_string(),
             amount,
             method: result.method,
             action: result.action,
-            type_: type_,
+            type_,
             block_height,
             timestamp: datetime,
             delegator_address: delegator_address.to_string(),
+            validator_account_id: config.validator_account_id.clone(),
+            reward_amount: (reward_amount != "0").then_some(reward_amount),
+            explorer_url: None,
         }))
     } else {
         Ok(None)
     }
 }
 
+/// Block timestamp of a nearblocks transaction in nanoseconds. When `block_timestamp` is
+/// missing or garbage, the timestamp of the block at `block_height` is fetched instead.
+async fn transaction_timestamp(
+    tx: &NearblocksTxn,
+    block_height: u64,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Option<u64> {
+    if let Some(timestamp_nanos) = tx.timestamp_nanos() {
+        return Some(timestamp_nanos);
+    }
+    if block_height == 0 {
+        return None;
+    }
+
+    warn!(
+        "Invalid block_timestamp {:?} for transaction at block {}, reading it from the block",
+        tx.block_timestamp, block_height
+    );
+    match near_rpc::get_block_info(primary_client, secondary_client, block_height).await {
+        // get_block_info moves on to the next block when one is missing; its time is not ours
+        Ok((height, block)) if height == block_height => Some(block.header.timestamp),
+        Ok((height, _)) => {
+            warn!(
+                "Block {} is unavailable (got block {} instead)",
+                block_height, height
+            );
+            None
+        }
+        Err(e) => {
+            warn!("Failed to fetch block {}: {}", block_height, e);
+            None
+        }
+    }
+}
+
+/// Fetches the transaction's receipts, retrying rate limits and transient RPC errors with
+/// exponential backoff. Returns `None` when the RPC doesn't know the transaction.
 async fn get_transaction_receipts(
     transaction_hash: &str,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<Value, Box<dyn Error>> {
-    let request = methods::EXPERIMENTAL_tx_status::RpcTransactionStatusRequest {
+    config: &Config,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<Option<Value>, IndexerError> {
+    let hash = near_primitives::hash::CryptoHash::from_str(transaction_hash)
+        .map_err(|e| IndexerError::Parse(e.to_string()))?;
+    let sender = near_primitives::types::AccountId::from_str("system")?;
+    let request = || methods::EXPERIMENTAL_tx_status::RpcTransactionStatusRequest {
         transaction_info: methods::EXPERIMENTAL_tx_status::TransactionInfo::TransactionId {
-            hash: near_primitives::hash::CryptoHash::from_str(transaction_hash)
-                .map_err(|e| Box::<dyn Error>::from(e.to_string()))?,
-            account_id: near_primitives::types::AccountId::from_str("system")
-                .map_err(|e| Box::<dyn Error>::from(e.to_string()))?,
+            hash,
+            account_id: sender.clone(),
         },
     };
 
-    let response = match primary_client.call(&request).await {
-        Ok(response) => response,
-        Err(_) => secondary_client.call(&request).await?,
-    };
+    let max_retries = 5;
+    let mut retry_count = 0;
+    let mut backoff_time = 1; // Start with 1 second
+
+    loop {
+        near_rpc::pace_request(config.rpc_inter_request_delay_ms).await;
+        let e =
+            match near_rpc::query_rpc(primary_client, secondary_client, request(), request).await {
+                Ok(response) => {
+                    near_rpc::record_paced_result(false);
+                    return Ok(Some(serde_json::to_value(response)?));
+                }
+                Err(e) => e,
+            };
 
-    Ok(serde_json::to_value(response)?)
+        if let Some(RpcTransactionError::UnknownTransaction { .. }) = e.handler_error() {
+            warn!(
+                "Transaction {} is unknown to the RPC, skipping it",
+                transaction_hash
+            );
+            return Ok(None);
+        }
+
+        let e = IndexerError::from(e);
+        if !e.is_retryable() {
+            return Err(e);
+        }
+        if let IndexerError::RateLimited(_) = e {
+            metrics::record_rate_limited();
+            near_rpc::record_paced_result(true);
+        }
+        retry_count += 1;
+        if retry_count >= max_retries {
+            return Err(e);
+        }
+
+        retry_budget::reserve(
+            Duration::from_secs(backoff_time),
+            &format!("fetching receipts for transaction {}", transaction_hash),
+        )?;
+        info!(
+            "Error fetching receipts for transaction {} ({}/{}). Backing off for {} seconds: {:?}",
+            transaction_hash, retry_count, max_retries, backoff_time, e
+        );
+        sleep(Duration::from_secs(backoff_time)).await;
+        backoff_time *= 2; // Exponential backoff
+    }
 }
 
 async fn analyze_receipts(
     tx_data: &Value,
-    tx: &Value,
+    tx: &NearblocksTxn,
     config: &Config,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<Option<StakingAction>, Box<dyn Error>> {
-    let mut total_stake_amount = BigInt::from(0);
-    let mut total_unstake_amount = BigInt::from(0);
-    let mut action = None;
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<Option<StakingAction>, IndexerError> {
+    if staking_parser::transaction_failed(tx_data) {
+        debug!(
+            "Skipping failed transaction {}: {}",
+            tx.transaction_hash, tx_data["status"]["Failure"]
+        );
+        return Ok(None);
+    }
 
+    let mut actions = Vec::new();
     if let Some(receipts) = tx_data["receipts_outcome"].as_array() {
         for receipt in receipts {
-            if let Some(result) =
-                analyze_receipt(receipt, tx, config, primary_client, secondary_client).await?
-            {
-                match result.action.as_str() {
-                    "stake" => {
-                        let amount = safe_parse_amount(&result.amount)?;
-                        total_stake_amount += BigInt::from_str(&amount)?;
-                        action = Some("stake".to_string());
-                    }
-                    "unstake" => {
-                        let amount = safe_parse_amount(&result.amount)?;
-                        total_unstake_amount += BigInt::from_str(&amount)?;
-                        action = Some("unstake".to_string());
-                    }
-                    _ => {}
-                }
+            if let Some(call) = staking_parser::parse_receipt(receipt, tx)? {
+                actions.push(
+                    resolve_amount(call, tx, config, primary_client, secondary_client).await?,
+                );
             }
         }
     }
 
-    if action.is_none() {
-        let method = tx["actions"][0]["method"].as_str().unwrap_or_default();
-        if method == "deposit_and_stake" {
-            action = Some("stake".to_string());
-            let amount = tx["actions_agg"]["deposit"].as_str().unwrap_or("0");
-            total_stake_amount = BigInt::from_str(&safe_parse_amount(amount)?)?;
+    Ok(Some(staking_parser::combine_actions(&actions, tx)?))
+}
+
+/// Looks up the balances that `unstake_all` and `withdraw_all` act on.
+async fn resolve_amount(
+    call: StakingCall,
+    transaction: &NearblocksTxn,
+    config: &Config,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+) -> Result<StakingAction, IndexerError> {
+    let balance_field = match call.amount {
+        CallAmount::Known(amount) => {
+            return Ok(StakingAction {
+                action: call.action,
+                amount,
+                reward_amount: call.reward_amount,
+                method: call.method,
+            })
         }
+        CallAmount::StakedBalance => "staked_balance",
+        // Resolve "all" to the concrete unstaked balance so the stake math never sees a
+        // non-numeric amount.
+        CallAmount::UnstakedBalance => "unstaked_balance",
+    };
+
+    let account_id = transaction.signer_id.as_deref().unwrap_or_default();
+    let block_height = transaction_block_height(transaction)?;
+    let amount = get_account_balance(
+        config,
+        primary_client,
+        secondary_client,
+        account_id,
+        block_height - 1,
+        balance_field,
+    )
+    .await?;
+
+    Ok(StakingAction {
+        action: call.action,
+        amount,
+        reward_amount: call.reward_amount,
+        method: call.method,
+    })
+}
+
+/// Balances are read at `block_height - 1`, so a missing or zero height is rejected.
+fn transaction_block_height(transaction: &NearblocksTxn) -> Result<u64, IndexerError> {
+    transaction.block_height().ok_or_else(|| {
+        IndexerError::Parse(format!(
+            "Transaction {} has no usable block height",
+            transaction.transaction_hash
+        ))
+    })
+}
+
+/// `balance_field` of the pool's `get_account` view of `account_id`, zero when it is absent.
+async fn get_account_balance(
+    config: &Config,
+    primary_client: &RpcClient,
+    secondary_client: &RpcClient,
+    account_id: &str,
+    block_height: u64,
+    balance_field: &str,
+) -> Result<String, IndexerError> {
+    near_rpc::pace_request(config.rpc_inter_request_delay_ms).await;
+    let account_info = near_rpc::get_pool_account(
+        primary_client,
+        secondary_client,
+        &config.validator_account_id,
+        account_id,
+        block_height,
+    )
+    .await;
+    near_rpc::record_paced_result(matches!(account_info, Err(IndexerError::RateLimited(_))));
+
+    match &account_info?[balance_field] {
+        Value::Null => Ok("0".to_string()),
+        balance => Ok(staking_parser::parse_balance_field(balance)?),
     }
+}
 
-    if let Some(action) = action {
-        Ok(Some(StakingAction {
-            action: action.clone(),
-            amount: if action == "stake" {
-                total_stake_amount.to_string()
-            } else {
-                total_unstake_amount.to_string()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_jsonrpc_client::JsonRpcClient;
+    use serde_json::json;
+    use std::sync::atomic::Ordering;
+
+    const TX_HASH: &str = "HUZyX8rKmzFCrUdKCku86ZRbJyhduNW3tPjW76E8L8Cj";
+
+    fn tx_status() -> Value {
+        let outcome = json!({
+            "logs": [],
+            "receipt_ids": [],
+            "gas_burnt": 0,
+            "tokens_burnt": "0",
+            "executor_id": "alice.near",
+            "status": { "SuccessValue": "" },
+            "metadata": { "version": 1, "gas_profile": null },
+        });
+        json!({
+            "status": { "SuccessValue": "" },
+            "transaction": {
+                "signer_id": "alice.near",
+                "public_key": "ed25519:Gq69vzrMBSooNK53o8TnnCEzFk6CSNRJAfrsxNFgSmST",
+                "nonce": 1,
+                "receiver_id": "pool.poolv1.near",
+                "actions": [],
+                "signature": "ed25519:2AXDGYSE4f2sz7tvMMzyHvUfcoJmxudvdhBcmiUSo6ijskFdN6YH28jTi9qxPiVmshwHGHSDNqRxx2zhC1prj4K",
+                "hash": TX_HASH,
             },
-            method: tx["actions"][0]["method"]
-                .as_str()
-                .unwrap_or("unknown")
-                .to_string(),
-        }))
-    } else {
-        let deposit = tx["actions_agg"]["deposit"].as_str().unwrap_or("0");
-        Ok(Some(StakingAction {
-            action: "stake".to_string(),
-            amount: safe_parse_amount(deposit)?,
-            method: tx["actions"][0]["method"]
-                .as_str()
-                .unwrap_or("unknown")
-                .to_string(),
+            "transaction_outcome": {
+                "proof": [],
+                "block_hash": "FciD4i2WPEYinnKaCzFZAPTUsRxTCpJM6FyQmezmkkoj",
+                "id": TX_HASH,
+                "outcome": outcome,
+            },
+            "receipts_outcome": [],
+            "receipts": [],
+        })
+    }
+
+    fn block(height: u64, timestamp_nanos: u64) -> Value {
+        let hash = "FciD4i2WPEYinnKaCzFZAPTUsRxTCpJM6FyQmezmkkoj";
+        json!({
+            "author": "validator.near",
+            "header": {
+                "height": height,
+                "prev_height": height - 1,
+                "epoch_id": hash,
+                "next_epoch_id": hash,
+                "hash": hash,
+                "prev_hash": hash,
+                "prev_state_root": hash,
+                "block_body_hash": null,
+                "chunk_receipts_root": hash,
+                "chunk_headers_root": hash,
+                "chunk_tx_root": hash,
+                "outcome_root": hash,
+                "chunks_included": 1,
+                "challenges_root": hash,
+                "timestamp": timestamp_nanos,
+                "timestamp_nanosec": timestamp_nanos.to_string(),
+                "random_value": hash,
+                "validator_proposals": [],
+                "chunk_mask": [true],
+                "gas_price": "100000000",
+                "block_ordinal": null,
+                "rent_paid": "0",
+                "validator_reward": "0",
+                "total_supply": "0",
+                "challenges_result": [],
+                "last_final_block": hash,
+                "last_ds_final_block": hash,
+                "next_bp_hash": hash,
+                "block_merkle_root": hash,
+                "epoch_sync_data_hash": null,
+                "approvals": [],
+                "signature": "ed25519:2AXDGYSE4f2sz7tvMMzyHvUfcoJmxudvdhBcmiUSo6ijskFdN6YH28jTi9qxPiVmshwHGHSDNqRxx2zhC1prj4K",
+                "latest_protocol_version": 63,
+            },
+            "chunks": [],
+        })
+    }
+
+    fn nearblocks_txn(hash: &str, block_height: Option<u64>) -> NearblocksTxn {
+        serde_json::from_value(json!({
+            "transaction_hash": hash,
+            "block": { "block_height": block_height },
         }))
+        .unwrap()
     }
-}
 
-async fn analyze_receipt(
-    receipt: &Value,
-    transaction: &Value,
-    config: &Config,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<Option<StakingAction>, Box<dyn Error>> {
-    if let Some(logs) = receipt["outcome"]["logs"].as_array() {
-        for log in logs {
-            if let Some(staking_action) = parse_staking_log(log.as_str().unwrap_or_default()) {
-                return Ok(Some(staking_action));
-            }
+    fn stored(hash: &str, block_height: u64) -> Transaction {
+        Transaction {
+            transaction_hash: hash.to_string(),
+            amount: "1".to_string(),
+            method: "deposit_and_stake".to_string(),
+            action: "stake".to_string(),
+            type_: "stake".to_string(),
+            block_height,
+            timestamp: Utc::now(),
+            delegator_address: "alice.near".to_string(),
+            validator_account_id: "pool.poolv1.near".to_string(),
+            reward_amount: None,
+            explorer_url: None,
         }
     }
 
-    if let Some(actions) = receipt["receipt"]["Action"]["actions"].as_array() {
-        for action in actions {
-            if let Some(function_call) = action.get("FunctionCall") {
-                if let Some(result) = analyze_function_call(
-                    function_call,
-                    transaction,
-                    config,
-                    primary_client,
-                    secondary_client,
-                )
-                .await?
-                {
-                    return Ok(Some(result));
-                }
-            }
-        }
+    fn failure() -> Result<Option<Transaction>, IndexerError> {
+        Err(IndexerError::Rpc("timeout".to_string()))
     }
 
-    Ok(None)
-}
+    #[test]
+    fn failures_hold_back_to_the_lowest_failed_block() {
+        let (transactions, first_failed_block) = collect_analyzed(vec![
+            (nearblocks_txn("a", Some(100)), Ok(Some(stored("a", 100)))),
+            (nearblocks_txn("b", Some(300)), failure()),
+            (nearblocks_txn("c", Some(200)), failure()),
+            (nearblocks_txn("d", Some(150)), Ok(None)),
+        ]);
+        let hashes: Vec<_> = transactions
+            .iter()
+            .map(|transaction| transaction.transaction_hash.as_str())
+            .collect();
+        assert_eq!(hashes, ["a"]);
+        assert_eq!(first_failed_block, Some(200));
+    }
 
-fn parse_staking_log(log: &str) -> Option<StakingAction> {
-    if log.contains(r#""event":"dist.stak""#) {
-        if let Ok(json_log) = serde_json::from_str::<Value>(log) {
-            return Some(StakingAction {
-                action: "stake".to_string(),
-                amount: json_log["amount"].as_str().unwrap_or("0").to_string(),
-                method: "distribute_staking".to_string(),
-            });
-        }
+    #[test]
+    fn failures_without_a_height_do_not_reset_the_cursor() {
+        let (_, first_failed_block) = collect_analyzed(vec![
+            (nearblocks_txn("a", None), failure()),
+            (nearblocks_txn("b", Some(300)), failure()),
+        ]);
+        assert_eq!(first_failed_block, Some(300));
+
+        let (_, first_failed_block) =
+            collect_analyzed(vec![(nearblocks_txn("a", None), failure())]);
+        assert_eq!(first_failed_block, None);
     }
 
-    let staking_keywords = [
-        ("deposited", "stake"),
-        ("staking", "stake"),
-        ("unstaking", "unstake"),
-        ("withdrew", "unstake"),
-    ];
-
-    for (keyword, action) in &staking_keywords {
-        if log.contains(keyword) {
-            if let Some(amount) = log
-                .split_whitespace()
-                .find(|&part| part.parse::<u128>().is_ok())
-            {
-                return Some(StakingAction {
-                    action: action.to_string(),
-                    amount: amount.to_string(),
-                    method: "unknown".to_string(),
-                });
-            }
+    #[test]
+    fn balance_height_is_read_from_the_nested_block() {
+        // Shape of a nearblocks stake-txns entry: the height is nested under `block`
+        let txn: NearblocksTxn = serde_json::from_value(json!({
+            "transaction_hash": "7Tn6XoMmVU1GqgC9PNv1JQzKoSSsTCzeEHxWpJEmbmuB",
+            "included_in_block_hash": "DfBRgPx8DrsFMiaTMEfMkc26F7phjvzvUJ8DuFhm2iR6",
+            "block_timestamp": "1717000000000000000",
+            "block": { "block_height": 120_000_000 },
+            "signer_id": "alice.near",
+            "receiver_account_id": "pool.poolv1.near",
+            "actions": [{ "action": "FUNCTION_CALL", "method": "unstake_all" }],
+            "actions_agg": { "deposit": 0 },
+        }))
+        .unwrap();
+        assert_eq!(transaction_block_height(&txn).unwrap(), 120_000_000);
+    }
+
+    #[test]
+    fn missing_or_zero_height_is_rejected_rather_than_underflowing() {
+        for block in [json!(null), json!({ "block_height": 0 })] {
+            let txn: NearblocksTxn = serde_json::from_value(json!({
+                "transaction_hash": "abc",
+                "block": block,
+            }))
+            .unwrap();
+            assert!(matches!(
+                transaction_block_height(&txn),
+                Err(IndexerError::Parse(_))
+            ));
         }
     }
 
-    None
-}
+    #[test]
+    fn late_indexed_transaction_in_the_overlap_window_is_kept() {
+        // Stored up to block 1000; nearblocks indexed the transaction at 960 after that run
+        let from_block = refetch_from(1_000, None, 100);
+        assert_eq!(from_block, 900);
+        assert_eq!(refetch_from(1_000, Some(500), 100), 500);
+        assert_eq!(refetch_from(50, None, 100), 0);
 
-async fn analyze_function_call(
-    function_call: &Value,
-    transaction: &Value,
-    config: &Config,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<Option<StakingAction>, Box<dyn Error>> {
-    let staking_methods = [
-        ("deposit_and_stake", "stake"),
-        ("stake", "stake"),
-        ("unstake", "unstake"),
-        ("unstake_all", "unstake"),
-        ("withdraw", "unstake"),
-        ("withdraw_all", "unstake"),
-        ("distribute_staking", "stake"),
-    ];
-
-    let method = function_call["method_name"].as_str().unwrap_or_default();
-
-    for &(method_name, action) in &staking_methods {
-        if method == method_name {
-            let amount = if method == "unstake" || method == "unstake_all" {
-                get_unstake_amount(
-                    transaction,
-                    function_call,
-                    config,
-                    primary_client,
-                    secondary_client,
-                )
-                .await?
-            } else if method.contains("all") {
-                "all".to_string()
-            } else {
-                function_call["deposit"]
-                    .as_str()
-                    .or_else(|| transaction["actions_agg"]["deposit"].as_str())
-                    .unwrap_or("0")
-                    .to_string()
-            };
+        let refetched = vec![
+            nearblocks_txn("stored", Some(950)),
+            nearblocks_txn("late", Some(960)),
+            nearblocks_txn("latest", Some(1_000)),
+        ];
+        let stored = HashSet::from(["stored".to_string(), "latest".to_string()]);
+        let new: Vec<_> = drop_stored(refetched, &stored)
+            .into_iter()
+            .map(|tx| tx.transaction_hash)
+            .collect();
+        assert_eq!(new, ["late"]);
+    }
 
-            return Ok(Some(StakingAction {
-                action: action.to_string(),
-                amount,
-                method: method.to_string(),
-            }));
-        }
+    #[tokio::test]
+    async fn rate_limited_receipts_fetch_succeeds_on_retry() {
+        // Both endpoints are rate limited on the first attempt; the retry gets through
+        let (url, requests) = near_rpc::mock::serve(2, tx_status()).await;
+        let primary = RpcClient::new(JsonRpcClient::connect(&url));
+        let secondary = RpcClient::new(JsonRpcClient::connect(&url));
+
+        let receipts = get_transaction_receipts(TX_HASH, &Config::from_env(), &primary, &secondary)
+            .await
+            .unwrap();
+        let receipts = receipts.expect("the transaction is known to the RPC");
+        assert_eq!(receipts["transaction"]["hash"], TX_HASH);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
     }
 
-    Ok(None)
-}
+    #[test]
+    fn paginated_response_reports_its_total_count() {
+        assert_eq!(
+            transaction_count(&json!({ "txns": [{ "count": "1340" }] })),
+            Some(1340)
+        );
+        assert_eq!(
+            transaction_count(&json!({ "txns": [{ "count": 1340 }] })),
+            Some(1340)
+        );
+        assert_eq!(
+            transaction_count(&json!({ "txns": [], "total": 1340 })),
+            Some(1340)
+        );
+        assert_eq!(transaction_count(&json!({ "txns": [] })), None);
 
-async fn get_unstake_amount(
-    transaction: &Value,
-    function_call: &Value,
-    config: &Config,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-) -> Result<String, Box<dyn Error>> {
-    let account_id = transaction["signer_id"].as_str().unwrap_or_default();
-    let block_height = transaction["block_height"].as_u64().unwrap_or_default();
+        let page = json!({
+            "txns": [
+                { "transaction_hash": "a", "block": { "block_height": 100 } },
+                { "transaction_hash": "b", "block": { "block_height": 101 } },
+                { "transaction_hash": 7 },
+            ],
+            "total": 1340,
+        });
+        let txns: Vec<_> = page["txns"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(parse_nearblocks_txn)
+            .collect();
+        assert_eq!(txns.len(), 2);
+        assert_eq!(transaction_count(&page), Some(1340));
+    }
 
-    let prev_block_balance = get_account_stake_balance(
-        config,
-        primary_client,
-        secondary_client,
-        account_id,
-        block_height - 1,
-    )
-    .await?;
+    #[tokio::test]
+    async fn duplicate_hashes_fetch_receipts_once() {
+        let (url, requests) = near_rpc::mock::serve(0, tx_status()).await;
+        let primary = RpcClient::new(JsonRpcClient::connect(&url));
+        let secondary = RpcClient::new(JsonRpcClient::connect(&url));
+        // Never connected to: raw transactions aren't saved by default
+        let db = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9")
+            .await
+            .unwrap()
+            .database("near_indexer_test");
+        let txn = || {
+            serde_json::from_value::<NearblocksTxn>(json!({
+                "transaction_hash": TX_HASH,
+                "block_timestamp": "1717000000000000000",
+                "block": { "block_height": 100 },
+            }))
+            .unwrap()
+        };
 
-    if function_call["method_name"].as_str().unwrap_or_default() == "unstake_all" {
-        Ok(prev_block_balance)
-    } else {
-        let args = function_call["args"].as_str().unwrap_or("{}");
-        let args: Value = serde_json::from_str(args)?;
-        let amount = args["amount"]
-            .as_str()
-            .or_else(|| function_call["deposit"].as_str())
-            .or_else(|| transaction["actions_agg"]["deposit"].as_str())
-            .unwrap_or("0");
-        safe_parse_amount(amount)
+        let transactions = drop_duplicates(vec![txn(), txn()]);
+        assert_eq!(transactions.len(), 1);
+        process_transactions(transactions, &Config::from_env(), &db, &primary, &secondary).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
     }
-}
 
-async fn get_account_stake_balance(
-    config: &Config,
-    primary_client: &JsonRpcClient,
-    secondary_client: &JsonRpcClient,
-    account_id: &str,
-    block_height: u64,
-) -> Result<String, Box<dyn Error>> {
-    let query_request = methods::query::RpcQueryRequest {
-        block_reference: BlockReference::BlockId(near_primitives::types::BlockId::Height(
-            block_height,
-        )),
-        request: near_primitives::views::QueryRequest::CallFunction {
-            account_id: config.validator_account_id.parse()?,
-            method_name: "get_account".to_string(),
-            args: FunctionArgs::from(
-                serde_json::json!({ "account_id": account_id })
-                    .to_string()
-                    .into_bytes(),
-            ),
-        },
-    };
+    #[tokio::test]
+    async fn timestamps_are_read_as_strings_numbers_or_from_the_block() {
+        let (url, requests) = near_rpc::mock::serve(0, block(100, 1_717_000_000_123_456_789)).await;
+        let primary = RpcClient::new(JsonRpcClient::connect(&url));
+        let secondary = RpcClient::new(JsonRpcClient::connect(&url));
+        let txn = |block_timestamp: Value| -> NearblocksTxn {
+            serde_json::from_value(json!({
+                "transaction_hash": TX_HASH,
+                "block_timestamp": block_timestamp,
+            }))
+            .unwrap()
+        };
 
-    let result = match primary_client.call(&query_request).await {
-        Ok(response) => response,
-        Err(_) => secondary_client.call(&query_request).await?,
-    };
+        for block_timestamp in [
+            json!("1717000000000000000"),
+            json!(1_717_000_000_000_000_000u64),
+        ] {
+            let txn = txn(block_timestamp);
+            let timestamp = transaction_timestamp(&txn, 100, &primary, &secondary).await;
+            assert_eq!(timestamp, Some(1_717_000_000_000_000_000));
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
 
-    if let QueryResponseKind::CallResult(call_result) = result.kind {
-        let account_info: Value = serde_json::from_slice(&call_result.result)?;
-        let staked_balance = account_info["staked_balance"].as_str().unwrap_or("0");
-        safe_parse_amount(staked_balance)
-    } else {
-        Ok("0".to_string())
+        // Missing or garbage: read from the block, or skipped without a height to read it at
+        for block_timestamp in [json!(null), json!("garbage")] {
+            let txn = txn(block_timestamp);
+            let timestamp = transaction_timestamp(&txn, 100, &primary, &secondary).await;
+            assert_eq!(timestamp, Some(1_717_000_000_123_456_789));
+            let timestamp = transaction_timestamp(&txn, 0, &primary, &secondary).await;
+            assert_eq!(timestamp, None);
+        }
     }
-}
 
-fn determine_type(action: &str, method: &str) -> String {
-    match action {
-        "unstake" => "unstake".to_string(),
-        "stake" => "stake".to_string(),
-        _ => match method {
-            "deposit_and_stake" | "stake" | "distribute_staking" => "stake".to_string(),
-            "unstake" | "unstake_all" | "withdraw" | "withdraw_all" => "unstake".to_string(),
-            _ => {
-                eprintln!(
-                    "Unexpected action/method combination: {}/{}",
-                    action, method
-                );
-                "stake".to_string()
-            }
-        },
+    #[test]
+    fn rate_limit_backoff_doubles_with_jitter_up_to_the_cap() {
+        let mut config = Config::from_env();
+        config.nearblocks_backoff_base_secs = 2;
+        config.nearblocks_backoff_max_secs = 60;
+        for (attempt, full_secs) in [(0, 2), (1, 4), (2, 8), (4, 32), (5, 60), (63, 60), (64, 60)] {
+            let delay = rate_limit_backoff(&config, attempt);
+            let full = Duration::from_secs(full_secs);
+            assert!(
+                delay <= full && delay >= full / 2,
+                "attempt {}: {:?}",
+                attempt,
+                delay
+            );
+        }
     }
-}
 
-#[derive(Debug)]
-struct StakingAction {
-    action: String,
-    amount: String,
-    method: String,
+    #[test]
+    fn retry_after_is_read_as_seconds_or_an_http_date() {
+        let headers = |value: &str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::RETRY_AFTER, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(retry_after(&headers("30")), Some(Duration::from_secs(30)));
+        // A date in the past means retry now
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::ZERO)
+        );
+        let later = (Utc::now() + chrono::Duration::seconds(120)).to_rfc2822();
+        let delay = retry_after(&headers(&later)).unwrap();
+        assert!(delay > Duration::from_secs(110) && delay <= Duration::from_secs(120));
+        assert_eq!(retry_after(&headers("soon")), None);
+        assert_eq!(retry_after(&reqwest::header::HeaderMap::new()), None);
+    }
 }
@@ -0,0 +1,170 @@
+use crate::models::{DelegatorData, EpochInfo};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Serialize)]
+struct ExportRow<'a> {
+    delegator_id: &'a str,
+    epoch: u64,
+    start_block: u64,
+    rewards: &'a str,
+    apy: Option<f64>,
+    auto_compounded_stake: &'a str,
+}
+
+/// One row per delegator per epoch, ordered by epoch then delegator.
+fn export_rows<'a>(epochs: &[EpochInfo], delegators: &'a [DelegatorData]) -> Vec<ExportRow<'a>> {
+    let mut rows: Vec<ExportRow> = delegators
+        .iter()
+        .filter_map(|delegator| {
+            let epoch = epochs
+                .iter()
+                .find(|epoch| epoch.epoch_id == delegator.epoch_id)?;
+            Some(ExportRow {
+                delegator_id: &delegator.delegator_id,
+                epoch: delegator.epoch,
+                start_block: epoch.start_block,
+                rewards: &delegator.rewards,
+                apy: delegator.apy,
+                auto_compounded_stake: &delegator.auto_compounded_stake,
+            })
+        })
+        .collect();
+    rows.sort_by(|a, b| (a.epoch, a.delegator_id).cmp(&(b.epoch, b.delegator_id)));
+    rows
+}
+
+pub fn export_epoch_csv(
+    epochs: &[EpochInfo],
+    delegators: &[DelegatorData],
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut csv =
+        String::from("delegator_id,epoch,start_block,rewards,apy,auto_compounded_stake\n");
+    for row in export_rows(epochs, delegators) {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            row.delegator_id,
+            row.epoch,
+            row.start_block,
+            row.rewards,
+            row.apy.map(|apy| apy.to_string()).unwrap_or_default(),
+            row.auto_compounded_stake
+        ));
+    }
+    fs::write(path, csv)?;
+    Ok(())
+}
+
+pub fn export_epoch_json(
+    epochs: &[EpochInfo],
+    delegators: &[DelegatorData],
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let json = serde_json::to_string_pretty(&export_rows(epochs, delegators))?;
+    fs::write(path, json)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn epoch(epoch_id: &str, start_block: u64) -> EpochInfo {
+        EpochInfo {
+            start_block,
+            end_block: Some(start_block + 43_199),
+            epoch_id: epoch_id.to_string(),
+            timestamp: Utc::now(),
+            block_hash: None,
+            finalized: true,
+            boundary_verified: true,
+        }
+    }
+
+    fn delegator(
+        delegator_id: &str,
+        epoch: u64,
+        epoch_id: &str,
+        apy: Option<f64>,
+    ) -> DelegatorData {
+        DelegatorData {
+            delegator_id: delegator_id.to_string(),
+            validator_account_id: "pool.poolv1.near".to_string(),
+            epoch,
+            start_block_height: 0,
+            end_block_height: 0,
+            timestamp: 0,
+            initial_stake: "100".to_string(),
+            auto_compounded_stake: "105".to_string(),
+            last_update_block: 0,
+            epoch_id: epoch_id.to_string(),
+            rewards: "5".to_string(),
+            apy,
+            cumulative_rewards: "5".to_string(),
+            stake_at_start: None,
+            stake_at_end: None,
+            negative_rewards: false,
+            beneficiary_id: None,
+            gross_staked: String::new(),
+            gross_unstaked: String::new(),
+        }
+    }
+
+    #[test]
+    fn csv_rows_are_read_back_by_epoch_then_delegator() {
+        let epochs = [epoch("a", 100), epoch("b", 43_300)];
+        let delegators = [
+            delegator("bob.near", 2, "b", None),
+            delegator("carol.near", 1, "a", Some(9.5)),
+            delegator("alice.near", 2, "b", Some(10.25)),
+        ];
+        let path = std::env::temp_dir().join(format!("export-{}.csv", std::process::id()));
+        export_epoch_csv(&epochs, &delegators, &path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(
+            rows,
+            [
+                vec![
+                    "delegator_id",
+                    "epoch",
+                    "start_block",
+                    "rewards",
+                    "apy",
+                    "auto_compounded_stake"
+                ],
+                vec!["carol.near", "1", "100", "5", "9.5", "105"],
+                vec!["alice.near", "2", "43300", "5", "10.25", "105"],
+                vec!["bob.near", "2", "43300", "5", "", "105"],
+            ]
+        );
+    }
+
+    #[test]
+    fn json_export_has_one_object_per_row() {
+        let epochs = [epoch("a", 100)];
+        let delegators = [delegator("alice.near", 1, "a", None)];
+        let path = std::env::temp_dir().join(format!("export-{}.json", std::process::id()));
+        export_epoch_json(&epochs, &delegators, &path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "delegator_id": "alice.near",
+                "epoch": 1,
+                "start_block": 100,
+                "rewards": "5",
+                "apy": null,
+                "auto_compounded_stake": "105",
+            }])
+        );
+    }
+}